@@ -8,7 +8,7 @@ fn main(){
             let mut home_dir = env::home_dir().expect("could not get home directory.");
             home_dir.push(".config");
             home_dir.push("openid-rs");
-            println!("cargo:rustc-env=CONFIG_DIR={}",home_dir.to_string_lossy().to_lowercase());
+            println!("cargo:rustc-env=CONFIG_DIR={}",home_dir.to_string_lossy());
         }
     }
 }
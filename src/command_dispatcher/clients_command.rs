@@ -1,7 +1,14 @@
 use clap;
-use store::{Store, Client};
+use store::{Store, Client, EncryptionSettings, TokenLifetimes};
 use command_dispatcher::error::CliError;
 use uuid;
+use openssl;
+use base64;
+use utils::hash_secret;
+use server::{jwe, subject};
+use reqwest;
+use std::fs;
+use std::io::prelude::*;
 
 pub fn handle_clients_command(
     command: &clap::ArgMatches,
@@ -13,6 +20,14 @@ pub fn handle_clients_command(
         ("delete", Some(args)) => handle_delete_client_command(args, store),
         ("add-redirect-url", Some(args)) => handle_add_redirect_command(args, store),
         ("remove-redirect-url", Some(args)) => handle_remove_redirect_command(args, store),
+        ("set-acr", Some(args)) => handle_set_acr_command(args, store),
+        ("set-first-party", Some(args)) => handle_set_first_party_command(args, store),
+        ("rotate-secret", Some(args)) => handle_rotate_secret_command(args, store),
+        ("set-signing-alg", Some(args)) => handle_set_signing_alg_command(args, store),
+        ("set-encryption", Some(args)) => handle_set_encryption_command(args, store),
+        ("set-subject-type", Some(args)) => handle_set_subject_type_command(args, store),
+        ("set-lifetimes", Some(args)) => handle_set_lifetimes_command(args, store),
+        ("revoke-consents", Some(args)) => handle_revoke_consents_command(args, store),
         ("list", Some(_)) => handle_list_clients_command(store),
         _ => panic!("unknown command"),
     }
@@ -24,6 +39,39 @@ fn handle_list_clients_command(store: Box<Store>) -> Result<(), CliError> {
     for (_, client) in clients {
         println!("{}", client.name);
         println!("{}", client.redirect_urls.join(" "));
+        if let Some(acr) = client.required_acr {
+            println!("Required acr: {}", acr);
+        }
+        if client.first_party {
+            println!("First party, no consent required");
+        }
+        if let Some(alg) = client.id_token_signed_response_alg {
+            println!("Signing algorithm: {}", alg);
+        }
+        if let Some(alg) = client.encryption.id_token_encrypted_response_alg {
+            println!("Id tokens encrypted with: {}", alg);
+        }
+        if let Some(alg) = client.encryption.userinfo_encrypted_response_alg {
+            println!("Userinfo encrypted with: {}", alg);
+        }
+        if client.subject_type != "public" {
+            match client.sector_identifier_uri {
+                Some(uri) => println!("Subject type: {}, sector {}", client.subject_type, uri),
+                None => println!("Subject type: {}", client.subject_type),
+            }
+        }
+        let lifetimes = &client.lifetimes;
+        for &(name, lifetime) in &[
+            ("Id token", lifetimes.id_token),
+            ("Access token", lifetimes.access_token),
+            ("Refresh token", lifetimes.refresh_token),
+            ("Code", lifetimes.code),
+        ]
+        {
+            if let Some(seconds) = lifetime {
+                println!("{} lifetime: {}s", name, seconds);
+            }
+        }
     }
     Ok(())
 }
@@ -35,16 +83,56 @@ fn handle_add_client_command(args: &clap::ArgMatches, store: Box<Store>) -> Resu
         Some(urls) => urls.map(|item| item.to_string()).collect(),
         None => Vec::new(),
     };
+    let secret = if args.is_present("public") {
+        None
+    } else {
+        Some(generate_secret()?)
+    };
     let client = Client {
         id: uuid::Uuid::new_v4().to_string(),
         name: String::from(client_name),
         redirect_urls: urls,
+        required_acr: args.value_of("acr").map(String::from),
+        secret: secret.as_ref().map(|s| hash_secret(s)),
+        first_party: args.is_present("first-party"),
+        id_token_signed_response_alg: args.value_of("signing-alg").map(String::from),
+        encryption: EncryptionSettings::default(),
+        subject_type: String::from("public"),
+        sector_identifier_uri: None,
+        lifetimes: TokenLifetimes::default(),
     };
     store.save_client(&client)?;
+    if let Some(secret) = secret {
+        println!("Client secret: {}", secret);
+        println!("Store it now, it can not be shown again.");
+    }
+    Ok(())
+}
+
+
+fn handle_rotate_secret_command(
+    args: &clap::ArgMatches,
+    store: Box<Store>,
+) -> Result<(), CliError> {
+    let name = args.value_of("REFERENCE").unwrap();
+    if store.get_client(name)?.is_none() {
+        return Err(CliError::OtherError("client not found"));
+    }
+    let secret = generate_secret()?;
+    store.set_client_secret(name, &hash_secret(&secret))?;
+    println!("Client secret: {}", secret);
+    println!("Store it now, it can not be shown again.");
     Ok(())
 }
 
 
+fn generate_secret() -> Result<String, CliError> {
+    let mut raw = [0; 32];
+    openssl::rand::rand_bytes(&mut raw)?;
+    Ok(base64::encode_config(&raw, base64::URL_SAFE_NO_PAD))
+}
+
+
 fn handle_delete_client_command(
     args: &clap::ArgMatches,
     store: Box<Store>,
@@ -57,6 +145,14 @@ fn handle_delete_client_command(
 fn handle_add_redirect_command(args: &clap::ArgMatches, store: Box<Store>) -> Result<(), CliError> {
     let name = args.value_of("REFERENCE").unwrap();
     let url = args.value_of("URL").unwrap();
+    // pairwise subjects must not change with the new url
+    if let Some(client) = store.get_client(name)? {
+        if client.subject_type == "pairwise" {
+            let mut redirect_urls = client.redirect_urls.clone();
+            redirect_urls.push(String::from(url));
+            check_sector(&redirect_urls, client.sector_identifier_uri.as_ref())?;
+        }
+    }
     store.add_redirect_url(name, url)?;
     Ok(())
 }
@@ -70,3 +166,158 @@ fn handle_remove_redirect_command(
     store.remove_redirect_url(name, url)?;
     Ok(())
 }
+
+fn handle_set_acr_command(args: &clap::ArgMatches, store: Box<Store>) -> Result<(), CliError> {
+    let name = args.value_of("REFERENCE").unwrap();
+    store.set_required_acr(name, args.value_of("ACR"))?;
+    Ok(())
+}
+
+fn handle_set_first_party_command(
+    args: &clap::ArgMatches,
+    store: Box<Store>,
+) -> Result<(), CliError> {
+    let name = args.value_of("REFERENCE").unwrap();
+    let first_party = args.value_of("FIRST_PARTY").unwrap() == "true";
+    store.set_first_party(name, first_party)?;
+    Ok(())
+}
+
+fn handle_set_signing_alg_command(
+    args: &clap::ArgMatches,
+    store: Box<Store>,
+) -> Result<(), CliError> {
+    let name = args.value_of("REFERENCE").unwrap();
+    store.set_signing_alg(name, args.value_of("ALG"))?;
+    Ok(())
+}
+
+fn handle_set_encryption_command(
+    args: &clap::ArgMatches,
+    store: Box<Store>,
+) -> Result<(), CliError> {
+    let name = args.value_of("REFERENCE").unwrap();
+    let key = match args.value_of("key") {
+        Some(path) => {
+            let mut pem = String::new();
+            fs::File::open(path)?.read_to_string(&mut pem)?;
+            Some(pem)
+        }
+        None => None,
+    };
+
+    let (id_token_alg, id_token_enc) =
+        encryption_algorithms(args, key.as_ref(), "id-token-alg", "id-token-enc")?;
+    let (userinfo_alg, userinfo_enc) =
+        encryption_algorithms(args, key.as_ref(), "userinfo-alg", "userinfo-enc")?;
+
+    let encryption = EncryptionSettings {
+        key: key,
+        id_token_encrypted_response_alg: id_token_alg,
+        id_token_encrypted_response_enc: id_token_enc,
+        userinfo_encrypted_response_alg: userinfo_alg,
+        userinfo_encrypted_response_enc: userinfo_enc,
+    };
+    store.set_encryption(name, &encryption)?;
+    Ok(())
+}
+
+// alg and enc of one response type, enc defaults to the only supported one.
+fn encryption_algorithms(
+    args: &clap::ArgMatches,
+    key: Option<&String>,
+    alg_arg: &str,
+    enc_arg: &str,
+) -> Result<(Option<String>, Option<String>), CliError> {
+    let alg = match args.value_of(alg_arg) {
+        Some(alg) => alg,
+        None => return Ok((None, None)),
+    };
+    let pem = key.ok_or(CliError::OtherError(
+        "an encryption algorithm requires the key of the client",
+    ))?;
+    if jwe::public_key(pem, alg).is_err() {
+        return Err(CliError::OtherError("the key can not be used with this algorithm"));
+    }
+    let enc = args.value_of(enc_arg).unwrap_or("A256GCM");
+    Ok((Some(String::from(alg)), Some(String::from(enc))))
+}
+
+fn handle_set_subject_type_command(
+    args: &clap::ArgMatches,
+    store: Box<Store>,
+) -> Result<(), CliError> {
+    let name = args.value_of("REFERENCE").unwrap();
+    let subject_type = args.value_of("TYPE").unwrap();
+    let sector_identifier_uri = args.value_of("sector-identifier-uri").map(String::from);
+    let client = store.get_client(name)?.ok_or(
+        CliError::OtherError("client not found"),
+    )?;
+    if subject_type == "pairwise" {
+        check_sector(&client.redirect_urls, sector_identifier_uri.as_ref())?;
+    }
+    store.set_subject_type(
+        name,
+        subject_type,
+        sector_identifier_uri.as_ref().map(|uri| &uri[..]),
+    )?;
+    Ok(())
+}
+
+// whether pairwise subjects are stable for these redirect urls. The sector
+// identifier document is fetched each time, it may have changed.
+fn check_sector(redirect_urls: &[String], sector_identifier_uri: Option<&String>) -> Result<(), CliError> {
+    let uri = match sector_identifier_uri {
+        Some(uri) => uri,
+        None => return subject::validate_redirect_hosts(redirect_urls).map_err(CliError::OtherError),
+    };
+    let fetch_error = CliError::OtherError("could not fetch the sector identifier document");
+    let mut response = match reqwest::get(&uri[..]) {
+        Ok(ref response) if !response.status().is_success() => return Err(fetch_error),
+        Ok(response) => response,
+        Err(_) => return Err(fetch_error),
+    };
+    let mut document = String::new();
+    response.read_to_string(&mut document)?;
+    subject::validate_sector_document(uri, &document, redirect_urls).map_err(CliError::OtherError)
+}
+
+fn handle_set_lifetimes_command(
+    args: &clap::ArgMatches,
+    store: Box<Store>,
+) -> Result<(), CliError> {
+    let name = args.value_of("REFERENCE").unwrap();
+    store.set_lifetimes(name, &token_lifetimes(args)?)?;
+    Ok(())
+}
+
+/// The lifetimes given with the lifetime arguments, None where omitted.
+pub fn token_lifetimes(args: &clap::ArgMatches) -> Result<TokenLifetimes, CliError> {
+    let lifetime = |name: &str| -> Result<Option<i64>, CliError> {
+        match args.value_of(name) {
+            Some(seconds) => {
+                let seconds = seconds.parse::<i64>()?;
+                if seconds <= 0 {
+                    return Err(CliError::OtherError("lifetimes have to be at least one second"));
+                }
+                Ok(Some(seconds))
+            }
+            None => Ok(None),
+        }
+    };
+    Ok(TokenLifetimes {
+        id_token: lifetime("id-token-lifetime")?,
+        access_token: lifetime("access-token-lifetime")?,
+        refresh_token: lifetime("refresh-token-lifetime")?,
+        code: lifetime("code-lifetime")?,
+    })
+}
+
+fn handle_revoke_consents_command(
+    args: &clap::ArgMatches,
+    store: Box<Store>,
+) -> Result<(), CliError> {
+    let name = args.value_of("REFERENCE").unwrap();
+    store.revoke_client_consents(name)?;
+    Ok(())
+}
@@ -1,16 +1,23 @@
 use store;
 use openssl;
+use server::keys::KeyError;
+use server::mail::MailError;
 use std::error::Error;
 use std;
 use std::fmt;
+use std::path::PathBuf;
 
 #[derive(Debug)]
 pub enum CliError {
     StoreError(store::error::StoreError),
     IoError(std::io::Error),
     OpensslError(openssl::error::ErrorStack),
+    KeyError(KeyError),
     ParseIntError(std::num::ParseIntError),
+    MailError(MailError),
     OtherError(&'static str),
+    /// the configured directory and the one key material was found in.
+    ConfigDirMismatch(PathBuf, PathBuf),
 }
 
 impl Error for CliError {
@@ -19,8 +26,11 @@ impl Error for CliError {
             CliError::StoreError(ref err) => err.description(),
             CliError::IoError(ref err) => err.description(),
             CliError::OpensslError(ref err) => err.description(),
+            CliError::KeyError(ref err) => err.description(),
             CliError::ParseIntError(ref err) => err.description(),
+            CliError::MailError(ref err) => err.description(),
             CliError::OtherError(m) => m,
+            CliError::ConfigDirMismatch(_, _) => "key material found in another configuration directory",
         }
     }
 
@@ -29,7 +39,9 @@ impl Error for CliError {
             CliError::StoreError(ref err) => Some(err as &Error),
             CliError::IoError(ref err) => Some(err as &Error),
             CliError::OpensslError(ref err) => Some(err as &Error),
+            CliError::KeyError(ref err) => Some(err as &Error),
             CliError::ParseIntError(ref err) => Some(err as &Error),
+            CliError::MailError(ref err) => Some(err as &Error),
             _ => None,
         }
     }
@@ -47,12 +59,24 @@ impl From<openssl::error::ErrorStack> for CliError {
     }
 }
 
+impl From<KeyError> for CliError {
+    fn from(err: KeyError) -> CliError {
+        CliError::KeyError(err)
+    }
+}
+
 impl From<std::num::ParseIntError> for CliError {
     fn from(err: std::num::ParseIntError) -> CliError {
         CliError::ParseIntError(err)
     }
 }
 
+impl From<MailError> for CliError {
+    fn from(err: MailError) -> CliError {
+        CliError::MailError(err)
+    }
+}
+
 impl From<store::error::StoreError> for CliError {
     fn from(err: store::error::StoreError) -> CliError {
         CliError::StoreError(err)
@@ -64,10 +88,23 @@ impl fmt::Display for CliError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             CliError::OpensslError(ref err) => fmt::Display::fmt(err, f),
+            CliError::KeyError(ref err) => fmt::Display::fmt(err, f),
             CliError::IoError(ref err) => fmt::Display::fmt(err, f),
             CliError::ParseIntError(ref err) => fmt::Display::fmt(err, f),
+            CliError::MailError(ref err) => fmt::Display::fmt(err, f),
             CliError::StoreError(ref err) => fmt::Display::fmt(err, f),
             CliError::OtherError(m) => f.write_str(m),
+            CliError::ConfigDirMismatch(ref configured, ref found) => {
+                write!(
+                    f,
+                    "this build uses the configuration directory {}, but the salt and keys \
+                     of another build were found in {}. Users and tokens created with one do \
+                     not work with the other: move the files or build with CONFIG_DIR={}",
+                    configured.display(),
+                    found.display(),
+                    found.display()
+                )
+            }
         }
     }
 }
@@ -0,0 +1,65 @@
+use clap;
+use time;
+use store::Store;
+use command_dispatcher::error::CliError;
+use server::cookie_secret::CookieSecret;
+use server::keys::{self, KeyType, KEY_TYPES};
+use command_dispatcher::secrets::ConfigDir;
+
+
+pub const DEFAULT_ROTATION_DAYS: i64 = 90;
+
+pub fn handle_keys_command(command: &clap::ArgMatches, store: Box<Store>) -> Result<(), CliError> {
+    match command.subcommand() {
+        ("list", Some(_)) => handle_list_keys_command(store),
+        ("rotate", Some(args)) => handle_rotate_command(args, store),
+        ("rotate-cookie-secret", Some(_)) => handle_rotate_cookie_secret_command(),
+        _ => panic!("unknown command"),
+    }
+}
+
+fn handle_list_keys_command(store: Box<Store>) -> Result<(), CliError> {
+    for key in store.get_signing_keys()? {
+        let since = time::at_utc(time::Timespec::new(key.state_changed, 0));
+        println!(
+            "{} {} {} since {}",
+            key.kid,
+            key.key_type,
+            key.state,
+            since.rfc3339()
+        );
+    }
+    Ok(())
+}
+
+fn handle_rotate_command(args: &clap::ArgMatches, store: Box<Store>) -> Result<(), CliError> {
+    let key_dir = ConfigDir::locate()?.key_directory(&*store)?;
+    let retention_days = args.value_of("retention-days")
+        .map(|item| item.parse::<i64>())
+        .unwrap_or(Ok(DEFAULT_ROTATION_DAYS))?;
+    let key_types: Vec<KeyType> = match args.value_of("key-type") {
+        Some(name) => {
+            let key_type = KeyType::from_name(name).ok_or(
+                CliError::OtherError("unknown key type"),
+            )?;
+            vec![key_type]
+        }
+        None => KEY_TYPES.to_vec(),
+    };
+    let now = time::get_time().sec;
+    for key_type in key_types {
+        keys::rotate(&*store, &key_dir, key_type, retention_days * 24 * 60 * 60, now)?;
+    }
+    println!("Rotated, running servers pick up the new keys within a minute.");
+    Ok(())
+}
+
+fn handle_rotate_cookie_secret_command() -> Result<(), CliError> {
+    let path = ConfigDir::locate()?.cookie_secret_path();
+    CookieSecret::rotate(&path, time::get_time().sec)?;
+    println!(
+        "Rotated, restart the servers to use the new secret. Cookies of the previous \
+         one are accepted for the grace period."
+    );
+    Ok(())
+}
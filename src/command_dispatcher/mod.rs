@@ -1,8 +1,10 @@
 
 mod clients_command;
+mod keys_command;
 mod run_command;
+pub mod secrets;
 mod user_command;
-mod error;
+pub mod error;
 
 use clap;
 use store;
@@ -14,6 +16,7 @@ pub fn dispatch_command(command: clap::ArgMatches, backend_store: Box<store::Sto
         ("clients", Some(command)) => {
             clients_command::handle_clients_command(command, backend_store)
         }
+        ("keys", Some(command)) => keys_command::handle_keys_command(command, backend_store),
         ("run", Some(command)) => run_command::handle_run_command(command, backend_store),
         _ => Err(CliError::OtherError("unknown command")),
     };
@@ -1,29 +1,32 @@
 use clap;
 use std::fs;
-use utils::get_path;
 use store::Store;
 use command_dispatcher::error::CliError;
-use openssl::ec::{EcGroup, NAMED_CURVE, EcKey};
-use openssl::nid::X9_62_PRIME256V1;
-use openssl::pkey::PKey;
-use std::sync::RwLock;
+use server::keys::{self, KeyRing, KeySet, RotationPolicy};
+use command_dispatcher::keys_command::DEFAULT_ROTATION_DAYS;
+use command_dispatcher::secrets::ConfigDir;
+use command_dispatcher::clients_command::token_lifetimes;
+use server::cookie_secret::{CookieSecret, PreviousSecret, DEFAULT_GRACE_PERIOD};
+use server::grants::Lifetimes;
+use server::mail::{Mailer, MaildirMailer, SmtpMailer};
+use server::registration::RegistrationPolicy;
+use server::security::SecurityPolicy;
+use server::throttle::{Throttle, ThrottlePolicy};
+use time;
+use rocket::http::SameSite;
+use std::sync::{Arc, RwLock};
 use std::collections::HashMap;
 use server;
 use std::io::prelude::*;
-use uuid;
 use std::path::PathBuf;
 
 
-
-//set in build script
-static CONFIG_DIR: &str = env!("CONFIG_DIR");
-
 pub fn handle_run_command(
     command: &clap::ArgMatches,
     store: Box<Store + Send + Sync>,
 ) -> Result<(), CliError> {
 
-    let config_dir = PathBuf::from(CONFIG_DIR.to_string());
+    let config_dir = ConfigDir::locate()?;
 
     let listen = command.value_of("address").unwrap_or("0.0.0.0");
 
@@ -34,57 +37,176 @@ pub fn handle_run_command(
         .map(|item| item.parse::<u16>())
         .unwrap_or(Ok(8080))?;
 
+    let session_policy = server::session::SessionPolicy {
+        lifetime: command
+            .value_of("session-lifetime")
+            .map(|item| item.parse::<i64>())
+            .unwrap_or(Ok(10 * 60 * 60))?,
+        idle_timeout: command
+            .value_of("session-idle-timeout")
+            .map(|item| item.parse::<i64>())
+            .unwrap_or(Ok(60 * 60))?,
+    };
+
+    let lifetimes = Lifetimes::default().overridden_by(&token_lifetimes(command)?);
+
+    let defaults = ThrottlePolicy::default();
+    let throttle_policy = ThrottlePolicy {
+        account_threshold: command
+            .value_of("lockout-threshold")
+            .map(|item| item.parse::<i64>())
+            .unwrap_or(Ok(defaults.account_threshold))?,
+        address_threshold: command
+            .value_of("address-lockout-threshold")
+            .map(|item| item.parse::<i64>())
+            .unwrap_or(Ok(defaults.address_threshold))?,
+        lockout: command
+            .value_of("lockout-duration")
+            .map(|item| item.parse::<i64>())
+            .unwrap_or(Ok(defaults.lockout))?,
+    };
 
-    let private_dir = get_path(&config_dir, &["private"]);
-    let sign_key = get_path(&private_dir, &["sign-key.pem"]);
-    let verification_key_path = get_path(&config_dir, &["verification-key.pem"]);
-    let salt_file_path = get_path(&private_dir, &["salt.txt"]);
-    fs::create_dir_all(sign_key.parent().unwrap())?;
-
-    let key_pair = if !sign_key.exists() {
-        let mut group = EcGroup::from_curve_name(X9_62_PRIME256V1)?;
-        group.set_asn1_flag(NAMED_CURVE);
-        let key = EcKey::generate(&group)?;
-        let key = PKey::from_ec_key(key)?;
-
-        let pem = key.private_key_to_pem()?;
-        let public_pem = key.public_key_to_pem()?;
-        let _ = fs::remove_file(verification_key_path.clone());
-        let mut sign_key_file = fs::File::create(sign_key)?;
-        let mut verification_key_file = fs::File::create(verification_key_path)?;
-        sign_key_file.write_all(&pem)?;
-        verification_key_file.write_all(&public_pem)?;
-        key
+    let mailer = mailer(command);
+    let registration = if command.is_present("allow-registration") {
+        if mailer.is_none() {
+            return Err(CliError::OtherError(
+                "registration needs --smtp-server or --maildir to send verification links",
+            ));
+        }
+        Some(RegistrationPolicy {
+            allowed_domains: command
+                .values_of("registration-domain")
+                .map(|domains| domains.map(String::from).collect())
+                .unwrap_or_default(),
+            approval_required: command.is_present("registration-approval"),
+        })
     } else {
-        let mut private_key_file = fs::File::open(sign_key)?;
-        let mut content = Vec::new();
-        private_key_file.read_to_end(&mut content)?;
-        PKey::private_key_from_pem(&content)?
+        None
     };
 
-    let mut salt = String::new();
+    let security_defaults = SecurityPolicy::default();
+    let security = SecurityPolicy {
+        https: !command.is_present("plain-http"),
+        hsts_max_age: command
+            .value_of("hsts-max-age")
+            .map(|item| item.parse::<i64>())
+            .unwrap_or(Ok(security_defaults.hsts_max_age))?,
+        same_site: match command.value_of("cookie-same-site") {
+            Some("strict") => SameSite::Strict,
+            _ => security_defaults.same_site,
+        },
+    };
+
+    let salt = config_dir.salt()?;
+    let pairwise_secret = config_dir.pairwise_secret()?;
+
+    let cookie_secret = CookieSecret::load_or_create(&config_dir.cookie_secret_path())?;
+    let grace_period = command
+        .value_of("cookie-secret-grace-period")
+        .map(|item| item.parse::<i64>())
+        .unwrap_or(Ok(DEFAULT_GRACE_PERIOD))?;
+    let previous_secret = PreviousSecret::new(&cookie_secret, grace_period, &security)?;
 
-    if salt_file_path.exists() {
-        let mut salt_file = fs::File::open(salt_file_path)?;
-        salt_file.read_to_string(&mut salt)?;
+    // shared with the thread that keeps the signing keys rotated
+    let store: Arc<Store + Send + Sync> = Arc::from(store);
+    let signing_alg = command.value_of("signing-alg").unwrap_or("ES256");
+    let keys = if command.is_present("pkcs11-module") {
+        Arc::new(KeyRing::fixed(pkcs11_keys(command, signing_alg)?))
     } else {
-        salt = uuid::Uuid::new_v4().simple().to_string();
-        let mut salt_file = fs::File::create(salt_file_path)?;
-        write!(salt_file, "{}", salt)?;
+        let rotation_days = command
+            .value_of("key-rotation-days")
+            .map(|item| item.parse::<i64>())
+            .unwrap_or(Ok(DEFAULT_ROTATION_DAYS))?;
+        let rotation_policy = RotationPolicy {
+            directory: config_dir.key_directory(&*store)?,
+            period: rotation_days * 24 * 60 * 60,
+        };
+        let keys = Arc::new(KeyRing::managed(
+            &*store,
+            rotation_policy,
+            signing_alg,
+            time::get_time().sec,
+        )?);
+        keys::refresh_in_background(keys.clone(), store.clone());
+        keys
+    };
+
+    // the public key of the default algorithm at startup, for relying
+    // parties that do not fetch the jwks.
+    {
+        let current = keys.current();
+        let default_key = current.signing_key(signing_alg).unwrap(); // safe unwrap
+        let mut verification_key_file = fs::File::create(config_dir.verification_key_path())?;
+        verification_key_file.write_all(&default_key.signer.public_key().public_key_to_pem()?)?;
     }
 
     let app_config = server::Config {
         issuer: issuer,
-        config_dir_path: String::from(config_dir.to_str().ok_or(CliError::OtherError(
+        config_dir_path: String::from(config_dir.path().to_str().ok_or(CliError::OtherError(
             "could not convert path to string",
         ))?),
         store: store,
         sessions: RwLock::new(HashMap::new()),
-        token_duration: 7 * 24 * 60 * 60,
+        session_policy: session_policy,
+        throttle: Throttle::new(throttle_policy),
+        lifetimes: lifetimes,
         codes: RwLock::new(HashMap::new()),
+        access_tokens: RwLock::new(HashMap::new()),
+        refresh_tokens: RwLock::new(HashMap::new()),
         salt: salt,
-        key_pair: key_pair,
+        pairwise_secret: pairwise_secret,
+        keys: keys,
+        mailer: mailer.map(Arc::from),
+        registration: registration,
+        security: security,
     };
-    server::run(app_config, listen, port);
+    server::run(app_config, listen, port, &cookie_secret, previous_secret);
     Ok(())
 }
+
+
+/// The mailer set up with --smtp-server or --maildir, if any.
+pub fn mailer(command: &clap::ArgMatches) -> Option<Box<Mailer>> {
+    let mail_from = command.value_of("mail-from").map(String::from).unwrap_or_default();
+    if let Some(server) = command.value_of("smtp-server") {
+        Some(Box::new(SmtpMailer {
+            server: String::from(server),
+            from: mail_from,
+        }))
+    } else if let Some(directory) = command.value_of("maildir") {
+        Some(Box::new(MaildirMailer {
+            directory: PathBuf::from(directory),
+            from: mail_from,
+        }))
+    } else {
+        None
+    }
+}
+
+
+// the signing keys on the PKCS#11 token given on the command line.
+#[cfg(feature = "pkcs11")]
+fn pkcs11_keys(command: &clap::ArgMatches, signing_alg: &str) -> Result<KeySet, CliError> {
+    use server::keys::SigningKey;
+    use server::signer::pkcs11::Pkcs11Token;
+
+    let mut pin = String::new();
+    fs::File::open(command.value_of("pkcs11-pin-file").unwrap())?
+        .read_to_string(&mut pin)?;
+    let token = Pkcs11Token::open(
+        command.value_of("pkcs11-module").unwrap(),
+        command.value_of("pkcs11-token").unwrap(),
+        pin.trim(),
+    )?;
+    let mut keys = Vec::new();
+    for label in command.values_of("pkcs11-key").unwrap() {
+        let signer = Pkcs11Token::signer(&token, label)?;
+        keys.push(SigningKey::new(Box::new(signer))?);
+    }
+    Ok(KeySet::new(keys, signing_alg)?)
+}
+
+#[cfg(not(feature = "pkcs11"))]
+fn pkcs11_keys(_command: &clap::ArgMatches, _signing_alg: &str) -> Result<KeySet, CliError> {
+    Err(CliError::OtherError("built without the pkcs11 feature"))
+}
@@ -0,0 +1,151 @@
+use base64;
+use openssl;
+use std::env;
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use command_dispatcher::error::CliError;
+use server::keys;
+use store::Store;
+use time;
+use utils::get_path;
+use uuid;
+
+
+//set in build script
+static CONFIG_DIR: &str = env!("CONFIG_DIR");
+
+// the signing keys of former versions, kept outside of the key directory.
+static LEGACY_KEY_FILES: &[&str] = &[
+    "sign-key.pem",
+    "sign-key-p384.pem",
+    "sign-key-rsa.pem",
+    "sign-key-ed25519.pem",
+];
+
+/// The directory holding the salt, the signing keys, the cookie secret, the
+/// pairwise subject secret and the published verification key. The CLI and the server locate all of
+/// them here.
+pub struct ConfigDir {
+    path: PathBuf,
+}
+
+impl ConfigDir {
+    /// The directory chosen at build time. Fails if it holds no salt yet
+    /// but a directory other builds used does, as logins and tokens would
+    /// silently stop working with a new salt and new keys.
+    pub fn locate() -> Result<ConfigDir, CliError> {
+        ConfigDir::locate_among(PathBuf::from(CONFIG_DIR), &ConfigDir::candidates())
+    }
+
+    /// `configured` unless it holds no salt but one of the `candidates`
+    /// does, see `locate`.
+    pub fn locate_among(configured: PathBuf, candidates: &[PathBuf]) -> Result<ConfigDir, CliError> {
+        let config_dir = ConfigDir { path: configured };
+        if config_dir.salt_path().exists() {
+            return Ok(config_dir);
+        }
+        for path in candidates {
+            let candidate = ConfigDir { path: path.clone() };
+            if candidate.path != config_dir.path && candidate.salt_path().exists() {
+                return Err(CliError::ConfigDirMismatch(config_dir.path, candidate.path));
+            }
+        }
+        Ok(config_dir)
+    }
+
+    // where the configuration was kept by default, before and after build
+    // scripts stopped lowercasing the path.
+    fn candidates() -> Vec<PathBuf> {
+        let mut candidates = Vec::new();
+        if let Some(home_dir) = env::home_dir() {
+            let default = get_path(&home_dir, &[".config", "openid-rs"]);
+            let lowercased = PathBuf::from(default.to_string_lossy().to_lowercase());
+            candidates.push(default);
+            candidates.push(lowercased);
+        }
+        candidates
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn private_dir(&self) -> PathBuf {
+        get_path(&self.path, &["private"])
+    }
+
+    /// The public key of the default algorithm, for relying parties that do
+    /// not fetch the jwks.
+    pub fn verification_key_path(&self) -> PathBuf {
+        get_path(&self.path, &["verification-key.pem"])
+    }
+
+    /// The secret the private cookies are encrypted with.
+    pub fn cookie_secret_path(&self) -> PathBuf {
+        get_path(&self.private_dir(), &["cookie-secret.txt"])
+    }
+
+    fn pairwise_secret_path(&self) -> PathBuf {
+        get_path(&self.private_dir(), &["pairwise-secret.txt"])
+    }
+
+    fn salt_path(&self) -> PathBuf {
+        get_path(&self.private_dir(), &["salt.txt"])
+    }
+
+    fn read_salt(&self) -> Result<Option<String>, CliError> {
+        let salt_path = self.salt_path();
+        if !salt_path.exists() {
+            return Ok(None);
+        }
+        let mut salt = String::new();
+        fs::File::open(salt_path)?.read_to_string(&mut salt)?;
+        Ok(Some(salt))
+    }
+
+    /// The salt of this provider, created on first use.
+    pub fn salt(&self) -> Result<String, CliError> {
+        if let Some(salt) = self.read_salt()? {
+            return Ok(salt);
+        }
+        fs::create_dir_all(self.private_dir())?;
+        let salt = uuid::Uuid::new_v4().simple().to_string();
+        let mut salt_file = fs::File::create(self.salt_path())?;
+        write!(salt_file, "{}", salt)?;
+        Ok(salt)
+    }
+
+    /// The secret pairwise subjects are derived with, created on first use.
+    /// Replacing it changes the subjects all pairwise clients know.
+    pub fn pairwise_secret(&self) -> Result<String, CliError> {
+        let path = self.pairwise_secret_path();
+        if path.exists() {
+            let mut secret = String::new();
+            fs::File::open(path)?.read_to_string(&mut secret)?;
+            return Ok(String::from(secret.trim()));
+        }
+        fs::create_dir_all(self.private_dir())?;
+        let mut bytes = [0; 32];
+        openssl::rand::rand_bytes(&mut bytes)?;
+        let secret = base64::encode(&bytes);
+        let mut secret_file = fs::File::create(path)?;
+        write!(secret_file, "{}", secret)?;
+        Ok(secret)
+    }
+
+    /// The directory the private signing keys are kept in. Keys of former
+    /// versions are moved there on first use.
+    pub fn key_directory(&self, store: &Store) -> Result<PathBuf, CliError> {
+        let private_dir = self.private_dir();
+        let key_dir = get_path(&private_dir, &["keys"]);
+        let now = time::get_time().sec;
+        for file_name in LEGACY_KEY_FILES {
+            let legacy_path = get_path(&private_dir, &[*file_name]);
+            if legacy_path.exists() {
+                keys::import(store, &key_dir, &legacy_path, now)?;
+            }
+        }
+        Ok(key_dir)
+    }
+}
@@ -1,20 +1,46 @@
 use clap;
 use store::{self, Store};
 use command_dispatcher::error::CliError;
+use command_dispatcher::run_command;
 use std::{self, fs};
 use std::io::prelude::*;
 use uuid;
-use openssl;
-use base64;
+use time;
+use utils::{password, recovery_codes};
+use server::{invitation, session, throttle};
 
 pub fn handle_users_command(command: &clap::ArgMatches, store: Box<Store>) -> Result<(), CliError> {
     match command.subcommand() {
         ("add", Some(sub_command)) => handle_add_user_command(sub_command, store),
+        ("invite", Some(sub_command)) => handle_invite_command(sub_command, store),
+        ("invitations", Some(_)) => handle_invitations_command(store),
+        ("revoke-invitation", Some(sub_command)) => {
+            handle_revoke_invitation_command(sub_command, store)
+        }
         ("list", Some(_)) => handle_list_command(store),
         ("delete", Some(sub_command)) => handle_delete_user_command(sub_command, store),
         ("change-email", Some(sub_command)) => handle_change_email_command(sub_command, store),
+        ("update-profile", Some(sub_command)) => handle_update_profile_command(sub_command, store),
+        ("consents", Some(sub_command)) => handle_consents_command(sub_command, store),
+        ("revoke-consent", Some(sub_command)) => handle_revoke_consent_command(sub_command, store),
         ("join-group", Some(sub_command)) => handle_join_group_command(sub_command, store),
         ("leave-group", Some(sub_command)) => handle_leave_group_command(sub_command, store),
+        ("reset-totp", Some(sub_command)) => handle_reset_totp_command(sub_command, store),
+        ("regenerate-recovery-codes", Some(sub_command)) => {
+            handle_regenerate_recovery_codes_command(sub_command, store)
+        }
+        ("security-keys", Some(sub_command)) => handle_security_keys_command(sub_command, store),
+        ("remove-security-key", Some(sub_command)) => {
+            handle_remove_security_key_command(sub_command, store)
+        }
+        ("require-mfa", Some(sub_command)) => handle_require_mfa_command(sub_command, store),
+        ("require-group-mfa", Some(sub_command)) => {
+            handle_require_group_mfa_command(sub_command, store)
+        }
+        ("pending", Some(_)) => handle_pending_command(store),
+        ("approve", Some(sub_command)) => handle_approve_command(sub_command, store),
+        ("unlock", Some(sub_command)) => handle_unlock_command(sub_command, store),
+        ("lockouts", Some(_)) => handle_lockouts_command(store),
         _ => {
             eprintln!("require at least one subcommand!");
             std::process::exit(1);
@@ -22,6 +48,127 @@ pub fn handle_users_command(command: &clap::ArgMatches, store: Box<Store>) -> Re
     }
 }
 
+fn handle_reset_totp_command(args: &clap::ArgMatches, store: Box<Store>) -> Result<(), CliError> {
+    let reference = args.value_of("REFERENCE").unwrap();
+    store.set_totp(reference, None)?;
+    allow_mfa_enrollment(reference, &*store)
+}
+
+// lets the user enroll a new second factor on the next login.
+fn allow_mfa_enrollment(reference: &str, store: &Store) -> Result<(), CliError> {
+    let until = time::get_time().sec + session::MFA_ENROLLMENT_PERIOD;
+    store.set_mfa_enrollment(reference, Some(until))?;
+    Ok(())
+}
+
+fn handle_regenerate_recovery_codes_command(
+    args: &clap::ArgMatches,
+    store: Box<Store>,
+) -> Result<(), CliError> {
+    let reference = args.value_of("REFERENCE").unwrap();
+    let user = store.find_user(reference)?.ok_or(
+        CliError::OtherError("user not found"),
+    )?;
+    let codes = recovery_codes::generate()?;
+    let hashes: Vec<String> = codes
+        .iter()
+        .map(|code| recovery_codes::hash(&user.id, code))
+        .collect();
+    store.set_recovery_codes(&user.id, &hashes)?;
+    for code in codes {
+        println!("{}", code);
+    }
+    Ok(())
+}
+
+fn handle_security_keys_command(args: &clap::ArgMatches, store: Box<Store>) -> Result<(), CliError> {
+    let reference = args.value_of("REFERENCE").unwrap();
+    for credential in store.get_webauthn_credentials(reference)? {
+        let created = time::at_utc(time::Timespec::new(credential.created, 0));
+        println!("{} {} {}", credential.id, created.rfc3339(), credential.name);
+    }
+    Ok(())
+}
+
+fn handle_remove_security_key_command(
+    args: &clap::ArgMatches,
+    store: Box<Store>,
+) -> Result<(), CliError> {
+    let reference = args.value_of("REFERENCE").unwrap();
+    let id = args.value_of("ID").unwrap();
+    store.delete_webauthn_credential(reference, id)?;
+    Ok(())
+}
+
+fn handle_require_mfa_command(args: &clap::ArgMatches, store: Box<Store>) -> Result<(), CliError> {
+    let reference = args.value_of("REFERENCE").unwrap();
+    let required = args.value_of("REQUIRED").unwrap() == "true";
+    store.set_user_mfa_required(reference, required)?;
+    if required {
+        allow_mfa_enrollment(reference, &*store)?;
+    }
+    Ok(())
+}
+
+fn handle_require_group_mfa_command(
+    args: &clap::ArgMatches,
+    store: Box<Store>,
+) -> Result<(), CliError> {
+    let group = args.value_of("GROUP").unwrap();
+    let required = args.value_of("REQUIRED").unwrap() == "true";
+    store.set_group_mfa_required(group, required)?;
+    Ok(())
+}
+
+fn handle_pending_command(store: Box<Store>) -> Result<(), CliError> {
+    for (email, requested) in store.get_pending_approvals()? {
+        let requested = time::at_utc(time::Timespec::new(requested, 0));
+        println!("{} {}", requested.rfc3339(), email);
+    }
+    Ok(())
+}
+
+fn handle_approve_command(args: &clap::ArgMatches, store: Box<Store>) -> Result<(), CliError> {
+    let reference = args.value_of("REFERENCE").unwrap();
+    store.find_user(reference)?.ok_or(
+        CliError::OtherError("user not found"),
+    )?;
+    store.approve_user(reference)?;
+    Ok(())
+}
+
+fn handle_unlock_command(args: &clap::ArgMatches, store: Box<Store>) -> Result<(), CliError> {
+    let reference = args.value_of("REFERENCE").unwrap();
+    let user = store.find_user(reference)?.ok_or(
+        CliError::OtherError("user not found"),
+    )?;
+    store.clear_failed_attempts(&user.id)?;
+    store.record_lockout_event(&store::LockoutEvent {
+        subject: throttle::user_key(&user.id),
+        event: String::from("unlocked"),
+        remote_addr: None,
+        at: time::get_time().sec,
+    })?;
+    Ok(())
+}
+
+fn handle_lockouts_command(store: Box<Store>) -> Result<(), CliError> {
+    for event in store.get_lockout_events()? {
+        let at = time::at_utc(time::Timespec::new(event.at, 0));
+        println!(
+            "{} {} {}{}",
+            at.rfc3339(),
+            event.subject,
+            event.event,
+            event
+                .remote_addr
+                .map(|address| format!(" from {}", address))
+                .unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
 fn handle_leave_group_command(args: &clap::ArgMatches, store: Box<Store>) -> Result<(), CliError> {
     let email = args.value_of("REFERENCE").unwrap();
     let group = args.value_of("GROUP").unwrap();
@@ -41,6 +188,54 @@ fn handle_change_email_command(_: &clap::ArgMatches, _: Box<Store>) -> Result<()
     Err(CliError::OtherError("not implemented yet"))
 }
 
+fn handle_update_profile_command(
+    args: &clap::ArgMatches,
+    store: Box<Store>,
+) -> Result<(), CliError> {
+    let reference = args.value_of("REFERENCE").unwrap();
+    store.update_profile(reference, &profile_from_args(args))?;
+    Ok(())
+}
+
+fn profile_from_args(args: &clap::ArgMatches) -> store::Profile {
+    let value = |name: &str| args.value_of(name).map(String::from);
+    store::Profile {
+        name: value("name"),
+        given_name: value("given-name"),
+        family_name: value("family-name"),
+        picture: value("picture"),
+        phone_number: value("phone"),
+        locale: value("locale"),
+        address: value("address"),
+    }
+}
+
+fn handle_consents_command(args: &clap::ArgMatches, store: Box<Store>) -> Result<(), CliError> {
+    let reference = args.value_of("REFERENCE").unwrap();
+    for (client, consent) in store.get_consents(reference)? {
+        if consent.claims.is_empty() {
+            println!("{}: {}", client, consent.scopes.join(" "));
+        } else {
+            println!(
+                "{}: {} (claims {})",
+                client,
+                consent.scopes.join(" "),
+                consent.claims.join(" ")
+            );
+        }
+    }
+    Ok(())
+}
+
+fn handle_revoke_consent_command(
+    args: &clap::ArgMatches,
+    store: Box<Store>,
+) -> Result<(), CliError> {
+    let reference = args.value_of("REFERENCE").unwrap();
+    store.revoke_consent(reference, args.value_of("client"))?;
+    Ok(())
+}
+
 fn handle_list_command(store: Box<Store>) -> Result<(), CliError> {
     let users = store.get_users()?;
     for (_, user) in users {
@@ -67,7 +262,7 @@ fn handle_add_user_command(args: &clap::ArgMatches, store: Box<Store>) -> Result
         None => Vec::new(),
     };
 
-    let mut pwd = match args.value_of("password") {
+    let pwd = match args.value_of("password") {
         Some(pwd) => pwd.to_string(),
         None => {
             if let Some(pwd_file) = args.value_of("password-file") {
@@ -78,45 +273,104 @@ fn handle_add_user_command(args: &clap::ArgMatches, store: Box<Store>) -> Result
         }
     };
 
-
-    let mut salt_file_path = std::env::home_dir().ok_or(CliError::OtherError(
-        "could not determine home directory",
-    ))?;
-
-    salt_file_path.push(".config");
-    salt_file_path.push("openid-rs");
-    salt_file_path.push("private");
-    salt_file_path.push("salt.txt");
-    std::fs::create_dir_all(salt_file_path.parent().unwrap())?;
-
-
-    let mut salt = String::new();
-
-    if salt_file_path.exists() {
-        let mut salt_file = fs::File::open(salt_file_path)?;
-        salt_file.read_to_string(&mut salt)?;
-    } else {
-        salt = uuid::Uuid::new_v4().simple().to_string();
-        let mut salt_file = fs::File::create(salt_file_path)?;
-        write!(salt_file, "{}", salt)?;
-    }
-
-    pwd.push_str(&salt[..]);
-
-    let hashed_pwd_bytes = openssl::sha::sha256(pwd.as_bytes());
-
-    let hashed_pwd = base64::encode(&hashed_pwd_bytes);
+    let hashed_pwd = password::hash_password(&pwd)?;
 
     let user = store::User {
         id: uuid::Uuid::new_v4().to_string(),
         email: String::from(email),
         password: Some(hashed_pwd),
         groups: groups,
+        profile: profile_from_args(args),
+        email_verified: true,
     };
     store.save_user(&user)?;
     Ok(())
 }
 
+fn handle_invite_command(args: &clap::ArgMatches, store: Box<Store>) -> Result<(), CliError> {
+    let email = args.value_of("EMAIL").unwrap();
+    let days = args.value_of("expires-days")
+        .map(|item| item.parse::<i64>())
+        .unwrap_or(Ok(invitation::DEFAULT_LIFETIME_DAYS))?;
+    if days <= 0 {
+        return Err(CliError::OtherError("invitations must be valid for at least a day"));
+    }
+    let mfa_required = args.is_present("require-mfa");
+
+    // users who did not accept their invitation yet are invited again
+    let user_id = match store.find_user(email)? {
+        Some(store::User { password: Some(_), .. }) => {
+            return Err(CliError::OtherError("the user exists and has a password"))
+        }
+        Some(user) => user.id,
+        None => {
+            let user = store::User {
+                id: uuid::Uuid::new_v4().to_string(),
+                email: String::from(email),
+                password: None,
+                groups: args.values_of("group")
+                    .map(|groups| groups.map(String::from).collect())
+                    .unwrap_or_default(),
+                profile: profile_from_args(args),
+                email_verified: false,
+            };
+            store.save_user(&user)?;
+            user.id
+        }
+    };
+    if mfa_required {
+        store.set_user_mfa_required(&user_id, true)?;
+    }
+
+    let token = invitation::new_token()?;
+    let now = time::get_time().sec;
+    store.save_invitation(&store::Invitation {
+        user_id: user_id,
+        email: String::from(email),
+        token_hash: invitation::hash(&token),
+        created: now,
+        expires: now + days * 24 * 60 * 60,
+    })?;
+    let link = invitation::link(args.value_of("url").unwrap(), &token);
+    match run_command::mailer(args) {
+        Some(mailer) => mailer.send(&invitation::mail(email, &link, days, mfa_required))?,
+        None => println!("{}", link),
+    }
+    Ok(())
+}
+
+fn handle_invitations_command(store: Box<Store>) -> Result<(), CliError> {
+    let now = time::get_time().sec;
+    for invitation in store.get_invitations()? {
+        let created = time::at_utc(time::Timespec::new(invitation.created, 0));
+        let expires = time::at_utc(time::Timespec::new(invitation.expires, 0));
+        println!(
+            "{} {} {}{}",
+            created.rfc3339(),
+            invitation.email,
+            if invitation.expires > now { "expires " } else { "expired " },
+            expires.rfc3339()
+        );
+    }
+    Ok(())
+}
+
+fn handle_revoke_invitation_command(
+    args: &clap::ArgMatches,
+    store: Box<Store>,
+) -> Result<(), CliError> {
+    let reference = args.value_of("REFERENCE").unwrap();
+    let user = store.find_user(reference)?.ok_or(
+        CliError::OtherError("user not found"),
+    )?;
+    store.delete_invitation(&user.id)?;
+    // users who accepted have a password and stay
+    if user.password.is_none() {
+        store.delete_user(&user.email)?;
+    }
+    Ok(())
+}
+
 fn read_pwd_from_file(file: &str) -> Result<String, CliError> {
     let mut pwd_file = fs::File::open(file)?;
     let mut content = String::new();
@@ -1,11 +1,11 @@
 #![feature(plugin,custom_derive)]
 #![plugin(rocket_codegen)]
 extern crate rocket;
-extern crate rustwt;
 extern crate rusqlite;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde;
+#[macro_use]
 extern crate serde_json;
 extern crate uuid;
 extern crate time;
@@ -13,6 +13,10 @@ extern crate openssl;
 extern crate url;
 extern crate base64;
 extern crate clap;
+extern crate reqwest;
+extern crate qrcode;
+#[cfg(feature = "pkcs11")]
+extern crate pkcs11;
 
 
 pub mod store;
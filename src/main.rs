@@ -51,10 +51,178 @@ fn main() {
                     If not set, the issuer is set to the incoming requests host name.",
                         )
                         .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("session-lifetime")
+                        .long("session-lifetime")
+                        .value_name("SECONDS")
+                        .takes_value(true)
+                        .help(
+                            "How long a login is valid for all clients. Defaults to 36000 (10 hours)",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("session-idle-timeout")
+                        .long("session-idle-timeout")
+                        .value_name("SECONDS")
+                        .takes_value(true)
+                        .help(
+                            "Sessions that were not used for this long expire. Defaults to 3600",
+                        ),
+                )
+                .args(&lifetime_args())
+                .arg(
+                    Arg::with_name("lockout-threshold")
+                        .long("lockout-threshold")
+                        .value_name("FAILURES")
+                        .takes_value(true)
+                        .help(
+                            "Failed logins in a row that lock a user or client. Defaults to 5",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("address-lockout-threshold")
+                        .long("address-lockout-threshold")
+                        .value_name("FAILURES")
+                        .takes_value(true)
+                        .help(
+                            "Failed logins in a row that lock out a remote address. Defaults to 20",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("lockout-duration")
+                        .long("lockout-duration")
+                        .value_name("SECONDS")
+                        .takes_value(true)
+                        .help(
+                            "How long a lockout lasts. Defaults to 900 (15 minutes)",
+                        ),
+                )
+                .args(&mail_args())
+                .arg(
+                    Arg::with_name("allow-registration")
+                        .long("allow-registration")
+                        .help(
+                            "Let users sign up themselves, they verify their address by mail. \
+                            Requires --smtp-server or --maildir",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("registration-domain")
+                        .long("registration-domain")
+                        .value_name("DOMAIN")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .requires("allow-registration")
+                        .help("Only addresses of this domain may sign up. Can be given more than once"),
+                )
+                .arg(
+                    Arg::with_name("registration-approval")
+                        .long("registration-approval")
+                        .requires("allow-registration")
+                        .help("Users who signed up can log in only after `users approve`"),
+                )
+                .arg(Arg::with_name("plain-http").long("plain-http").help(
+                    "Browsers reach the server over plain http, for development. \
+                    Cookies are not marked secure and no HSTS header is sent",
+                ))
+                .arg(
+                    Arg::with_name("hsts-max-age")
+                        .long("hsts-max-age")
+                        .value_name("SECONDS")
+                        .takes_value(true)
+                        .conflicts_with("plain-http")
+                        .help(
+                            "How long browsers keep to https, 0 sends no HSTS header. \
+                            Defaults to 31536000 (one year)",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("cookie-same-site")
+                        .long("cookie-same-site")
+                        .value_name("MODE")
+                        .takes_value(true)
+                        .possible_values(&["lax", "strict"])
+                        .help(
+                            "SameSite attribute of the session cookies. With strict, \
+                            logins are not reused when clients redirect to the server. \
+                            Defaults to lax",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("cookie-secret-grace-period")
+                        .long("cookie-secret-grace-period")
+                        .value_name("SECONDS")
+                        .takes_value(true)
+                        .help(
+                            "Cookies encrypted with the secret replaced by \
+                            keys rotate-cookie-secret are accepted this long after the rotation. \
+                            Defaults to 86400 (one day)",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("signing-alg")
+                        .long("signing-alg")
+                        .value_name("ALG")
+                        .takes_value(true)
+                        .possible_values(openid::server::keys::SUPPORTED_ALGORITHMS)
+                        .help(
+                            "The algorithm id tokens are signed with, \
+                            unless a client asks for another one. Defaults to ES256",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("key-rotation-days")
+                        .long("key-rotation-days")
+                        .value_name("DAYS")
+                        .takes_value(true)
+                        .help(
+                            "Signing keys are rotated after this many days, \
+                            retired keys are published as long. Defaults to 90",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("pkcs11-module")
+                        .long("pkcs11-module")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .requires_all(&["pkcs11-token", "pkcs11-pin-file", "pkcs11-key"])
+                        .help(
+                            "Sign with keys on a PKCS#11 token instead of the key directory, \
+                            using this module. Requires a build with the pkcs11 feature",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("pkcs11-token")
+                        .long("pkcs11-token")
+                        .value_name("LABEL")
+                        .takes_value(true)
+                        .help("Label of the PKCS#11 token holding the signing keys"),
+                )
+                .arg(
+                    Arg::with_name("pkcs11-pin-file")
+                        .long("pkcs11-pin-file")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .help("File containing the user pin of the PKCS#11 token"),
+                )
+                .arg(
+                    Arg::with_name("pkcs11-key")
+                        .long("pkcs11-key")
+                        .value_name("LABEL")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help(
+                            "Label of a key pair on the PKCS#11 token, once per key. \
+                            These keys are rotated on the token",
+                        ),
                 ),
         )
         .subcommand(users_subcommand())
         .subcommand(clients_subcommand())
+        .subcommand(keys_subcommand())
         .get_matches();
 
     let home_dir = std::env::home_dir().unwrap();
@@ -92,6 +260,29 @@ fn clients_subcommand() -> clap::App<'static, 'static> {
                         .multiple(true)
                         .value_name("REDIRECT_URL")
                         .help("add a redirect url to this client"),
+                )
+                .arg(Arg::with_name("first-party").long("first-party").help(
+                    "Trust this client, users are not asked for consent.",
+                ))
+                .arg(Arg::with_name("public").long("public").help(
+                    "Do not generate a client secret. \
+                    Use this for clients that can not keep a secret, they have to use PKCE.",
+                ))
+                .arg(
+                    Arg::with_name("acr")
+                        .long("acr")
+                        .value_name("ACR")
+                        .takes_value(true)
+                        .possible_values(&["password", "mfa"])
+                        .help("the authentication context every login for this client must reach"),
+                )
+                .arg(
+                    Arg::with_name("signing-alg")
+                        .long("signing-alg")
+                        .value_name("ALG")
+                        .takes_value(true)
+                        .possible_values(openid::server::keys::SUPPORTED_ALGORITHMS)
+                        .help("the algorithm id tokens for this client are signed with"),
                 ),
         )
         .subcommand(SubCommand::with_name("list").about("show clients"))
@@ -120,6 +311,251 @@ fn clients_subcommand() -> clap::App<'static, 'static> {
                     "the redirect url to remove",
                 )),
         )
+        .subcommand(
+            SubCommand::with_name("set-acr")
+                .about("set the authentication context a client requires")
+                .arg(Arg::with_name("REFERENCE").required(true).help(
+                    "A reference to a client. Either the ID of the name of the client.",
+                ))
+                .arg(
+                    Arg::with_name("ACR")
+                        .possible_values(&["password", "mfa"])
+                        .help("the required acr. If omitted, the requirement is removed."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("rotate-secret")
+                .about(
+                    "give a client a new secret, the old one stops working. \
+                    Clients added before public clients needed PKCE have to get one.",
+                )
+                .arg(Arg::with_name("REFERENCE").required(true).help(
+                    "A reference to a client. Either the ID of the name of the client.",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("set-first-party")
+                .about("decide whether users are asked for consent for a client")
+                .arg(Arg::with_name("REFERENCE").required(true).help(
+                    "A reference to a client. Either the ID of the name of the client.",
+                ))
+                .arg(
+                    Arg::with_name("FIRST_PARTY")
+                        .required(true)
+                        .possible_values(&["true", "false"])
+                        .help("true to skip the consent page for this client"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("set-signing-alg")
+                .about("choose the algorithm id tokens for a client are signed with")
+                .arg(Arg::with_name("REFERENCE").required(true).help(
+                    "A reference to a client. Either the ID of the name of the client.",
+                ))
+                .arg(
+                    Arg::with_name("ALG")
+                        .possible_values(openid::server::keys::SUPPORTED_ALGORITHMS)
+                        .help("the algorithm. If omitted, the server default is used."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("set-encryption")
+                .about(
+                    "encrypt id tokens or userinfo responses for a client. \
+                    Without algorithms, nothing is encrypted.",
+                )
+                .arg(Arg::with_name("REFERENCE").required(true).help(
+                    "A reference to a client. Either the ID of the name of the client.",
+                ))
+                .arg(
+                    Arg::with_name("key")
+                        .long("key")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .help("pem file with the public RSA or EC key of the client"),
+                )
+                .args(&encryption_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("set-subject-type")
+                .about("choose how the subject identifiers a client sees are derived")
+                .arg(Arg::with_name("REFERENCE").required(true).help(
+                    "A reference to a client. Either the ID of the name of the client.",
+                ))
+                .arg(
+                    Arg::with_name("TYPE")
+                        .required(true)
+                        .possible_values(openid::server::subject::SUBJECT_TYPES)
+                        .help(
+                            "public subjects are the user id, \
+                            pairwise subjects differ between sectors",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("sector-identifier-uri")
+                        .long("sector-identifier-uri")
+                        .value_name("URL")
+                        .takes_value(true)
+                        .help(
+                            "https url of a JSON array listing the redirect urls \
+                            of all clients sharing pairwise subjects",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("set-lifetimes")
+                .about(
+                    "set the lifetimes of the tokens issued to a client. \
+                    Omitted lifetimes fall back to the server defaults.",
+                )
+                .arg(Arg::with_name("REFERENCE").required(true).help(
+                    "A reference to a client. Either the ID of the name of the client.",
+                ))
+                .args(&lifetime_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("revoke-consents")
+                .about("revoke the consent of all users for a client")
+                .arg(Arg::with_name("REFERENCE").required(true).help(
+                    "A reference to a client. Either the ID of the name of the client.",
+                )),
+        )
+}
+
+
+
+fn lifetime_args() -> Vec<clap::Arg<'static, 'static>> {
+    [
+        (
+            "id-token-lifetime",
+            "Seconds id tokens are valid. Defaults to 1200 (20 minutes)",
+        ),
+        (
+            "access-token-lifetime",
+            "Seconds access tokens are valid. Defaults to 604800 (7 days)",
+        ),
+        (
+            "refresh-token-lifetime",
+            "Seconds refresh tokens for the offline_access scope are valid. Defaults to 2592000 (30 days)",
+        ),
+        (
+            "code-lifetime",
+            "Seconds authorization codes are valid. Defaults to 600 (10 minutes)",
+        ),
+    ].iter()
+        .map(|&(name, help)| {
+            Arg::with_name(name)
+                .long(name)
+                .value_name("SECONDS")
+                .takes_value(true)
+                .help(help)
+        })
+        .collect()
+}
+
+
+fn encryption_args() -> Vec<clap::Arg<'static, 'static>> {
+    use openid::server::jwe::{SUPPORTED_ALGORITHMS, SUPPORTED_ENCRYPTIONS};
+    [
+        (
+            "id-token-alg",
+            "ALG",
+            SUPPORTED_ALGORITHMS,
+            "the key management algorithm for id tokens",
+        ),
+        (
+            "id-token-enc",
+            "ENC",
+            SUPPORTED_ENCRYPTIONS,
+            "the content encryption for id tokens. Defaults to A256GCM",
+        ),
+        (
+            "userinfo-alg",
+            "ALG",
+            SUPPORTED_ALGORITHMS,
+            "the key management algorithm for userinfo responses",
+        ),
+        (
+            "userinfo-enc",
+            "ENC",
+            SUPPORTED_ENCRYPTIONS,
+            "the content encryption for userinfo responses. Defaults to A256GCM",
+        ),
+    ].iter()
+        .map(|&(name, value_name, values, help)| {
+            Arg::with_name(name)
+                .long(name)
+                .value_name(value_name)
+                .takes_value(true)
+                .possible_values(values)
+                .help(help)
+        })
+        .collect()
+}
+
+
+fn mail_args() -> Vec<clap::Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("smtp-server")
+            .long("smtp-server")
+            .value_name("HOST:PORT")
+            .takes_value(true)
+            .conflicts_with("maildir")
+            .requires("mail-from")
+            .help(
+                "Send mail, like password reset links and invitations, through this SMTP relay. \
+                Without a mailer passwords can not be reset",
+            ),
+        Arg::with_name("maildir")
+            .long("maildir")
+            .value_name("DIR")
+            .takes_value(true)
+            .requires("mail-from")
+            .help("Drop outgoing mail into this maildir instead of sending it"),
+        Arg::with_name("mail-from")
+            .long("mail-from")
+            .value_name("ADDRESS")
+            .takes_value(true)
+            .help("The sender address of outgoing mail"),
+    ]
+}
+
+
+fn keys_subcommand() -> clap::App<'static, 'static> {
+    SubCommand::with_name("keys")
+        .setting(AppSettings::SubcommandRequired)
+        .about("manage the id token signing keys and the cookie secret")
+        .subcommand(SubCommand::with_name("list").about(
+            "show all signing keys and their state",
+        ))
+        .subcommand(
+            SubCommand::with_name("rotate")
+                .about(
+                    "activate the published next keys, retire the active ones \
+                    and generate new next keys",
+                )
+                .arg(
+                    Arg::with_name("key-type")
+                        .long("key-type")
+                        .value_name("TYPE")
+                        .takes_value(true)
+                        .possible_values(&["P-256", "P-384", "RSA", "Ed25519"])
+                        .help("Only rotate keys of this type. Defaults to all types."),
+                )
+                .arg(
+                    Arg::with_name("retention-days")
+                        .long("retention-days")
+                        .value_name("DAYS")
+                        .takes_value(true)
+                        .help(
+                            "Keys retired at least this many days ago are removed. Defaults to 90",
+                        ),
+                ),
+        )
+        .subcommand(SubCommand::with_name("rotate-cookie-secret").about(
+            "replace the secret the session cookies are encrypted with, \
+            servers use the new one once restarted",
+        ))
 }
 
 
@@ -170,7 +606,68 @@ fn users_subcommand() -> clap::App<'static, 'static> {
                             "Add a group for this user.
                     If it does not exist, it will be created.",
                         ),
-                ),
+                )
+                .args(&profile_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("invite")
+                .about(
+                    "Create a user without a password and mail a link to choose one. \
+                    Without a mailer the link is printed.",
+                )
+                .arg(
+                    Arg::with_name("EMAIL")
+                        .help("the email address of the user")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("url")
+                        .long("url")
+                        .value_name("URL")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The address the server is reached at, like https://id.example.com"),
+                )
+                .arg(
+                    Arg::with_name("group")
+                        .short("g")
+                        .long("group")
+                        .takes_value(true)
+                        .value_name("GROUP")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Add a group for this user. If it does not exist, it will be created."),
+                )
+                .arg(
+                    Arg::with_name("expires-days")
+                        .long("expires-days")
+                        .value_name("DAYS")
+                        .takes_value(true)
+                        .help("How long the link is valid. Defaults to 7"),
+                )
+                .arg(Arg::with_name("require-mfa").long("require-mfa").help(
+                    "The user sets up a second factor on the first login",
+                ))
+                .args(&mail_args())
+                .args(&profile_args()),
+        )
+        .subcommand(SubCommand::with_name("invitations").about(
+            "List the invitations that were not accepted yet.",
+        ))
+        .subcommand(
+            SubCommand::with_name("revoke-invitation")
+                .about("Revoke the invitation of a user and remove the user.")
+                .arg(Arg::with_name("REFERENCE").required(true).help(
+                    "Id or email of user",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("update-profile")
+                .about("Replace the profile attributes of a user.")
+                .arg(Arg::with_name("REFERENCE").required(true).help(
+                    "Id or email of user",
+                ))
+                .args(&profile_args()),
         )
         .subcommand(SubCommand::with_name("list").help("List available users."))
         .subcommand(SubCommand::with_name("delete").arg(
@@ -195,6 +692,113 @@ fn users_subcommand() -> clap::App<'static, 'static> {
                     If it does not exist, it will becreated.",
                 )),
         )
+        .subcommand(
+            SubCommand::with_name("consents")
+                .about("List the clients a user gave consent to.")
+                .arg(Arg::with_name("REFERENCE").required(true).help(
+                    "Id or email of user",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("revoke-consent")
+                .about("Revoke the consent a user gave.")
+                .arg(Arg::with_name("REFERENCE").required(true).help(
+                    "Id or email of user",
+                ))
+                .arg(
+                    Arg::with_name("client")
+                        .short("c")
+                        .long("client")
+                        .takes_value(true)
+                        .value_name("CLIENT")
+                        .help("Only revoke the consent for this client. Defaults to all clients."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("reset-totp")
+                .about(
+                    "Remove the authenticator of a user, a new one may be enrolled at logins \
+                     within the next seven days.",
+                )
+                .arg(Arg::with_name("REFERENCE").required(true).help(
+                    "Id or email of user",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("regenerate-recovery-codes")
+                .about("Replace the recovery codes of a user by new ones and print them.")
+                .arg(Arg::with_name("REFERENCE").required(true).help(
+                    "Id or email of user",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("security-keys")
+                .about("List the security keys and passkeys of a user.")
+                .arg(Arg::with_name("REFERENCE").required(true).help(
+                    "Id or email of user",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("remove-security-key")
+                .about("Remove a security key or passkey of a user.")
+                .arg(Arg::with_name("REFERENCE").required(true).help(
+                    "Id or email of user",
+                ))
+                .arg(Arg::with_name("ID").required(true).help(
+                    "Id of the key, as listed by security-keys",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("require-mfa")
+                .about("decide whether a user has to log in with a second factor")
+                .arg(Arg::with_name("REFERENCE").required(true).help(
+                    "Id or email of user",
+                ))
+                .arg(
+                    Arg::with_name("REQUIRED")
+                        .required(true)
+                        .possible_values(&["true", "false"])
+                        .help(
+                            "true to ask the user for a TOTP code on every login, an \
+                             authenticator may be enrolled at logins within the next seven days",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("require-group-mfa")
+                .about("decide whether the members of a group have to log in with a second factor")
+                .arg(Arg::with_name("GROUP").required(true).help("The group"))
+                .arg(
+                    Arg::with_name("REQUIRED")
+                        .required(true)
+                        .possible_values(&["true", "false"])
+                        .help(
+                            "true to ask all members for a TOTP code on every login, those \
+                             without an authenticator enroll one after a password reset or \
+                             reset-totp",
+                        ),
+                ),
+        )
+        .subcommand(SubCommand::with_name("pending").about(
+            "List users who signed up and await approval.",
+        ))
+        .subcommand(
+            SubCommand::with_name("approve")
+                .about("Let a user who signed up log in.")
+                .arg(Arg::with_name("REFERENCE").required(true).help(
+                    "Id or email of user",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("unlock")
+                .about("Lift the lockout of a user after failed logins.")
+                .arg(Arg::with_name("REFERENCE").required(true).help(
+                    "Id or email of user",
+                )),
+        )
+        .subcommand(SubCommand::with_name("lockouts").about(
+            "List when users, clients and addresses were locked out and unlocked.",
+        ))
         .subcommand(
             SubCommand::with_name("leave-group")
                 .arg(Arg::with_name("REFERENCE").required(true).help(
@@ -205,3 +809,25 @@ fn users_subcommand() -> clap::App<'static, 'static> {
                 )),
         )
 }
+
+
+
+fn profile_args() -> Vec<clap::Arg<'static, 'static>> {
+    [
+        ("name", "the full name of the user"),
+        ("given-name", "the given name of the user"),
+        ("family-name", "the family name of the user"),
+        ("picture", "url of a profile picture"),
+        ("phone", "the phone number of the user"),
+        ("locale", "the locale of the user, e.g. en-US"),
+        ("address", "the formatted postal address of the user"),
+    ].iter()
+        .map(|&(name, help)| {
+            Arg::with_name(name)
+                .long(name)
+                .takes_value(true)
+                .value_name("VALUE")
+                .help(help)
+        })
+        .collect()
+}
@@ -0,0 +1,63 @@
+use store::Client;
+
+/// Authentication levels, ordered from weakest to strongest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AuthLevel {
+    Password,
+    MultiFactor,
+}
+
+// authentication methods (RFC 8176) that count as a second factor. "rc",
+// a recovery code, is our own, RFC 8176 has no value for it.
+static SECOND_FACTOR_METHODS: &[&str] = &["otp", "hwk", "swk", "rc"];
+
+impl AuthLevel {
+    pub fn from_acr(acr: &str) -> Option<AuthLevel> {
+        match acr {
+            "password" => Some(AuthLevel::Password),
+            "mfa" => Some(AuthLevel::MultiFactor),
+            _ => None,
+        }
+    }
+
+    pub fn acr(&self) -> &'static str {
+        match *self {
+            AuthLevel::Password => "password",
+            AuthLevel::MultiFactor => "mfa",
+        }
+    }
+
+    /// The level reached by a session that authenticated with the given
+    /// methods. "mfa" is claimed by methods that are multi-factor on their
+    /// own, like passkeys that verify the user.
+    pub fn achieved_by(amr: &[String]) -> AuthLevel {
+        let has_password = amr.iter().any(|m| m == "pwd");
+        let has_second_factor = amr.iter().any(|m| SECOND_FACTOR_METHODS.contains(&&m[..]));
+        if has_password && has_second_factor || amr.iter().any(|m| m == "mfa") {
+            AuthLevel::MultiFactor
+        } else {
+            AuthLevel::Password
+        }
+    }
+
+    /// The level an authentication request has to reach, taking the
+    /// requested `acr_values` and the acr required by the client into account.
+    /// Any of the requested values satisfies the request, unknown ones are ignored.
+    pub fn required(acr_values: Option<&str>, client: &Client) -> AuthLevel {
+        let requested = acr_values
+            .map(|values| {
+                values.split_whitespace().filter_map(AuthLevel::from_acr).min()
+            })
+            .unwrap_or(None);
+        let demanded_by_client = client.required_acr.as_ref().and_then(
+            |acr| AuthLevel::from_acr(acr),
+        );
+        let mut level = AuthLevel::Password;
+        for candidate in requested.into_iter().chain(demanded_by_client) {
+            if candidate > level {
+                level = candidate;
+            }
+        }
+        level
+    }
+}
@@ -1,6 +1,9 @@
 
-use {uuid, url};
-use server::Config;
+use {serde_json, url};
+use serde_json::Value;
+use server::{jwt, Config};
+use server::acr::AuthLevel;
+use server::claims::{requested_values, ClaimsRequest, SUPPORTED_SCOPES};
 use store;
 
 #[derive(FromForm, Serialize, Deserialize)]
@@ -18,12 +21,16 @@ pub struct AuthenticationRequest {
     pub id_token_hint: Option<String>,
     pub login_hint: Option<String>,
     pub acr_values: Option<String>,
+    pub claims: Option<String>,
+    /// PKCE, RFC 7636. Public clients redeem their codes with the verifier.
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
 }
 
 impl AuthenticationRequest {
-    pub fn validate(&mut self, config: &Config) -> Result<(), OidcErr> {
-        if self.scope != "openid" {
-            return Err(OidcErr::ClientErr("only scopen openid is supported"));
+    pub fn validate(&mut self, config: &Config) -> Result<store::Client, OidcErr> {
+        if !self.scopes().iter().any(|scope| scope == "openid") {
+            return Err(OidcErr::ClientErr("scope openid is required"));
         }
         let flow_check_result = match self.response_type.trim() {
             "id_token" => {
@@ -54,7 +61,7 @@ impl AuthenticationRequest {
             return Err(OidcErr::ClientErr("invalid redirect url"));
         }
         if flow_check_result.is_err() {
-            return flow_check_result;
+            return Err(flow_check_result.err().unwrap());
         }
 
         let client_lookup_result = config.store.get_client(self.client_id.trim());
@@ -79,16 +86,123 @@ impl AuthenticationRequest {
             return Err(OidcErr::ClientErr("invalid redirect uri"));
         }
 
+        if let Some(ref claims) = self.claims {
+            if serde_json::from_str::<ClaimsRequest>(claims).is_err() {
+                return Err(OidcErr::ClientErr("invalid claims parameter"));
+            }
+        }
+
+        match (self.code_challenge.as_ref(), self.code_challenge_method.as_ref()) {
+            (None, None) => {}
+            (Some(_), Some(method)) if method == "S256" => {}
+            _ => return Err(OidcErr::ClientErr("code_challenge_method has to be S256")),
+        }
+
         if self.display.is_none() {
             self.display = Some(String::from("page"));
         }
 
-        self.state = Some(uuid::Uuid::new_v4().simple().to_string());
-
         //TODO implement optional options
 
-        Ok(())
+        Ok(client)
+
+    }
+
+    // requested scopes, the ones we don't know are ignored. offline_access
+    // only counts for codes asked for with prompt=consent, OpenID Connect
+    // Core section 11.
+    pub fn scopes(&self) -> Vec<String> {
+        let offline = self.response_type.trim() == "code" && self.prompts("consent");
+        self.scope
+            .split_whitespace()
+            .filter(|scope| SUPPORTED_SCOPES.contains(scope))
+            .filter(|scope| *scope != "offline_access" || offline)
+            .map(String::from)
+            .collect()
+    }
+
+    // the claims parameter, validated in validate.
+    pub fn claims_request(&self) -> ClaimsRequest {
+        self.claims
+            .as_ref()
+            .and_then(|claims| serde_json::from_str(claims).ok())
+            .unwrap_or_default()
+    }
+
+    // acr can be requested with acr_values or as a claim of the id token.
+    pub fn required_level(&self, client: &store::Client) -> AuthLevel {
+        let level = AuthLevel::required(self.acr_values.as_ref().map(|v| &v[..]), client);
+        let claimed = self.claims_request()
+            .id_token
+            .get("acr")
+            .map(requested_values)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|acr| AuthLevel::from_acr(acr))
+            .min();
+        match claimed {
+            Some(claimed) if claimed > level => claimed,
+            _ => level,
+        }
+    }
+
+    // whether a login at auth_time is recent enough for max_age.
+    pub fn max_age_allows(&self, auth_time: i64, now: i64) -> bool {
+        match self.max_age.as_ref().and_then(|age| age.trim().parse::<i64>().ok()) {
+            Some(max_age) => now - auth_time <= max_age,
+            None => true,
+        }
+    }
 
+    pub fn prompts(&self, value: &str) -> bool {
+        match self.prompt {
+            Some(ref prompt) => prompt.split_whitespace().any(|item| item == value),
+            None => false,
+        }
+    }
+
+    // returns the subject of the id_token_hint, if present.
+    // Expired hints are accepted, as permitted by the spec.
+    pub fn hinted_subject(&self, config: &Config) -> Result<Option<String>, OidcErr> {
+        let hint = match self.id_token_hint {
+            Some(ref hint) if !hint.trim().is_empty() => hint,
+            _ => return Ok(None),
+        };
+
+        let decoded = {
+            let keys = config.keys.current();
+            jwt::decode_verified(hint, &keys)
+        };
+        let claims = match decoded {
+            Ok(claims) => claims,
+            Err(e) => {
+                println!("rejected id_token_hint: {}", e);
+                return Err(OidcErr::ClientErr("invalid id_token_hint"));
+            }
+        };
+
+        let client_id = self.client_id.trim();
+        let issued_to_client = match claims.get("aud") {
+            Some(&Value::String(ref aud)) => aud == client_id,
+            Some(&Value::Array(ref auds)) => auds.iter().any(|aud| aud.as_str() == Some(client_id)),
+            _ => false,
+        };
+        if !issued_to_client {
+            return Err(OidcErr::ClientErr("id_token_hint was not issued to this client"));
+        }
+
+        if let (&Some(ref issuer), Some(iss)) =
+            (&config.issuer, claims.get("iss").and_then(|iss| iss.as_str()))
+        {
+            if issuer != iss {
+                return Err(OidcErr::ClientErr("id_token_hint was issued by someone else"));
+            }
+        }
+
+        match claims.get("sub").and_then(|sub| sub.as_str()) {
+            Some(sub) => Ok(Some(String::from(sub))),
+            None => Err(OidcErr::ClientErr("id_token_hint has no subject")),
+        }
     }
 }
 
@@ -0,0 +1,184 @@
+use serde_json::{Map, Value};
+use store::User;
+
+pub static SUPPORTED_SCOPES: &[&str] = &[
+    "openid",
+    "profile",
+    "email",
+    "phone",
+    "address",
+    "groups",
+    "offline_access",
+];
+
+static PROFILE_CLAIMS: &[&str] = &[
+    "name",
+    "given_name",
+    "family_name",
+    "picture",
+    "locale",
+];
+
+/// Names of the claims a scope releases.
+pub fn scope_claim_names(scope: &str) -> &'static [&'static str] {
+    match scope {
+        "profile" => PROFILE_CLAIMS,
+        "email" => &["email", "email_verified"],
+        "phone" => &["phone_number"],
+        "address" => &["address"],
+        "groups" => &["groups"],
+        _ => &[],
+    }
+}
+
+/// Value of a single claim for `user`, None if the user has no such attribute.
+pub fn user_claim(user: &User, name: &str) -> Option<Value> {
+    let optional = |value: &Option<String>| value.as_ref().map(|v| json!(v));
+    match name {
+        "name" => optional(&user.profile.name),
+        "given_name" => optional(&user.profile.given_name),
+        "family_name" => optional(&user.profile.family_name),
+        "picture" => optional(&user.profile.picture),
+        "locale" => optional(&user.profile.locale),
+        "phone_number" => optional(&user.profile.phone_number),
+        "email" => Some(json!(user.email)),
+        "email_verified" => Some(json!(user.email_verified)),
+        "address" => {
+            user.profile.address.as_ref().map(
+                |address| json!({"formatted": address}),
+            )
+        }
+        "groups" => Some(json!(user.groups)),
+        _ => None,
+    }
+}
+
+/// All claims released by `scopes`.
+pub fn scope_claims(user: &User, scopes: &[String]) -> Map<String, Value> {
+    let mut claims = Map::new();
+    for scope in scopes {
+        for name in scope_claim_names(scope) {
+            if let Some(value) = user_claim(user, name) {
+                claims.insert(String::from(*name), value);
+            }
+        }
+    }
+    claims
+}
+
+
+/// The `claims` request parameter. Each member maps a claim name to either
+/// null or an object with `essential`, `value` or `values`.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct ClaimsRequest {
+    #[serde(default)]
+    pub userinfo: Map<String, Value>,
+    #[serde(default)]
+    pub id_token: Map<String, Value>,
+}
+
+// whether a claim value satisfies the value or values constraint of its request.
+fn satisfies(request: &Value, value: &Value) -> bool {
+    if let Some(expected) = request.get("value") {
+        return expected == value;
+    }
+    match request.get("values") {
+        Some(&Value::Array(ref values)) => values.contains(value),
+        _ => true,
+    }
+}
+
+/// Values an individual claim request asks for, used for `acr`.
+pub fn requested_values(request: &Value) -> Vec<String> {
+    if let Some(value) = request.get("value").and_then(|v| v.as_str()) {
+        return vec![String::from(value)];
+    }
+    match request.get("values") {
+        Some(&Value::Array(ref values)) => {
+            values
+                .iter()
+                .filter_map(|v| v.as_str())
+                .map(String::from)
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Claims of `user` asked for individually. Claims the user doesn't have
+/// or whose value doesn't match the requested one are left out.
+pub fn requested_claims(user: &User, requests: &Map<String, Value>) -> Map<String, Value> {
+    let mut claims = Map::new();
+    for (name, request) in requests {
+        if let Some(value) = user_claim(user, name) {
+            if satisfies(request, &value) {
+                claims.insert(name.clone(), value);
+            }
+        }
+    }
+    claims
+}
+
+/// Whether one of `scopes` releases the claim `name`.
+pub fn scopes_cover(scopes: &[String], name: &str) -> bool {
+    scopes.iter().any(|scope| scope_claim_names(scope).contains(&name))
+}
+
+/// Names of the claims about the user the `claims` parameter asks for in
+/// the id token or at the userinfo endpoint, sorted. Claims about the
+/// login itself, like `acr`, are not among them.
+pub fn requested_user_claims(request: &ClaimsRequest) -> Vec<String> {
+    let mut names: Vec<String> = request
+        .userinfo
+        .keys()
+        .chain(request.id_token.keys())
+        .filter(|name| {
+            SUPPORTED_SCOPES.iter().any(|scope| scope_claim_names(scope).contains(&&name[..]))
+        })
+        .cloned()
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Names of all claims that can be released about a user.
+pub fn supported_claims() -> Vec<&'static str> {
+    let mut names = vec!["sub", "iss", "auth_time", "acr", "amr"];
+    for scope in SUPPORTED_SCOPES {
+        names.extend(scope_claim_names(scope));
+    }
+    names
+}
+
+/// Human readable description of a scope, shown on the consent page.
+pub fn scope_description(scope: &str) -> &'static str {
+    match scope {
+        "openid" => "your identity",
+        "profile" => "your name, picture and locale",
+        "email" => "your email address",
+        "phone" => "your phone number",
+        "address" => "your postal address",
+        "groups" => "the groups you belong to",
+        "offline_access" => "access while you are not logged in",
+        _ => "",
+    }
+}
+
+/// Human readable description of a single claim, shown on the consent page
+/// for claims asked for outside of a scope.
+pub fn claim_description(name: &str) -> &'static str {
+    match name {
+        "name" => "your name",
+        "given_name" => "your given name",
+        "family_name" => "your family name",
+        "picture" => "your picture",
+        "locale" => "your locale",
+        "email" => "your email address",
+        "email_verified" => "whether your email address is verified",
+        "phone_number" => "your phone number",
+        "address" => "your postal address",
+        "groups" => "the groups you belong to",
+        _ => "",
+    }
+}
@@ -0,0 +1,231 @@
+//! The secret the private cookies are encrypted with. It is kept in the
+//! configuration directory, so logins in flight survive restarts and all
+//! servers behind a load balancer read each others cookies. After a
+//! rotation the previous secret still opens cookies for a grace period,
+//! they are sealed again with the current one on their next request.
+
+use base64;
+use openssl;
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use openssl::symm::{self, Cipher};
+use rocket::Data;
+use rocket::Request;
+use rocket::fairing::{Fairing, Info, Kind};
+use server::security::SecurityPolicy;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use time;
+
+/// Seconds cookies of the previous secret are accepted after a rotation,
+/// longer than sessions last by default.
+pub const DEFAULT_GRACE_PERIOD: i64 = 24 * 60 * 60;
+// the private cookies of the server.
+const COOKIE_NAMES: &[&str] = &["session", "auth-request"];
+// 256 bits, what Rocket expects.
+const SECRET_SIZE: usize = 32;
+// how the cookie crate derives its keys from the secret.
+const KEYS_INFO: &[u8] = b"COOKIE;SIGNED:HMAC-SHA256;PRIVATE:AEAD-AES-256-GCM";
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+
+/// The current secret and the one it replaced, base64 encoded.
+pub struct CookieSecret {
+    pub current: String,
+    pub previous: Option<String>,
+    /// when `current` replaced `previous`.
+    pub rotated: i64,
+}
+
+fn new_secret() -> Result<String, ErrorStack> {
+    let mut bytes = [0; SECRET_SIZE];
+    openssl::rand::rand_bytes(&mut bytes)?;
+    Ok(base64::encode(&bytes))
+}
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn decode(secret: &str) -> Result<Vec<u8>, io::Error> {
+    match base64::decode(secret) {
+        Ok(ref bytes) if bytes.len() == SECRET_SIZE => Ok(bytes.clone()),
+        _ => Err(invalid("the cookie secret is no base64 encoded 256 bit key")),
+    }
+}
+
+impl CookieSecret {
+    /// The secret stored at `path`, a new one is created on first use.
+    pub fn load_or_create(path: &Path) -> Result<CookieSecret, io::Error> {
+        if path.exists() {
+            return CookieSecret::load(path);
+        }
+        let secret = CookieSecret {
+            current: new_secret()?,
+            previous: None,
+            rotated: time::get_time().sec,
+        };
+        secret.save(path)?;
+        Ok(secret)
+    }
+
+    // the current secret on the first line, the previous one and when it
+    // was replaced on the second.
+    fn load(path: &Path) -> Result<CookieSecret, io::Error> {
+        let mut content = String::new();
+        fs::File::open(path)?.read_to_string(&mut content)?;
+        let mut lines = content.lines();
+        let current = match lines.next() {
+            Some(line) if !line.trim().is_empty() => {
+                decode(line.trim())?;
+                String::from(line.trim())
+            }
+            _ => return Err(invalid("the cookie secret file is empty")),
+        };
+        let fields = lines.next().map(|line| line.split_whitespace().collect::<Vec<_>>());
+        let (previous, rotated) = match fields {
+            Some(ref fields) if fields.len() == 2 => {
+                let rotated = fields[1].parse::<i64>().map_err(|_| {
+                    invalid("malformed rotation time in the cookie secret file")
+                })?;
+                decode(fields[0])?;
+                (Some(String::from(fields[0])), rotated)
+            }
+            Some(ref fields) if !fields.is_empty() => {
+                return Err(invalid("malformed previous secret in the cookie secret file"))
+            }
+            _ => (None, 0),
+        };
+        Ok(CookieSecret {
+            current: current,
+            previous: previous,
+            rotated: rotated,
+        })
+    }
+
+    fn save(&self, path: &Path) -> Result<(), io::Error> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = fs::File::create(path)?;
+        writeln!(file, "{}", self.current)?;
+        if let Some(ref previous) = self.previous {
+            writeln!(file, "{} {}", previous, self.rotated)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the secret at `path` with a new one, the current one
+    /// becomes the previous. Servers use it once restarted.
+    pub fn rotate(path: &Path, now: i64) -> Result<CookieSecret, io::Error> {
+        let old = CookieSecret::load_or_create(path)?;
+        let secret = CookieSecret {
+            current: new_secret()?,
+            previous: Some(old.current),
+            rotated: now,
+        };
+        secret.save(path)?;
+        Ok(secret)
+    }
+}
+
+
+/// Opens private cookies sealed with the previous secret until the grace
+/// period ends and adds them again, so the handlers see them and the
+/// browser gets them sealed with the current secret.
+pub struct PreviousSecret {
+    key: Option<Vec<u8>>,
+    valid_until: i64,
+    policy: SecurityPolicy,
+}
+
+impl PreviousSecret {
+    pub fn new(
+        secret: &CookieSecret,
+        grace_period: i64,
+        policy: &SecurityPolicy,
+    ) -> Result<PreviousSecret, io::Error> {
+        let key = match secret.previous {
+            Some(ref previous) => Some(encryption_key(previous)?),
+            None => None,
+        };
+        Ok(PreviousSecret {
+            key: key,
+            valid_until: secret.rotated + grace_period,
+            policy: policy.clone(),
+        })
+    }
+}
+
+impl Fairing for PreviousSecret {
+    fn info(&self) -> Info {
+        Info {
+            name: "Previous Cookie Secret",
+            kind: Kind::Request,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _data: &Data) {
+        let key = match self.key {
+            Some(ref key) if time::get_time().sec < self.valid_until => key,
+            _ => return,
+        };
+        let mut cookies = request.cookies();
+        for &name in COOKIE_NAMES {
+            let sealed = match cookies.get(name) {
+                Some(cookie) => String::from(cookie.value()),
+                None => continue,
+            };
+            if cookies.get_private(name).is_some() {
+                continue;
+            }
+            if let Some(value) = open(key, name, &sealed) {
+                cookies.add_private(self.policy.cookie(name, value));
+            }
+        }
+    }
+}
+
+
+fn hmac_sha256(key: &[u8], parts: &[&[u8]]) -> Result<Vec<u8>, ErrorStack> {
+    let pkey = PKey::hmac(key)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &pkey)?;
+    for part in parts {
+        signer.update(part)?;
+    }
+    signer.sign_to_vec()
+}
+
+// the AES-256-GCM key of the private cookies: the second half of the 64
+// bytes HKDF-SHA256 derives from the secret with an empty salt.
+fn encryption_key(secret: &str) -> Result<Vec<u8>, io::Error> {
+    let master = decode(secret)?;
+    // an empty HMAC key is padded with zeros, like this one
+    let prk = hmac_sha256(&[0; 32], &[&master[..]])?;
+    let first = hmac_sha256(&prk, &[KEYS_INFO, &[1u8]])?;
+    let second = hmac_sha256(&prk, &[&first[..], KEYS_INFO, &[2u8]])?;
+    Ok(second)
+}
+
+// the plain value of a private cookie: base64 of nonce, ciphertext and
+// tag, authenticated together with the cookie name.
+fn open(key: &[u8], name: &str, sealed: &str) -> Option<String> {
+    let data = base64::decode(sealed).ok()?;
+    if data.len() < NONCE_SIZE + TAG_SIZE {
+        return None;
+    }
+    let (nonce, rest) = data.split_at(NONCE_SIZE);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TAG_SIZE);
+    let plain = symm::decrypt_aead(
+        Cipher::aes_256_gcm(),
+        key,
+        Some(nonce),
+        name.as_bytes(),
+        ciphertext,
+        tag,
+    ).ok()?;
+    String::from_utf8(plain).ok()
+}
@@ -0,0 +1,65 @@
+use serde_json::{Map, Value};
+use store::{Client, TokenLifetimes};
+
+/// An issued authorization code, waiting to be redeemed at the token endpoint.
+pub struct CodeGrant {
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub subject: String,
+    pub scopes: Vec<String>,
+    /// claims requested individually for the userinfo endpoint.
+    pub userinfo_claims: Map<String, Value>,
+    pub id_token: String,
+    /// the S256 PKCE challenge, the verifier has to match it.
+    pub code_challenge: Option<String>,
+    pub expires: i64,
+}
+
+/// What an access token grants access to. Refresh tokens carry the same,
+/// with the expiry of the refresh token.
+#[derive(Clone)]
+pub struct AccessGrant {
+    pub client_id: String,
+    pub subject: String,
+    pub scopes: Vec<String>,
+    pub userinfo_claims: Map<String, Value>,
+    pub expires: i64,
+}
+
+/// Lifetimes of issued tokens in seconds.
+#[derive(Clone, Copy)]
+pub struct Lifetimes {
+    pub id_token: i64,
+    pub access_token: i64,
+    /// refresh tokens are only issued for the offline_access scope.
+    pub refresh_token: i64,
+    /// authorization codes have to be redeemed within this time.
+    pub code: i64,
+}
+
+impl Default for Lifetimes {
+    fn default() -> Lifetimes {
+        Lifetimes {
+            id_token: 20 * 60,
+            access_token: 7 * 24 * 60 * 60,
+            refresh_token: 30 * 24 * 60 * 60,
+            code: 10 * 60,
+        }
+    }
+}
+
+impl Lifetimes {
+    /// The lifetimes for `client`, its own settings override these defaults.
+    pub fn for_client(&self, client: &Client) -> Lifetimes {
+        self.overridden_by(&client.lifetimes)
+    }
+
+    pub fn overridden_by(&self, own: &TokenLifetimes) -> Lifetimes {
+        Lifetimes {
+            id_token: own.id_token.unwrap_or(self.id_token),
+            access_token: own.access_token.unwrap_or(self.access_token),
+            refresh_token: own.refresh_token.unwrap_or(self.refresh_token),
+            code: own.code.unwrap_or(self.code),
+        }
+    }
+}
@@ -0,0 +1,52 @@
+//! Invitations create an account without the admin choosing its password.
+//! The invited user gets a mailed link to set one, the link is random and
+//! only its digest is stored, it works once and until the invitation expires.
+
+use base64;
+use openssl;
+use openssl::error::ErrorStack;
+use server::mail::Mail;
+use utils::hash_secret;
+
+/// Days an invitation is valid unless the admin says otherwise.
+pub const DEFAULT_LIFETIME_DAYS: i64 = 7;
+// 256 random bits.
+const TOKEN_SIZE: usize = 32;
+
+/// A new random token for the link.
+pub fn new_token() -> Result<String, ErrorStack> {
+    let mut bytes = [0; TOKEN_SIZE];
+    openssl::rand::rand_bytes(&mut bytes)?;
+    Ok(base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD))
+}
+
+/// The digest stored for `token`, tokens are random so it needs no salt.
+pub fn hash(token: &str) -> String {
+    hash_secret(token)
+}
+
+/// The link under `base_url` that accepts the invitation.
+pub fn link(base_url: &str, token: &str) -> String {
+    format!("{}/invitation?token={}", base_url.trim_right_matches('/'), token)
+}
+
+/// The mail that invites `email`, `days` is how long the link is valid.
+pub fn mail(email: &str, link: &str, days: i64, mfa_required: bool) -> Mail {
+    let mfa_note = if mfa_required {
+        "After that you set up a second factor on your first login.\n\n"
+    } else {
+        ""
+    };
+    Mail {
+        to: String::from(email),
+        subject: String::from("You are invited"),
+        body: format!(
+            "Hello,\n\nan account was created for you. Open this link within {} days \
+             to choose your password:\n\n{}\n\n{}\
+             If you did not expect this mail, ignore it.\n",
+            days,
+            link,
+            mfa_note
+        ),
+    }
+}
@@ -0,0 +1,265 @@
+use {base64, openssl, serde_json};
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::derive::Deriver;
+use openssl::ec::{EcGroup, EcKey, EcKeyRef};
+use openssl::nid::Nid;
+use openssl::pkey::{HasPublic, Id, PKey, Private, Public};
+use openssl::rsa::Padding;
+use openssl::symm::{self, Cipher};
+use serde_json::Value;
+use server::jwt::JwtError;
+
+/// Key management algorithms tokens can be encrypted with.
+pub static SUPPORTED_ALGORITHMS: &[&str] = &["ECDH-ES", "RSA-OAEP"];
+/// Content encryption algorithms.
+pub static SUPPORTED_ENCRYPTIONS: &[&str] = &["A256GCM"];
+
+// sizes of the A256GCM key, iv and authentication tag in bytes.
+const KEY_SIZE: usize = 32;
+const IV_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+
+
+fn encode(raw: &[u8]) -> String {
+    base64::encode_config(raw, base64::URL_SAFE_NO_PAD)
+}
+
+fn decode(segment: &str) -> Result<Vec<u8>, JwtError> {
+    base64::decode_config(segment, base64::URL_SAFE_NO_PAD).map_err(|_| {
+        JwtError::Malformed("token segment is not base64url encoded")
+    })
+}
+
+fn random_bytes(len: usize) -> Result<Vec<u8>, JwtError> {
+    let mut raw = vec![0; len];
+    openssl::rand::rand_bytes(&mut raw)?;
+    Ok(raw)
+}
+
+
+/// Parses a pem encoded public key and checks that it can be used with `alg`.
+pub fn public_key(pem: &str, alg: &str) -> Result<PKey<Public>, JwtError> {
+    let key = PKey::public_key_from_pem(pem.as_bytes())?;
+    let required = match alg {
+        "RSA-OAEP" => Id::RSA,
+        "ECDH-ES" => Id::EC,
+        _ => return Err(JwtError::UnsupportedAlgorithm(String::from(alg))),
+    };
+    if key.id() != required {
+        return Err(JwtError::Malformed("key does not match the encryption algorithm"));
+    }
+    Ok(key)
+}
+
+
+fn curve_name(nid: Option<Nid>) -> Option<&'static str> {
+    match nid {
+        Some(Nid::X9_62_PRIME256V1) => Some("P-256"),
+        Some(Nid::SECP384R1) => Some("P-384"),
+        Some(Nid::SECP521R1) => Some("P-521"),
+        _ => None,
+    }
+}
+
+fn curve_nid(name: &str) -> Option<Nid> {
+    match name {
+        "P-256" => Some(Nid::X9_62_PRIME256V1),
+        "P-384" => Some(Nid::SECP384R1),
+        "P-521" => Some(Nid::SECP521R1),
+        _ => None,
+    }
+}
+
+// the ephemeral public key sent in the epk header.
+fn ec_public_jwk<T: HasPublic>(key: &EcKeyRef<T>) -> Result<Value, JwtError> {
+    let crv = curve_name(key.group().curve_name()).ok_or(JwtError::Malformed(
+        "unsupported curve",
+    ))?;
+    let size = ((key.group().degree() + 7) / 8) as i32;
+    let mut x = BigNum::new()?;
+    let mut y = BigNum::new()?;
+    let mut ctx = BigNumContext::new()?;
+    key.public_key().affine_coordinates_gfp(
+        key.group(),
+        &mut x,
+        &mut y,
+        &mut ctx,
+    )?;
+    Ok(json!({
+        "kty": "EC",
+        "crv": crv,
+        "x": encode(&x.to_vec_padded(size)?),
+        "y": encode(&y.to_vec_padded(size)?),
+    }))
+}
+
+fn ec_public_key(jwk: &Value) -> Result<PKey<Public>, JwtError> {
+    let nid = jwk["crv"].as_str().and_then(curve_nid).ok_or(
+        JwtError::Malformed("unsupported curve"),
+    )?;
+    let coordinate = |name: &str| -> Result<BigNum, JwtError> {
+        let raw = decode(jwk[name].as_str().unwrap_or_default())?;
+        Ok(BigNum::from_slice(&raw)?)
+    };
+    let group = EcGroup::from_curve_name(nid)?;
+    let key = EcKey::from_public_key_affine_coordinates(
+        &group,
+        &coordinate("x")?,
+        &coordinate("y")?,
+    )?;
+    Ok(PKey::from_ec_key(key)?)
+}
+
+fn length_prefixed(out: &mut Vec<u8>, field: &[u8]) {
+    let len = field.len() as u32;
+    out.extend(&[(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8]);
+    out.extend(field);
+}
+
+// the Concat KDF of RFC 7518 section 4.6.2, without PartyUInfo and
+// PartyVInfo. A single round of SHA-256 yields the whole 256 bit key.
+fn concat_kdf(shared_secret: &[u8], enc: &str) -> Vec<u8> {
+    let mut input = vec![0, 0, 0, 1];
+    input.extend(shared_secret);
+    length_prefixed(&mut input, enc.as_bytes());
+    length_prefixed(&mut input, &[]);
+    length_prefixed(&mut input, &[]);
+    input.extend(&[0, 0, 1, 0]);
+    openssl::sha::sha256(&input).to_vec()
+}
+
+
+/// Encrypts `plaintext` for the holder of `key` and returns the compact
+/// serialized JWE. `cty` is set to "JWT" for signed tokens that are nested.
+pub fn encrypt(
+    plaintext: &[u8],
+    key: &PKey<Public>,
+    alg: &str,
+    enc: &str,
+    cty: Option<&str>,
+) -> Result<String, JwtError> {
+    if !SUPPORTED_ENCRYPTIONS.contains(&enc) {
+        return Err(JwtError::UnsupportedAlgorithm(String::from(enc)));
+    }
+    let mut header = json!({"alg": alg, "enc": enc});
+    if let Some(cty) = cty {
+        header["cty"] = json!(cty);
+    }
+
+    let (content_key, encrypted_key) = match alg {
+        "RSA-OAEP" => {
+            let content_key = random_bytes(KEY_SIZE)?;
+            let rsa = key.rsa()?;
+            let mut encrypted_key = vec![0; rsa.size() as usize];
+            let len = rsa.public_encrypt(
+                &content_key,
+                &mut encrypted_key,
+                Padding::PKCS1_OAEP,
+            )?;
+            encrypted_key.truncate(len);
+            (content_key, encrypted_key)
+        }
+        "ECDH-ES" => {
+            // direct key agreement, there is no encrypted key
+            let recipient = key.ec_key()?;
+            let ephemeral = EcKey::generate(recipient.group())?;
+            header["epk"] = ec_public_jwk(&ephemeral)?;
+            let ephemeral = PKey::from_ec_key(ephemeral)?;
+            let mut deriver = Deriver::new(&ephemeral)?;
+            deriver.set_peer(key)?;
+            (concat_kdf(&deriver.derive_to_vec()?, enc), Vec::new())
+        }
+        _ => return Err(JwtError::UnsupportedAlgorithm(String::from(alg))),
+    };
+
+    let protected = encode(header.to_string().as_bytes());
+    let iv = random_bytes(IV_SIZE)?;
+    let mut tag = vec![0; TAG_SIZE];
+    let ciphertext = symm::encrypt_aead(
+        Cipher::aes_256_gcm(),
+        &content_key,
+        Some(&iv),
+        protected.as_bytes(),
+        plaintext,
+        &mut tag,
+    )?;
+
+    Ok(format!(
+        "{}.{}.{}.{}.{}",
+        protected,
+        encode(&encrypted_key),
+        encode(&iv),
+        encode(&ciphertext),
+        encode(&tag)
+    ))
+}
+
+
+/// Decrypts a compact serialized JWE with the private key it was encrypted for.
+pub fn decrypt(token: &str, key: &PKey<Private>) -> Result<Vec<u8>, JwtError> {
+    let parts: Vec<&str> = token.trim().split('.').collect();
+    if parts.len() != 5 {
+        return Err(JwtError::Malformed("token must consist of five segments"));
+    }
+    let header: Value = serde_json::from_slice(&decode(parts[0])?).map_err(|_| {
+        JwtError::Malformed("token header is not json")
+    })?;
+    let alg = header["alg"].as_str().unwrap_or_default();
+    let enc = header["enc"].as_str().unwrap_or_default();
+    if !SUPPORTED_ENCRYPTIONS.contains(&enc) {
+        return Err(JwtError::UnsupportedAlgorithm(String::from(enc)));
+    }
+
+    let content_key = match alg {
+        "RSA-OAEP" => {
+            let rsa = key.rsa()?;
+            let mut content_key = vec![0; rsa.size() as usize];
+            let len = rsa.private_decrypt(
+                &decode(parts[1])?,
+                &mut content_key,
+                Padding::PKCS1_OAEP,
+            ).map_err(|_| JwtError::DecryptionFailed)?;
+            content_key.truncate(len);
+            content_key
+        }
+        "ECDH-ES" => {
+            let ephemeral = ec_public_key(&header["epk"])?;
+            let mut deriver = Deriver::new(key)?;
+            deriver.set_peer(&ephemeral)?;
+            concat_kdf(&deriver.derive_to_vec()?, enc)
+        }
+        _ => return Err(JwtError::UnsupportedAlgorithm(String::from(alg))),
+    };
+    if content_key.len() != KEY_SIZE {
+        return Err(JwtError::DecryptionFailed);
+    }
+
+    symm::decrypt_aead(
+        Cipher::aes_256_gcm(),
+        &content_key,
+        Some(&decode(parts[2])?),
+        parts[0].as_bytes(),
+        &decode(parts[3])?,
+        &decode(parts[4])?,
+    ).map_err(|_| JwtError::DecryptionFailed)
+}
+
+
+/// Encrypts `plaintext` as configured for a client, None if the client did
+/// not ask for encryption.
+pub fn encrypt_for(
+    plaintext: &[u8],
+    key: Option<&String>,
+    alg: Option<&String>,
+    enc: Option<&String>,
+    cty: Option<&str>,
+) -> Result<Option<String>, JwtError> {
+    let alg = match alg {
+        Some(alg) => alg,
+        None => return Ok(None),
+    };
+    let pem = key.ok_or(JwtError::Malformed("the client has no encryption key"))?;
+    let enc = enc.map(|enc| &enc[..]).unwrap_or("A256GCM");
+    let key = public_key(pem, alg)?;
+    encrypt(plaintext, &key, alg, enc, cty).map(Some)
+}
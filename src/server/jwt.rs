@@ -0,0 +1,221 @@
+use {base64, openssl, serde_json};
+use openssl::bn::BigNum;
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{HasPublic, PKey, Private};
+use openssl::rsa::Padding;
+use openssl::sign::{RsaPssSaltlen, Signer, Verifier};
+use server::keys::{KeySet, SigningKey};
+use serde_json::{Map, Value};
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum JwtError {
+    Malformed(&'static str),
+    UnsupportedAlgorithm(String),
+    InvalidSignature,
+    DecryptionFailed,
+    SigningFailed(String),
+    OpensslError(openssl::error::ErrorStack),
+}
+
+impl From<openssl::error::ErrorStack> for JwtError {
+    fn from(err: openssl::error::ErrorStack) -> JwtError {
+        JwtError::OpensslError(err)
+    }
+}
+
+impl Error for JwtError {
+    fn description(&self) -> &str {
+        match *self {
+            JwtError::Malformed(m) => m,
+            JwtError::UnsupportedAlgorithm(_) => "unsupported signing algorithm",
+            JwtError::InvalidSignature => "invalid token signature",
+            JwtError::DecryptionFailed => "token could not be decrypted",
+            JwtError::SigningFailed(_) => "token could not be signed",
+            JwtError::OpensslError(ref err) => err.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            JwtError::OpensslError(ref err) => Some(err as &Error),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for JwtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            JwtError::UnsupportedAlgorithm(ref alg) => {
+                write!(f, "unsupported signing algorithm: {}", alg)
+            }
+            JwtError::SigningFailed(ref message) => {
+                write!(f, "token could not be signed: {}", message)
+            }
+            JwtError::OpensslError(ref err) => fmt::Display::fmt(err, f),
+            _ => f.write_str(self.description()),
+        }
+    }
+}
+
+
+fn encode_segment(raw: &[u8]) -> String {
+    base64::encode_config(raw, base64::URL_SAFE_NO_PAD)
+}
+
+fn decode_segment(segment: &str) -> Result<Vec<u8>, JwtError> {
+    base64::decode_config(segment, base64::URL_SAFE_NO_PAD).map_err(|_| {
+        JwtError::Malformed("token segment is not base64url encoded")
+    })
+}
+
+fn decode_json(segment: &str) -> Result<Map<String, Value>, JwtError> {
+    let raw = decode_segment(segment)?;
+    match serde_json::from_slice(&raw) {
+        Ok(Value::Object(map)) => Ok(map),
+        _ => Err(JwtError::Malformed("token segment is not a json object")),
+    }
+}
+
+
+// JWS transports ECDSA signatures as the plain concatenation r || s,
+// openssl expects them DER encoded.
+fn ecdsa_der_to_raw(der: &[u8], coordinate_size: i32) -> Result<Vec<u8>, JwtError> {
+    let signature = EcdsaSig::from_der(der)?;
+    let mut raw = signature.r().to_vec_padded(coordinate_size)?;
+    raw.extend(signature.s().to_vec_padded(coordinate_size)?);
+    Ok(raw)
+}
+
+fn ecdsa_raw_to_der(raw: &[u8], coordinate_size: i32) -> Result<Vec<u8>, JwtError> {
+    if raw.len() != 2 * coordinate_size as usize {
+        return Err(JwtError::InvalidSignature);
+    }
+    let (r, s) = raw.split_at(raw.len() / 2);
+    let signature = EcdsaSig::from_private_components(BigNum::from_slice(r)?, BigNum::from_slice(s)?)?;
+    Ok(signature.to_der()?)
+}
+
+
+// digest and size of a single signature coordinate for an ecdsa algorithm.
+fn ecdsa_parameters(alg: &str) -> Option<(MessageDigest, i32)> {
+    match alg {
+        "ES256" => Some((MessageDigest::sha256(), 32)),
+        "ES384" => Some((MessageDigest::sha384(), 48)),
+        _ => None,
+    }
+}
+
+
+/// Creates the raw JWS signature of `input`. The key has to be of the type
+/// `alg` requires, see server::keys.
+pub fn sign_bytes(key: &PKey<Private>, alg: &str, input: &[u8]) -> Result<Vec<u8>, JwtError> {
+    if let Some((digest, coordinate_size)) = ecdsa_parameters(alg) {
+        let mut signer = Signer::new(digest, key)?;
+        signer.update(input)?;
+        return ecdsa_der_to_raw(&signer.sign_to_vec()?, coordinate_size);
+    }
+    match alg {
+        "RS256" | "PS256" => {
+            let mut signer = Signer::new(MessageDigest::sha256(), key)?;
+            if alg == "PS256" {
+                signer.set_rsa_padding(Padding::PKCS1_PSS)?;
+                signer.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)?;
+            }
+            signer.update(input)?;
+            Ok(signer.sign_to_vec()?)
+        }
+        "EdDSA" => {
+            let mut signer = Signer::new_without_digest(key)?;
+            Ok(signer.sign_oneshot_to_vec(input)?)
+        }
+        _ => Err(JwtError::UnsupportedAlgorithm(String::from(alg))),
+    }
+}
+
+
+/// Checks a raw JWS signature of `input`.
+pub fn verify_bytes<T: HasPublic>(
+    key: &PKey<T>,
+    alg: &str,
+    input: &[u8],
+    signature: &[u8],
+) -> Result<bool, JwtError> {
+    if let Some((digest, coordinate_size)) = ecdsa_parameters(alg) {
+        let der = match ecdsa_raw_to_der(signature, coordinate_size) {
+            Ok(der) => der,
+            Err(_) => return Ok(false),
+        };
+        let mut verifier = Verifier::new(digest, key)?;
+        verifier.update(input)?;
+        return Ok(verifier.verify(&der)?);
+    }
+    match alg {
+        "RS256" | "PS256" => {
+            let mut verifier = Verifier::new(MessageDigest::sha256(), key)?;
+            if alg == "PS256" {
+                verifier.set_rsa_padding(Padding::PKCS1_PSS)?;
+                verifier.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)?;
+            }
+            verifier.update(input)?;
+            Ok(verifier.verify(signature)?)
+        }
+        "EdDSA" => {
+            let mut verifier = Verifier::new_without_digest(key)?;
+            Ok(verifier.verify_oneshot(signature, input)?)
+        }
+        _ => Err(JwtError::UnsupportedAlgorithm(String::from(alg))),
+    }
+}
+
+
+/// Signs `claims` with `key` and returns the compact serialized JWS.
+pub fn sign(claims: &Map<String, Value>, key: &SigningKey, alg: &str) -> Result<String, JwtError> {
+    if !key.key_type.algorithms().contains(&alg) {
+        return Err(JwtError::UnsupportedAlgorithm(String::from(alg)));
+    }
+
+    let header = json!({"typ": "JWT", "alg": alg, "kid": key.kid});
+    let signing_input = format!(
+        "{}.{}",
+        encode_segment(header.to_string().as_bytes()),
+        encode_segment(Value::Object(claims.clone()).to_string().as_bytes())
+    );
+    let signature = key.signer.sign(alg, signing_input.as_bytes())?;
+
+    Ok(format!("{}.{}", signing_input, encode_segment(&signature)))
+}
+
+
+/// Verifies the signature of a compact serialized JWS against the matching
+/// key of `keys` and returns its claims. Temporal claims like `exp` are not
+/// checked here.
+pub fn decode_verified(token: &str, keys: &KeySet) -> Result<Map<String, Value>, JwtError> {
+    let parts: Vec<&str> = token.trim().split('.').collect();
+    if parts.len() != 3 {
+        return Err(JwtError::Malformed("token must consist of three segments"));
+    }
+
+    let header = decode_json(parts[0])?;
+    let alg = match header.get("alg").and_then(|a| a.as_str()) {
+        Some(alg) => alg.to_string(),
+        None => return Err(JwtError::Malformed("token header has no alg")),
+    };
+    let kid = header.get("kid").and_then(|k| k.as_str());
+
+    let key = match keys.verification_key(kid, &alg) {
+        Some(key) => key,
+        None => return Err(JwtError::UnsupportedAlgorithm(alg)),
+    };
+
+    let signing_input = format!("{}.{}", parts[0], parts[1]);
+    let signature = decode_segment(parts[2])?;
+    if !verify_bytes(key.signer.public_key(), &alg, signing_input.as_bytes(), &signature)? {
+        return Err(JwtError::InvalidSignature);
+    }
+
+    decode_json(parts[1])
+}
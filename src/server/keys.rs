@@ -0,0 +1,648 @@
+use {base64, openssl, time};
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::ec::{Asn1Flag, EcGroup, EcKey};
+use openssl::nid::Nid;
+use openssl::pkey::{HasPublic, Id, PKey, Private};
+use openssl::rsa::Rsa;
+use serde_json::Value;
+use server::signer::{FileSigner, Signer};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::thread;
+use std::time::Duration;
+use store::{Client, SigningKeyRecord, Store};
+use store::error::StoreError;
+
+/// Algorithms id tokens can be signed with.
+pub static SUPPORTED_ALGORITHMS: &[&str] = &["ES256", "ES384", "RS256", "PS256", "EdDSA"];
+
+#[derive(Debug)]
+pub enum KeyError {
+    IoError(io::Error),
+    OpensslError(openssl::error::ErrorStack),
+    StoreError(StoreError),
+    UnsupportedKey,
+    KeyAlreadyActive,
+    UnsupportedAlgorithm(String),
+    TokenError(String),
+}
+
+impl From<io::Error> for KeyError {
+    fn from(err: io::Error) -> KeyError {
+        KeyError::IoError(err)
+    }
+}
+
+impl From<openssl::error::ErrorStack> for KeyError {
+    fn from(err: openssl::error::ErrorStack) -> KeyError {
+        KeyError::OpensslError(err)
+    }
+}
+
+impl From<StoreError> for KeyError {
+    fn from(err: StoreError) -> KeyError {
+        KeyError::StoreError(err)
+    }
+}
+
+impl Error for KeyError {
+    fn description(&self) -> &str {
+        match *self {
+            KeyError::IoError(ref err) => err.description(),
+            KeyError::OpensslError(ref err) => err.description(),
+            KeyError::StoreError(ref err) => err.description(),
+            KeyError::UnsupportedKey => "unsupported key type",
+            KeyError::KeyAlreadyActive => "a key of this type is already active",
+            KeyError::UnsupportedAlgorithm(_) => "unsupported signing algorithm",
+            KeyError::TokenError(_) => "key token error",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            KeyError::IoError(ref err) => Some(err as &Error),
+            KeyError::OpensslError(ref err) => Some(err as &Error),
+            KeyError::StoreError(ref err) => Some(err as &Error),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KeyError::IoError(ref err) => fmt::Display::fmt(err, f),
+            KeyError::OpensslError(ref err) => fmt::Display::fmt(err, f),
+            KeyError::StoreError(ref err) => fmt::Display::fmt(err, f),
+            KeyError::UnsupportedAlgorithm(ref alg) => {
+                write!(f, "unsupported signing algorithm: {}", alg)
+            }
+            KeyError::TokenError(ref message) => write!(f, "key token error: {}", message),
+            _ => f.write_str(self.description()),
+        }
+    }
+}
+
+
+/// The kinds of keys the supported algorithms sign with.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KeyType {
+    P256,
+    P384,
+    Rsa,
+    Ed25519,
+}
+
+pub static KEY_TYPES: &[KeyType] = &[KeyType::P256, KeyType::P384, KeyType::Rsa, KeyType::Ed25519];
+
+impl KeyType {
+    /// The type of a loaded key, None for keys no supported algorithm uses.
+    pub fn of<T: HasPublic>(key: &PKey<T>) -> Option<KeyType> {
+        let id = key.id();
+        if id == Id::RSA {
+            return Some(KeyType::Rsa);
+        }
+        if id == Id::ED25519 {
+            return Some(KeyType::Ed25519);
+        }
+        if id != Id::EC {
+            return None;
+        }
+        match key.ec_key().ok().and_then(|k| k.group().curve_name()) {
+            Some(Nid::X9_62_PRIME256V1) => Some(KeyType::P256),
+            Some(Nid::SECP384R1) => Some(KeyType::P384),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            KeyType::P256 => "P-256",
+            KeyType::P384 => "P-384",
+            KeyType::Rsa => "RSA",
+            KeyType::Ed25519 => "Ed25519",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<KeyType> {
+        KEY_TYPES.iter().cloned().find(|key_type| key_type.name() == name)
+    }
+
+    pub fn for_algorithm(alg: &str) -> Option<KeyType> {
+        KEY_TYPES.iter().cloned().find(
+            |key_type| key_type.algorithms().contains(&alg),
+        )
+    }
+
+    /// Algorithms that sign with keys of this type, the preferred one first.
+    pub fn algorithms(&self) -> &'static [&'static str] {
+        match *self {
+            KeyType::P256 => &["ES256"],
+            KeyType::P384 => &["ES384"],
+            KeyType::Rsa => &["RS256", "PS256"],
+            KeyType::Ed25519 => &["EdDSA"],
+        }
+    }
+
+    pub fn generate(&self) -> Result<PKey<Private>, KeyError> {
+        let key = match *self {
+            KeyType::P256 => ec_key(Nid::X9_62_PRIME256V1)?,
+            KeyType::P384 => ec_key(Nid::SECP384R1)?,
+            KeyType::Rsa => PKey::from_rsa(Rsa::generate(2048)?)?,
+            KeyType::Ed25519 => PKey::generate_ed25519()?,
+        };
+        Ok(key)
+    }
+}
+
+
+/// Lifecycle of a signing key. Generated keys are published ahead of their
+/// use, only active keys sign and retired keys are published until the
+/// tokens they signed are no longer of interest.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KeyState {
+    Generated,
+    Active,
+    Retired,
+    Removed,
+}
+
+impl KeyState {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            KeyState::Generated => "generated",
+            KeyState::Active => "active",
+            KeyState::Retired => "retired",
+            KeyState::Removed => "removed",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<KeyState> {
+        [
+            KeyState::Generated,
+            KeyState::Active,
+            KeyState::Retired,
+            KeyState::Removed,
+        ].iter()
+            .cloned()
+            .find(|state| state.name() == name)
+    }
+}
+
+fn ec_key(curve: Nid) -> Result<PKey<Private>, openssl::error::ErrorStack> {
+    let mut group = EcGroup::from_curve_name(curve)?;
+    group.set_asn1_flag(Asn1Flag::NAMED_CURVE);
+    PKey::from_ec_key(EcKey::generate(&group)?)
+}
+
+fn encode(raw: &[u8]) -> String {
+    base64::encode_config(raw, base64::URL_SAFE_NO_PAD)
+}
+
+
+/// The key id of a public key, its JWK thumbprint (RFC 7638). serde_json
+/// keeps object members sorted, so the serialization is the canonical one.
+pub fn key_id<T: HasPublic>(key: &PKey<T>) -> Result<String, KeyError> {
+    let thumbprint = public_members(key)?.to_string();
+    Ok(encode(&openssl::sha::sha256(thumbprint.as_bytes())))
+}
+
+// the members of the public JWK that identify the key.
+fn public_members<T: HasPublic>(key: &PKey<T>) -> Result<Value, KeyError> {
+    let key_type = KeyType::of(key).ok_or(KeyError::UnsupportedKey)?;
+    let members = match key_type {
+        KeyType::P256 | KeyType::P384 => {
+            let (crv, size) = if key_type == KeyType::P256 {
+                ("P-256", 32)
+            } else {
+                ("P-384", 48)
+            };
+            let ec_key = key.ec_key()?;
+            let mut x = BigNum::new()?;
+            let mut y = BigNum::new()?;
+            let mut ctx = BigNumContext::new()?;
+            ec_key.public_key().affine_coordinates_gfp(
+                ec_key.group(),
+                &mut x,
+                &mut y,
+                &mut ctx,
+            )?;
+            json!({
+                "kty": "EC",
+                "crv": crv,
+                "x": encode(&x.to_vec_padded(size)?),
+                "y": encode(&y.to_vec_padded(size)?),
+            })
+        }
+        KeyType::Rsa => {
+            let rsa = key.rsa()?;
+            json!({
+                "kty": "RSA",
+                "n": encode(&rsa.n().to_vec()),
+                "e": encode(&rsa.e().to_vec()),
+            })
+        }
+        KeyType::Ed25519 => {
+            json!({
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "x": encode(&key.raw_public_key()?),
+            })
+        }
+    };
+    Ok(members)
+}
+
+
+/// A signer together with its key id.
+pub struct SigningKey {
+    pub kid: String,
+    pub key_type: KeyType,
+    pub state: KeyState,
+    pub signer: Box<Signer>,
+}
+
+impl SigningKey {
+    pub fn new(signer: Box<Signer>) -> Result<SigningKey, KeyError> {
+        let key_type = KeyType::of(signer.public_key()).ok_or(
+            KeyError::UnsupportedKey,
+        )?;
+        Ok(SigningKey {
+            kid: key_id(signer.public_key())?,
+            key_type: key_type,
+            state: KeyState::Active,
+            signer: signer,
+        })
+    }
+
+    /// A key held in memory.
+    pub fn from_private_key(key: PKey<Private>) -> Result<SigningKey, KeyError> {
+        SigningKey::new(Box::new(FileSigner::new(key)?))
+    }
+
+    /// The public key as JWK for the jwks endpoint.
+    pub fn jwk(&self) -> Result<Value, KeyError> {
+        let mut jwk = public_members(self.signer.public_key())?;
+        jwk["kid"] = json!(self.kid);
+        jwk["use"] = json!("sig");
+        // rsa keys are used with more than one algorithm
+        let algorithms = self.key_type.algorithms();
+        if algorithms.len() == 1 {
+            jwk["alg"] = json!(algorithms[0]);
+        }
+        Ok(jwk)
+    }
+}
+
+
+/// The published signing keys of this provider.
+pub struct KeySet {
+    keys: Vec<SigningKey>,
+    default_algorithm: String,
+}
+
+impl KeySet {
+    pub fn new(keys: Vec<SigningKey>, default_algorithm: &str) -> Result<KeySet, KeyError> {
+        let key_set = KeySet {
+            keys: keys,
+            default_algorithm: String::from(default_algorithm),
+        };
+        if key_set.signing_key(default_algorithm).is_none() {
+            return Err(KeyError::UnsupportedAlgorithm(String::from(default_algorithm)));
+        }
+        Ok(key_set)
+    }
+
+    /// Generates an active key of every type without persisting them.
+    pub fn generate(default_algorithm: &str) -> Result<KeySet, KeyError> {
+        let mut keys = Vec::new();
+        for key_type in KEY_TYPES {
+            keys.push(SigningKey::from_private_key(key_type.generate()?)?);
+        }
+        KeySet::new(keys, default_algorithm)
+    }
+
+    /// Loads the keys that are not removed, their private keys are read from `dir`.
+    pub fn load(store: &Store, dir: &Path, default_algorithm: &str) -> Result<KeySet, KeyError> {
+        let mut keys = Vec::new();
+        for record in store.get_signing_keys()? {
+            let state = KeyState::from_name(&record.state).ok_or(KeyError::UnsupportedKey)?;
+            if state == KeyState::Removed {
+                continue;
+            }
+            let signer = FileSigner::load(&key_path(dir, &record.kid))?;
+            let mut key = SigningKey::new(Box::new(signer))?;
+            if key.kid != record.kid || key.key_type.name() != record.key_type {
+                return Err(KeyError::UnsupportedKey);
+            }
+            key.state = state;
+            keys.push(key);
+        }
+        KeySet::new(keys, default_algorithm)
+    }
+
+    pub fn default_algorithm(&self) -> &str {
+        &self.default_algorithm
+    }
+
+    /// The active key signing with `alg`, None if there is no key for it.
+    pub fn signing_key(&self, alg: &str) -> Option<&SigningKey> {
+        let key_type = KeyType::for_algorithm(alg)?;
+        self.keys.iter().find(|key| {
+            key.key_type == key_type && key.state == KeyState::Active
+        })
+    }
+
+    /// The key that checks a token, chosen by its key id if present.
+    pub fn verification_key(&self, kid: Option<&str>, alg: &str) -> Option<&SigningKey> {
+        let key = match kid {
+            Some(kid) => self.keys.iter().find(|key| key.kid == kid)?,
+            None => self.signing_key(alg)?,
+        };
+        if key.key_type.algorithms().contains(&alg) {
+            Some(key)
+        } else {
+            None
+        }
+    }
+
+    /// The algorithm id tokens for `client` are signed with.
+    pub fn algorithm_for<'a>(&'a self, client: &'a Client) -> &'a str {
+        match client.id_token_signed_response_alg {
+            Some(ref alg) => alg,
+            None => &self.default_algorithm,
+        }
+    }
+
+    /// Algorithms there is an active key for.
+    pub fn algorithms(&self) -> Vec<&'static str> {
+        SUPPORTED_ALGORITHMS
+            .iter()
+            .cloned()
+            .filter(|alg| self.signing_key(alg).is_some())
+            .collect()
+    }
+
+    /// The public keys as JWK set.
+    pub fn jwks(&self) -> Result<Value, KeyError> {
+        let mut jwks = Vec::new();
+        for key in &self.keys {
+            jwks.push(key.jwk()?);
+        }
+        Ok(json!({ "keys": jwks }))
+    }
+}
+
+
+fn key_path(dir: &Path, kid: &str) -> PathBuf {
+    dir.join(format!("{}.pem", kid))
+}
+
+// saves the private key to `dir`, the returned record is not stored yet.
+fn write_key(
+    dir: &Path,
+    signer: &FileSigner,
+    state: KeyState,
+    now: i64,
+) -> Result<SigningKeyRecord, KeyError> {
+    let key_type = KeyType::of(signer.public_key()).ok_or(
+        KeyError::UnsupportedKey,
+    )?;
+    let kid = key_id(signer.public_key())?;
+    fs::create_dir_all(dir)?;
+    signer.save(&key_path(dir, &kid))?;
+    Ok(SigningKeyRecord {
+        kid: kid,
+        key_type: String::from(key_type.name()),
+        state: String::from(state.name()),
+        created: now,
+        state_changed: now,
+    })
+}
+
+// saves the private keys of `signers` to `dir` and hands their records to
+// `store_records`. The files are removed again if either fails, the store
+// never refers to a missing key and no keys are left it does not know of.
+fn save_keys<F>(
+    dir: &Path,
+    signers: &[(&FileSigner, KeyState)],
+    now: i64,
+    store_records: F,
+) -> Result<(), KeyError>
+where
+    F: FnOnce(&[SigningKeyRecord]) -> Result<(), StoreError>,
+{
+    let mut records = Vec::new();
+    let mut result = Ok(());
+    for &(signer, state) in signers {
+        match write_key(dir, signer, state, now) {
+            Ok(record) => records.push(record),
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    if result.is_ok() {
+        result = store_records(&records).map_err(KeyError::from);
+    }
+    if result.is_err() {
+        for record in &records {
+            let _ = fs::remove_file(key_path(dir, &record.kid));
+        }
+    }
+    result
+}
+
+fn create_key(
+    store: &Store,
+    dir: &Path,
+    signer: &FileSigner,
+    state: KeyState,
+    now: i64,
+) -> Result<(), KeyError> {
+    save_keys(dir, &[(signer, state)], now, |records| {
+        store.save_signing_key(&records[0])
+    })
+}
+
+fn records_of_type(store: &Store, key_type: KeyType) -> Result<Vec<SigningKeyRecord>, KeyError> {
+    Ok(
+        store
+            .get_signing_keys()?
+            .into_iter()
+            .filter(|record| record.key_type == key_type.name())
+            .collect(),
+    )
+}
+
+fn in_state(records: &[SigningKeyRecord], state: KeyState) -> Option<&SigningKeyRecord> {
+    records.iter().find(|record| record.state == state.name())
+}
+
+
+/// Advances the keys of one type by one step: the generated key becomes
+/// active, the active key is retired and a new key is generated for the next
+/// rotation. Keys retired for at least `retention` seconds are removed
+/// together with their private key. The store sees all changes at once,
+/// a failed rotation leaves the keys as they were.
+pub fn rotate(
+    store: &Store,
+    dir: &Path,
+    key_type: KeyType,
+    retention: i64,
+    now: i64,
+) -> Result<(), KeyError> {
+    let records = records_of_type(store, key_type)?;
+    let mut state_changes = Vec::new();
+    let mut removed = Vec::new();
+    for record in &records {
+        if record.state == KeyState::Active.name() {
+            state_changes.push((record.kid.clone(), String::from(KeyState::Retired.name())));
+        } else if record.state == KeyState::Retired.name() &&
+                   now - record.state_changed >= retention
+        {
+            state_changes.push((record.kid.clone(), String::from(KeyState::Removed.name())));
+            removed.push(key_path(dir, &record.kid));
+        }
+    }
+
+    let first = match in_state(&records, KeyState::Generated) {
+        Some(record) => {
+            state_changes.push((record.kid.clone(), String::from(KeyState::Active.name())));
+            None
+        }
+        // nothing was published ahead, e.g. on the very first start
+        None => Some(FileSigner::new(key_type.generate()?)?),
+    };
+    let next = FileSigner::new(key_type.generate()?)?;
+    let mut signers = Vec::new();
+    if let Some(ref signer) = first {
+        signers.push((signer, KeyState::Active));
+    }
+    signers.push((&next, KeyState::Generated));
+    save_keys(dir, &signers, now, |new_keys| {
+        store.rotate_signing_keys(&state_changes, new_keys, now)
+    })?;
+
+    // private keys go once the store no longer refers to them
+    for path in removed {
+        if let Err(e) = fs::remove_file(path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                return Err(KeyError::from(e));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Rotates the key types whose active key is older than `period` or that
+/// have no active key at all. Returns whether anything changed.
+pub fn rotate_due(store: &Store, dir: &Path, period: i64, now: i64) -> Result<bool, KeyError> {
+    let mut changed = false;
+    for key_type in KEY_TYPES {
+        let records = records_of_type(store, *key_type)?;
+        let due = match in_state(&records, KeyState::Active) {
+            Some(active) => now - active.state_changed >= period,
+            None => true,
+        };
+        if due {
+            rotate(store, dir, *key_type, period, now)?;
+            changed = true;
+        } else if in_state(&records, KeyState::Generated).is_none() {
+            let signer = FileSigner::new(key_type.generate()?)?;
+            create_key(store, dir, &signer, KeyState::Generated, now)?;
+            changed = true;
+        }
+    }
+    Ok(changed)
+}
+
+/// Moves a private key kept outside of the key directory, like the single
+/// key of former versions, under lifecycle management as active key.
+pub fn import(store: &Store, dir: &Path, path: &Path, now: i64) -> Result<(), KeyError> {
+    let signer = FileSigner::load(path)?;
+    let key_type = KeyType::of(signer.public_key()).ok_or(
+        KeyError::UnsupportedKey,
+    )?;
+    let records = records_of_type(store, key_type)?;
+    if in_state(&records, KeyState::Active).is_some() {
+        return Err(KeyError::KeyAlreadyActive);
+    }
+    create_key(store, dir, &signer, KeyState::Active, now)?;
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+
+/// Where signing keys are kept and how long each key signs.
+pub struct RotationPolicy {
+    pub directory: PathBuf,
+    /// seconds a key stays active, retired keys are published as long.
+    pub period: i64,
+}
+
+// how often the key ring looks for due or external rotations, in seconds.
+const REFRESH_INTERVAL: u64 = 60;
+
+/// The keys in use, kept up to date with their lifecycle.
+pub struct KeyRing {
+    keys: RwLock<KeySet>,
+    rotation: Option<RotationPolicy>,
+}
+
+impl KeyRing {
+    /// Keys that are never rotated.
+    pub fn fixed(keys: KeySet) -> KeyRing {
+        KeyRing {
+            keys: RwLock::new(keys),
+            rotation: None,
+        }
+    }
+
+    pub fn managed(
+        store: &Store,
+        policy: RotationPolicy,
+        default_algorithm: &str,
+        now: i64,
+    ) -> Result<KeyRing, KeyError> {
+        rotate_due(store, &policy.directory, policy.period, now)?;
+        let keys = KeySet::load(store, &policy.directory, default_algorithm)?;
+        Ok(KeyRing {
+            keys: RwLock::new(keys),
+            rotation: Some(policy),
+        })
+    }
+
+    /// The keys to sign and verify with.
+    pub fn current(&self) -> RwLockReadGuard<KeySet> {
+        self.keys.read().unwrap()
+    }
+
+    /// Rotates due keys and picks up rotations done from the command line.
+    /// Fixed keys stay as they are.
+    pub fn refresh(&self, store: &Store, now: i64) -> Result<(), KeyError> {
+        if let Some(ref policy) = self.rotation {
+            rotate_due(store, &policy.directory, policy.period, now)?;
+            let default_algorithm = String::from(self.current().default_algorithm());
+            let keys = KeySet::load(store, &policy.directory, &default_algorithm)?;
+            *self.keys.write().unwrap() = keys;
+        }
+        Ok(())
+    }
+}
+
+/// Refreshes `ring` once per refresh interval in a thread of its own, so
+/// that requests never wait for a rotation.
+pub fn refresh_in_background(ring: Arc<KeyRing>, store: Arc<Store + Send + Sync>) {
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(REFRESH_INTERVAL));
+        if let Err(e) = ring.refresh(&*store, time::get_time().sec) {
+            eprintln!("could not refresh signing keys: {}", e);
+        }
+    });
+}
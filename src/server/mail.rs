@@ -0,0 +1,184 @@
+//! Outgoing mail, like password reset links. Mail is handed to an SMTP
+//! relay or dropped into a maildir, where tests and offline setups can
+//! pick it up.
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::time::Duration;
+use time;
+use uuid::Uuid;
+
+/// A plain text mail to a single recipient.
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub enum MailError {
+    IoError(io::Error),
+    /// the SMTP server refused a command with this reply.
+    Rejected(String),
+}
+
+impl From<io::Error> for MailError {
+    fn from(err: io::Error) -> MailError {
+        MailError::IoError(err)
+    }
+}
+
+impl Error for MailError {
+    fn description(&self) -> &str {
+        match *self {
+            MailError::IoError(ref err) => err.description(),
+            MailError::Rejected(_) => "mail rejected by the smtp server",
+        }
+    }
+}
+
+impl fmt::Display for MailError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MailError::IoError(ref err) => write!(f, "could not send mail: {}", err),
+            MailError::Rejected(ref reply) => write!(f, "mail rejected: {}", reply),
+        }
+    }
+}
+
+/// Delivers mail on behalf of the server.
+pub trait Mailer: Send + Sync {
+    fn send(&self, mail: &Mail) -> Result<(), MailError>;
+}
+
+
+// header values must not start new header lines.
+fn header_value(value: &str) -> String {
+    value.replace(|c: char| c == '\r' || c == '\n', " ")
+}
+
+impl Mail {
+    /// The RFC 5322 message with CRLF line endings.
+    pub fn message(&self, from: &str) -> String {
+        let domain = from.rsplit('@').next().unwrap_or("localhost");
+        let mut message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMessage-ID: <{}@{}>\r\n\
+             MIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\n\
+             Content-Transfer-Encoding: 8bit\r\n\r\n",
+            header_value(from),
+            header_value(&self.to),
+            header_value(&self.subject),
+            time::now_utc().rfc822z(),
+            Uuid::new_v4().simple(),
+            header_value(domain)
+        );
+        for line in self.body.lines() {
+            message.push_str(line);
+            message.push_str("\r\n");
+        }
+        message
+    }
+}
+
+
+/// Relays mail through an SMTP server without authentication or TLS,
+/// meant for a relay on the same host or network.
+pub struct SmtpMailer {
+    /// host and port, like `localhost:25`.
+    pub server: String,
+    pub from: String,
+}
+
+impl SmtpMailer {
+    // the reply to the last command, an error unless it has `expected` code.
+    fn expect(&self, reader: &mut BufRead, expected: &str) -> Result<(), MailError> {
+        let mut reply = String::new();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(MailError::Rejected(String::from("connection closed")));
+            }
+            reply.push_str(&line);
+            // continuation lines have a dash after the code
+            if line.len() < 4 || line.as_bytes()[3] != b'-' {
+                break;
+            }
+        }
+        if reply.starts_with(expected) {
+            Ok(())
+        } else {
+            Err(MailError::Rejected(String::from(reply.trim())))
+        }
+    }
+
+    fn command(
+        &self,
+        stream: &mut TcpStream,
+        reader: &mut BufRead,
+        command: &str,
+        expected: &str,
+    ) -> Result<(), MailError> {
+        stream.write_all(command.as_bytes())?;
+        stream.write_all(b"\r\n")?;
+        self.expect(reader, expected)
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, mail: &Mail) -> Result<(), MailError> {
+        let mut stream = TcpStream::connect(&self.server[..])?;
+        stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+        stream.set_write_timeout(Some(Duration::from_secs(30)))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let domain = self.from.rsplit('@').next().unwrap_or("localhost");
+
+        self.expect(&mut reader, "220")?;
+        self.command(&mut stream, &mut reader, &format!("EHLO {}", domain), "250")?;
+        let from = format!("MAIL FROM:<{}>", header_value(&self.from));
+        self.command(&mut stream, &mut reader, &from, "250")?;
+        // 251 is fine as well, the server forwards the mail
+        let to = format!("RCPT TO:<{}>", header_value(&mail.to));
+        self.command(&mut stream, &mut reader, &to, "25")?;
+        self.command(&mut stream, &mut reader, "DATA", "354")?;
+        // lines starting with a dot are escaped, a lone dot ends the data
+        let mut data = String::new();
+        for line in mail.message(&self.from).lines() {
+            if line.starts_with('.') {
+                data.push('.');
+            }
+            data.push_str(line);
+            data.push_str("\r\n");
+        }
+        data.push_str(".\r\n");
+        stream.write_all(data.as_bytes())?;
+        self.expect(&mut reader, "250")?;
+        self.command(&mut stream, &mut reader, "QUIT", "221")
+    }
+}
+
+
+/// Drops mail into a maildir, delivery is left to whoever reads it.
+pub struct MaildirMailer {
+    pub directory: PathBuf,
+    pub from: String,
+}
+
+impl Mailer for MaildirMailer {
+    fn send(&self, mail: &Mail) -> Result<(), MailError> {
+        for sub_directory in &["tmp", "new", "cur"] {
+            fs::create_dir_all(self.directory.join(sub_directory))?;
+        }
+        // written to tmp first, so that readers never see partial messages
+        let name = format!("{}.{}.openid", time::get_time().sec, Uuid::new_v4().simple());
+        let tmp_path = self.directory.join("tmp").join(&name);
+        fs::File::create(&tmp_path)?.write_all(
+            mail.message(&self.from).as_bytes(),
+        )?;
+        fs::rename(&tmp_path, self.directory.join("new").join(&name))?;
+        Ok(())
+    }
+}
@@ -1,38 +1,124 @@
 
 use store::Store;
-use std::sync::RwLock;
+use std::sync::{Arc, RwLock};
 use std::collections::HashMap;
-use rustwt;
 use rocket::{self, config};
-use openssl;
 
 pub mod routes;
+pub mod jwt;
+pub mod jwe;
+pub mod keys;
+pub mod mail;
+pub mod signer;
+pub mod acr;
+pub mod claims;
+pub mod cookie_secret;
+pub mod grants;
+pub mod invitation;
+pub mod password_reset;
+pub mod registration;
+pub mod security;
+pub mod session;
+pub mod subject;
+pub mod throttle;
+pub mod webauthn;
+pub mod templates;
 mod authentication_request;
 
+use self::cookie_secret::{CookieSecret, PreviousSecret};
+use self::grants::{AccessGrant, CodeGrant, Lifetimes};
+use self::mail::Mailer;
+use self::registration::RegistrationPolicy;
+use self::security::{SecurityHeaders, SecurityPolicy};
+use self::session::{Session, SessionPolicy};
+use self::throttle::Throttle;
+
 pub struct Config {
     pub issuer: Option<String>,
     pub config_dir_path: String,
-    pub store: Box<Store + Send + Sync>,
-    pub sessions: RwLock<HashMap<String, String>>,
-    pub codes: RwLock<HashMap<String, rustwt::id_token::IDToken>>,
-    pub token_duration: u64,
+    pub store: Arc<Store + Send + Sync>,
+    pub sessions: RwLock<HashMap<String, Session>>,
+    pub session_policy: SessionPolicy,
+    pub throttle: Throttle,
+    pub codes: RwLock<HashMap<String, CodeGrant>>,
+    pub access_tokens: RwLock<HashMap<String, AccessGrant>>,
+    pub refresh_tokens: RwLock<HashMap<String, AccessGrant>>,
+    /// defaults, clients may have their own.
+    pub lifetimes: Lifetimes,
     pub salt: String,
-    pub key_pair: openssl::pkey::PKey,
+    /// pairwise subjects are derived with it, see server::subject.
+    pub pairwise_secret: String,
+    /// shared with the thread that rotates them, see keys::refresh_in_background.
+    pub keys: Arc<keys::KeyRing>,
+    /// sends password reset links, they are not offered without one.
+    /// shared with the threads that send mails in the background.
+    pub mailer: Option<Arc<Mailer>>,
+    /// users may sign up themselves if set, it takes a mailer as well.
+    pub registration: Option<RegistrationPolicy>,
+    /// cookie attributes and whether HSTS is sent.
+    pub security: SecurityPolicy,
 }
 
-pub fn run(con: Config, listen: &str, port: u16) {
+/// Serves with private cookies encrypted with the current `cookie_secret`,
+/// `previous_secret` still opens those of the one it replaced.
+pub fn run(
+    con: Config,
+    listen: &str,
+    port: u16,
+    cookie_secret: &CookieSecret,
+    previous_secret: PreviousSecret,
+) {
 
     let rocket_config = config::Config::build(config::Environment::Production)
         .address(listen)
         .port(port)
+        .secret_key(cookie_secret.current.clone())
         .finalize()
         .expect("could not create rocket config");
 
+    let security_headers = SecurityHeaders::new(&con.security);
     rocket::custom(rocket_config, false)
+        .attach(previous_secret)
+        .attach(security_headers)
         .manage(con)
         .mount(
             "/",
-            routes![routes::login, routes::authorize, routes::public_key],
+            routes![
+                routes::stylesheet,
+                routes::webauthn_script,
+                routes::login,
+                routes::login_totp,
+                routes::login_recovery,
+                routes::forgot_password_form,
+                routes::forgot_password,
+                routes::reset_password_form,
+                routes::reset_password,
+                routes::registration_form,
+                routes::register,
+                routes::verify_email,
+                routes::invitation_form,
+                routes::accept_invitation,
+                routes::login_webauthn,
+                routes::assertion_options,
+                routes::security_keys,
+                routes::registration_options,
+                routes::register_security_key,
+                routes::account,
+                routes::change_password,
+                routes::remove_totp,
+                routes::remove_security_key,
+                routes::regenerate_recovery_codes,
+                routes::end_session,
+                routes::revoke_consent,
+                routes::authorize,
+                routes::public_key,
+                routes::jwks,
+                routes::token,
+                routes::userinfo,
+                routes::userinfo_post,
+                routes::discovery,
+                routes::consent,
+            ],
         )
         .launch();
 }
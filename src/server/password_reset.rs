@@ -0,0 +1,73 @@
+//! Tokens of the links that reset a password. They are JWTs signed with
+//! the server keys, expire after an hour and carry a fingerprint of the
+//! password they replace, so they stop working once it is changed.
+
+use openssl;
+use serde_json::{Map, Value};
+use server::jwt::{self, JwtError};
+use server::keys::KeySet;
+use store::User;
+use utils::hash_secret;
+
+/// Seconds a reset link is valid.
+pub const LIFETIME: i64 = 60 * 60;
+// keeps id tokens and other JWTs of the server from passing as reset tokens.
+const PURPOSE: &str = "password_reset";
+
+/// The claims of a valid reset token.
+pub struct ResetToken {
+    pub user_id: String,
+    fingerprint: String,
+}
+
+// a digest of the password hash, changes with the password.
+fn fingerprint(user: &User) -> String {
+    hash_secret(user.password.as_ref().map(|hash| &hash[..]).unwrap_or(""))
+}
+
+/// A token that resets the password of `user` until `now` + LIFETIME.
+pub fn issue(keys: &KeySet, user: &User, iss: &str, now: i64) -> Result<String, JwtError> {
+    let mut claims = Map::new();
+    claims.insert(String::from("iss"), Value::from(iss));
+    claims.insert(String::from("sub"), Value::from(user.id.clone()));
+    claims.insert(String::from("iat"), Value::from(now));
+    claims.insert(String::from("exp"), Value::from(now + LIFETIME));
+    claims.insert(String::from("purpose"), Value::from(PURPOSE));
+    claims.insert(String::from("pwd"), Value::from(fingerprint(user)));
+    let alg = keys.default_algorithm();
+    let key = keys.signing_key(alg).ok_or(
+        JwtError::UnsupportedAlgorithm(String::from(alg)),
+    )?;
+    jwt::sign(&claims, key, alg)
+}
+
+/// The reset `token` asks for, if it is signed by the server and not expired.
+pub fn verify(token: &str, keys: &KeySet, now: i64) -> Result<ResetToken, JwtError> {
+    let claims = jwt::decode_verified(token, keys)?;
+    let text = |name: &str| claims.get(name).and_then(|value| value.as_str());
+    if text("purpose") != Some(PURPOSE) {
+        return Err(JwtError::Malformed("not a password reset token"));
+    }
+    if claims.get("exp").and_then(|exp| exp.as_i64()).map_or(true, |exp| exp <= now) {
+        return Err(JwtError::Malformed("password reset token expired"));
+    }
+    match (text("sub"), text("pwd")) {
+        (Some(sub), Some(pwd)) => {
+            Ok(ResetToken {
+                user_id: String::from(sub),
+                fingerprint: String::from(pwd),
+            })
+        }
+        _ => Err(JwtError::Malformed("incomplete password reset token")),
+    }
+}
+
+impl ResetToken {
+    /// Whether the token is for `user` and the password it was issued for
+    /// is still set, true only until the first reset.
+    pub fn is_unused_for(&self, user: &User) -> bool {
+        let current = fingerprint(user);
+        self.user_id == user.id && current.len() == self.fingerprint.len() &&
+            openssl::memcmp::eq(current.as_bytes(), self.fingerprint.as_bytes())
+    }
+}
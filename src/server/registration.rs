@@ -0,0 +1,76 @@
+//! Self-service sign up. New users verify their email with a mailed link
+//! before they can log in, if required they wait for an admin to approve
+//! them as well. The link carries a JWT signed with the server keys.
+
+use serde_json::{Map, Value};
+use server::jwt::{self, JwtError};
+use server::keys::KeySet;
+use store::User;
+
+/// Seconds a verification link is valid.
+pub const VERIFICATION_LIFETIME: i64 = 24 * 60 * 60;
+// keeps other JWTs of the server from passing as verification tokens.
+const PURPOSE: &str = "email_verification";
+
+/// Who may register.
+pub struct RegistrationPolicy {
+    /// domains addresses have to belong to, any if empty.
+    pub allowed_domains: Vec<String>,
+    /// verified users still wait for `users approve`.
+    pub approval_required: bool,
+}
+
+impl RegistrationPolicy {
+    pub fn allows(&self, email: &str) -> bool {
+        let domain = match email.rfind('@') {
+            Some(at) if at > 0 && at + 1 < email.len() => &email[at + 1..],
+            _ => return false,
+        };
+        self.allowed_domains.is_empty() ||
+            self.allowed_domains.iter().any(|allowed| {
+                allowed.eq_ignore_ascii_case(domain)
+            })
+    }
+}
+
+/// A token that verifies the current email of `user`.
+pub fn issue_verification(
+    keys: &KeySet,
+    user: &User,
+    iss: &str,
+    now: i64,
+) -> Result<String, JwtError> {
+    let mut claims = Map::new();
+    claims.insert(String::from("iss"), Value::from(iss));
+    claims.insert(String::from("sub"), Value::from(user.id.clone()));
+    claims.insert(String::from("email"), Value::from(user.email.clone()));
+    claims.insert(String::from("iat"), Value::from(now));
+    claims.insert(String::from("exp"), Value::from(now + VERIFICATION_LIFETIME));
+    claims.insert(String::from("purpose"), Value::from(PURPOSE));
+    let alg = keys.default_algorithm();
+    let key = keys.signing_key(alg).ok_or(
+        JwtError::UnsupportedAlgorithm(String::from(alg)),
+    )?;
+    jwt::sign(&claims, key, alg)
+}
+
+/// The id of the user and the address a valid, unexpired `token` verifies.
+/// The address has to be compared with the current one of the user.
+pub fn verify_verification(
+    token: &str,
+    keys: &KeySet,
+    now: i64,
+) -> Result<(String, String), JwtError> {
+    let claims = jwt::decode_verified(token, keys)?;
+    let text = |name: &str| claims.get(name).and_then(|value| value.as_str());
+    if text("purpose") != Some(PURPOSE) {
+        return Err(JwtError::Malformed("not an email verification token"));
+    }
+    if claims.get("exp").and_then(|exp| exp.as_i64()).map_or(true, |exp| exp <= now) {
+        return Err(JwtError::Malformed("email verification token expired"));
+    }
+    match (text("sub"), text("email")) {
+        (Some(sub), Some(email)) => Ok((String::from(sub), String::from(email))),
+        _ => Err(JwtError::Malformed("incomplete email verification token")),
+    }
+}
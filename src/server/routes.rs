@@ -1,31 +1,42 @@
-use {rocket, openssl, serde_json};
+use {rocket, openssl, serde_json, time, url};
 use server::authentication_request::{self, OidcErr};
-use std::fs::File;
-use std::io::Read;
+use server::{claims, invitation, jwe, jwt, password_reset, registration, subject, templates,
+             throttle, webauthn};
+use server::mail::Mail;
+use server::grants::{AccessGrant, CodeGrant, Lifetimes};
+use server::acr::AuthLevel;
+use server::session::{self, Authentication, PendingLogin, Session};
 
 
 use uuid::Uuid;
-use rustwt::Algorithm;
-use rustwt::id_token::IDToken;
 use rocket::{State, Response};
 use rocket::request::Form;
-use rocket::http::{Cookie, Cookies, Status};
+use rocket::http::{ContentType, Cookie, Cookies, Status};
+use std::fmt;
 use std::io::Cursor;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
 use std::ops::Deref;
 use rocket::request::{self, Request, FromRequest};
 use rocket::Outcome;
 use server::Config;
+use store::{Client, Consent, Invitation, Profile, SignIn, Totp, User, WebAuthnCredential};
+use store::error::StoreError;
+use utils::{escape_html, verify_secret};
+use utils::password::{self, Verification};
+use utils::{recovery_codes, totp};
 use base64;
+use qrcode::QrCode;
+use qrcode::render::svg;
 
 
 
-static FORM_TEMPLATE: &'static str = include_str!("form.html");
-
 #[derive(FromForm)]
 struct Login {
     email: String,
     password: String,
-    state: String,
+    csrf_token: String,
 }
 
 
@@ -35,9 +46,45 @@ struct RequestedHost(String);
 impl<'a, 'r> FromRequest<'a, 'r> for RequestedHost {
     type Error = ();
     fn from_request(request: &'a Request<'r>) -> request::Outcome<RequestedHost, ()> {
-        Outcome::Success(RequestedHost(
-            request.headers().get("Host").next().unwrap().to_string(),
-        ))
+        match request.headers().get_one("Host") {
+            Some(host) => Outcome::Success(RequestedHost(host.to_string())),
+            None => Outcome::Forward(()),
+        }
+    }
+}
+
+
+/// The Origin and Referer headers of a request, logins check them
+/// against the issuer.
+struct RequestOrigin {
+    origin: Option<String>,
+    referer: Option<String>,
+}
+
+
+impl<'a, 'r> FromRequest<'a, 'r> for RequestOrigin {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<RequestOrigin, ()> {
+        let header = |name: &str| request.headers().get_one(name).map(String::from);
+        Outcome::Success(RequestOrigin {
+            origin: header("Origin"),
+            referer: header("Referer"),
+        })
+    }
+}
+
+
+// refuses forms posted from pages of other sites.
+fn check_origin<'r>(config: &Config, origin: &RequestOrigin, iss: &str) -> Result<(), Response<'r>> {
+    let same_origin = config.security.is_same_origin(
+        &base_url(iss),
+        origin.origin.as_ref().map(|origin| &origin[..]),
+        origin.referer.as_ref().map(|referer| &referer[..]),
+    );
+    if same_origin {
+        Ok(())
+    } else {
+        Err(Response::build().raw_status(403, "cross-origin request").finalize())
     }
 }
 
@@ -46,159 +93,3181 @@ impl<'a, 'r> FromRequest<'a, 'r> for RequestedHost {
 pub fn authorize<'r>(
     mut authentication_request: authentication_request::AuthenticationRequest,
     state: State<Config>,
+    host: Option<RequestedHost>,
     mut cookies: Cookies,
 ) -> Response<'r> {
     let config = state.deref();
-    let validation_result = authentication_request.validate(&config);
-
-    if validation_result.is_err() {
-        let message = match validation_result.err().unwrap() {
-            OidcErr::ClientErr(m) => String::from(m),
-            OidcErr::InternalErr(m) => m.to_string(),
-        };
-        println!("{}", message);
-        return Response::build().status(Status::BadRequest).finalize();
-    }
+    let client = match authentication_request.validate(&config) {
+        Ok(client) => client,
+        Err(e) => return reject(e),
+    };
 
-    let session_id = Uuid::new_v4().simple().to_string();
+    let hinted_subject = match authentication_request.hinted_subject(&config) {
+        Ok(subject) => subject,
+        Err(e) => return reject(e),
+    };
 
-    let mut sessions = config.sessions.write().unwrap();
-    sessions.insert(
-        session_id.clone(),
-        authentication_request.state.clone().unwrap(),
+    let client_state = authentication_request.state.clone();
+    let existing_session = cookies.get_private("session").map(
+        |cookie| String::from(cookie.value()),
     );
+    let now = time::get_time().sec;
+
+    // visitors keep their session id and csrf token, forms open in other
+    // tabs stay valid. Sessions are stored once the visitor logs in.
+    let (session_id, csrf_token, authentication) = {
+        let mut sessions = config.sessions.write().unwrap();
+        sessions.retain(|_, session| !session.is_expired(&config.session_policy, now));
+
+        let session_id = existing_session.unwrap_or_else(|| Uuid::new_v4().simple().to_string());
+        match sessions.get_mut(&session_id) {
+            Some(session) => {
+                session.last_seen = now;
+                let csrf_token = session.csrf_token.clone();
+                (session_id, csrf_token, session.authentication.clone())
+            }
+            None => {
+                let csrf_token = session::csrf_token(&session_id);
+                (session_id, csrf_token, None)
+            }
+        }
+    };
+
+    if let (&Some(ref hinted), &Some(ref current)) = (&hinted_subject, &authentication) {
+        if hinted != &subject::subject_for(&client, &current.subject, &config.pairwise_secret) {
+            return error_redirect(
+                &authentication_request.redirect_uri,
+                "login_required",
+                client_state.as_ref(),
+            );
+        }
+    }
+
     let request_string = serde_json::to_string(&authentication_request).unwrap();
-    cookies.add_private(Cookie::new("auth-request", request_string));
-    cookies.add_private(Cookie::new("session", session_id));
-
-    rocket::Response::build()
-        .sized_body(Cursor::new(FORM_TEMPLATE.replace(
-            "{{CORS-TOKEN}}",
-            &authentication_request.state.unwrap(),
-        )))
+    // lax by default, the session has to survive the redirect from other sites.
+    cookies.add_private(config.security.cookie("auth-request", request_string));
+    cookies.add_private(config.security.cookie("session", session_id));
+
+    // an existing login is reused unless the request demands a new or stronger one.
+    let reusable = authentication.as_ref().map_or(false, |authentication| {
+        !authentication_request.prompts("login") &&
+            login_satisfies(&authentication_request, &client, authentication, now)
+    });
+
+    if reusable {
+        let iss = issuer(config, host);
+        return complete_authentication(
+            config,
+            &session_id,
+            &authentication_request,
+            authentication.as_ref().unwrap(), // safe unwrap
+            &csrf_token,
+            iss,
+        );
+    }
+
+    if authentication_request.prompts("none") {
+        return error_redirect(
+            &authentication_request.redirect_uri,
+            "login_required",
+            client_state.as_ref(),
+        );
+    }
+
+    let login_hint = authentication_request.login_hint.clone().unwrap_or_default();
+
+    templates::html_response(
+        templates::LOGIN,
+        &[
+            ("CORS-TOKEN", &csrf_token[..]),
+            ("LOGIN-HINT", &escape_html(&login_hint)[..]),
+            (
+                "PASSWORD-RESET-HIDDEN",
+                if config.mailer.is_some() { "" } else { "hidden" },
+            ),
+            (
+                "REGISTRATION-HIDDEN",
+                if registration_enabled(config) { "" } else { "hidden" },
+            ),
+        ],
+    )
+}
+
+
+// seconds until the remote address may try to authenticate again.
+fn address_retry_after(config: &Config, remote_addr: Option<&String>, now: i64) -> Option<i64> {
+    remote_addr.and_then(|address| {
+        config.throttle.retry_after(&throttle::address_key(address), now)
+    })
+}
+
+
+// counts a failure to authenticate against the remote address, if known.
+fn record_address_failure(
+    config: &Config,
+    remote_addr: Option<&String>,
+    now: i64,
+) -> Result<(), StoreError> {
+    match remote_addr {
+        Some(address) => {
+            config.throttle.record_failure(
+                &*config.store,
+                &throttle::address_key(address),
+                config.throttle.policy.address_threshold,
+                Some(address),
+                now,
+            )
+        }
+        None => Ok(()),
+    }
+}
+
+
+// seconds until the remote address may ask for another mail.
+fn mail_retry_after(config: &Config, remote_addr: Option<&String>, now: i64) -> Option<i64> {
+    remote_addr.and_then(|address| {
+        config.throttle.retry_after(&throttle::mail_key(address), now)
+    })
+}
+
+
+// counts a request for a mail against the remote address, if known. It
+// slows down flooding mailboxes without locking the address out of logins.
+fn record_mail_request(
+    config: &Config,
+    remote_addr: Option<&String>,
+    now: i64,
+) -> Result<(), StoreError> {
+    match remote_addr {
+        Some(address) => {
+            config.throttle.record_failure(
+                &*config.store,
+                &throttle::mail_key(address),
+                config.throttle.policy.address_threshold,
+                Some(address),
+                now,
+            )
+        }
+        None => Ok(()),
+    }
+}
+
+
+// sends `mail` in a thread of its own. Answers take as long whether there
+// was a mail to send or not, so they don't tell which addresses have accounts.
+fn send_in_background(mailer: &Arc<Mailer>, mail: Mail, failure: &'static str) {
+    let mailer = mailer.clone();
+    thread::spawn(move || if let Err(e) = mailer.send(&mail) {
+        log_failure(failure, &e);
+    });
+}
+
+
+// counts a failed login against the address and, if known, the account.
+fn record_login_failure(
+    config: &Config,
+    user_id: Option<&str>,
+    remote_addr: Option<&String>,
+    now: i64,
+) -> Result<(), StoreError> {
+    record_address_failure(config, remote_addr, now)?;
+    match user_id {
+        Some(user_id) => {
+            config.throttle.record_account_failure(
+                &*config.store,
+                user_id,
+                remote_addr.map(|address| &address[..]),
+                now,
+            )
+        }
+        None => Ok(()),
+    }
+}
+
+
+// the answer to a login with an unknown email, a wrong password or for a
+// locked account alike.
+fn invalid_credentials<'r>() -> Response<'r> {
+    Response::build()
+        .raw_status(404, "invalid email or password")
+        .finalize()
+}
+
+
+// reports a failure the visitor is not told about. Ids and addresses stay
+// out, they are kept in the store where needed.
+fn log_failure(what: &str, err: &fmt::Display) {
+    eprintln!("{}: {}", what, err);
+}
+
+
+fn too_many_attempts<'r>(retry_after: i64) -> Response<'r> {
+    Response::build()
+        .raw_status(429, "too many failed attempts")
+        .raw_header("Retry-After", retry_after.to_string())
+        .finalize()
+}
+
+
+fn reject<'r>(err: OidcErr) -> Response<'r> {
+    let message = match err {
+        OidcErr::ClientErr(m) => String::from(m),
+        OidcErr::InternalErr(m) => m.to_string(),
+    };
+    println!("{}", message);
+    Response::build().status(Status::BadRequest).finalize()
+}
+
+
+fn error_redirect<'r>(redirect_uri: &str, error: &str, state: Option<&String>) -> Response<'r> {
+    redirect_with(redirect_uri, &[("error", error)], state)
+}
+
+
+// redirects back to the client, the state it sent is echoed unchanged.
+fn redirect_with<'r>(
+    redirect_uri: &str,
+    params: &[(&str, &str)],
+    state: Option<&String>,
+) -> Response<'r> {
+    let mut location = match url::Url::parse(redirect_uri) {
+        Ok(location) => location,
+        Err(_) => return Response::build().status(Status::BadRequest).finalize(),
+    };
+    {
+        let mut query = location.query_pairs_mut();
+        query.extend_pairs(params);
+        if let Some(state) = state {
+            query.append_pair("state", state);
+        }
+    }
+    Response::build()
+        .raw_header("Location", location.into_string())
+        .raw_status(302, "Found")
+        .finalize()
+}
+
+
+fn issuer(config: &Config, host: Option<RequestedHost>) -> String {
+    match config.issuer {
+        Some(ref i) => i.clone(),
+        None => host.map(|h| h.0).unwrap_or_default(),
+    }
+}
+
+
+
+// the issuer identifier is used as base url of all endpoints.
+fn base_url(iss: &str) -> String {
+    let base = if iss.starts_with("https://") || iss.starts_with("http://") {
+        String::from(iss)
+    } else {
+        format!("https://{}", iss)
+    };
+    String::from(base.trim_right_matches('/'))
+}
+
+
+#[get("/static/style.css")]
+pub fn stylesheet<'r>() -> Response<'r> {
+    static_asset(ContentType::CSS, templates::STYLE)
+}
+
+
+#[get("/static/webauthn.js")]
+pub fn webauthn_script<'r>() -> Response<'r> {
+    static_asset(ContentType::JavaScript, templates::WEBAUTHN_SCRIPT)
+}
+
+
+// the pages link their stylesheet and script, the content security policy
+// does not allow them inline.
+fn static_asset<'r>(content_type: ContentType, body: &'static str) -> Response<'r> {
+    Response::build()
+        .header(content_type)
+        .raw_header("Cache-Control", "public, max-age=3600")
+        .sized_body(Cursor::new(body))
         .finalize()
 }
 
 
+#[get("/.well-known/openid-configuration")]
+pub fn discovery<'r>(state: State<Config>, host: Option<RequestedHost>) -> Response<'r> {
+    let iss = issuer(state.inner(), host);
+    let base = base_url(&iss);
+    let keys = state.keys.current();
+    let acr_values: Vec<&str> = [AuthLevel::Password, AuthLevel::MultiFactor]
+        .iter()
+        .map(|level| level.acr())
+        .collect();
+    json_response(
+        Status::Ok,
+        json!({
+            "issuer": iss,
+            "authorization_endpoint": format!("{}/authorize", base),
+            "token_endpoint": format!("{}/token", base),
+            "userinfo_endpoint": format!("{}/userinfo", base),
+            "jwks_uri": format!("{}/jwks", base),
+            "response_types_supported": ["code", "id_token"],
+            "grant_types_supported": ["authorization_code", "implicit", "refresh_token"],
+            "subject_types_supported": subject::SUBJECT_TYPES,
+            "id_token_signing_alg_values_supported": keys.algorithms(),
+            "id_token_encryption_alg_values_supported": jwe::SUPPORTED_ALGORITHMS,
+            "id_token_encryption_enc_values_supported": jwe::SUPPORTED_ENCRYPTIONS,
+            "userinfo_encryption_alg_values_supported": jwe::SUPPORTED_ALGORITHMS,
+            "userinfo_encryption_enc_values_supported": jwe::SUPPORTED_ENCRYPTIONS,
+            "token_endpoint_auth_methods_supported":
+                ["client_secret_basic", "client_secret_post", "none"],
+            "code_challenge_methods_supported": ["S256"],
+            "scopes_supported": claims::SUPPORTED_SCOPES,
+            "claims_supported": claims::supported_claims(),
+            "claims_parameter_supported": true,
+            "acr_values_supported": acr_values,
+            "prompt_values_supported": ["none", "login"],
+        }),
+    )
+}
+
 
+// the key signing with the default algorithm.
 #[get("/public-key")]
 pub fn public_key<'r>(state: State<Config>) -> String {
-    let raw_key = state.key_pair.public_key_to_pem().expect(
+    let keys = state.keys.current();
+    let key = keys.signing_key(keys.default_algorithm()).unwrap(); // safe unwrap
+    let raw_key = key.signer.public_key().public_key_to_pem().expect(
         "could not convert public key to pem",
     );
     String::from_utf8(raw_key).expect("could not convert pem bytes to utf8 string")
 }
 
 
+#[get("/jwks")]
+pub fn jwks<'r>(state: State<Config>) -> Response<'r> {
+    let keys = state.keys.current();
+    let jwks = keys.jwks().expect("could not export public keys");
+    Response::build()
+        .header(ContentType::JSON)
+        .sized_body(Cursor::new(jwks.to_string()))
+        .finalize()
+}
+
+
 
 
 #[post("/login", data = "<login_form>")]
-fn login<'r>(
+pub fn login<'r>(
     login_form: Form<Login>,
     state: State<Config>,
     host: RequestedHost,
+    origin: RequestOrigin,
+    remote: Option<SocketAddr>,
     mut cookies: Cookies,
 ) -> rocket::Response<'r> {
-
-    let possible_cookie = cookies.get_private("auth-request");
-
+    let iss = issuer(state.inner(), Some(host));
+    if let Err(response) = check_origin(state.inner(), &origin, &iss) {
+        return response;
+    }
     let login = login_form.into_inner();
 
-    let mut pwd = login.password;
-
-    pwd.push_str(&state.salt[..]);
-    let hashed_pwd_bytes = openssl::sha::sha256(pwd.as_bytes());
+    let auth_request = match auth_request_cookie(&mut cookies) {
+        Some(auth_request) => auth_request,
+        None => {
+            return Response::build()
+                .raw_status(400, "auth-request cookie not present")
+                .finalize()
+        }
+    };
+    let session_id = match checked_session(state.inner(), &mut cookies, &login.csrf_token) {
+        Ok(session_id) => session_id,
+        Err(response) => return response,
+    };
+    let now = time::get_time().sec;
+    let logged_in_as = logged_in_subject(state.inner(), &session_id, now);
+    store_session(state.inner(), &session_id, now);
+    let remote_addr = remote.map(|address| address.ip().to_string());
+    if let Some(seconds) = address_retry_after(state.inner(), remote_addr.as_ref(), now) {
+        return too_many_attempts(seconds);
+    }
 
-    let hashed_pwd = base64::encode(&hashed_pwd_bytes);
+    let get_user_result = state.store.find_user(&login.email);
 
-    if possible_cookie.is_none() {
+    if get_user_result.is_err() {
         return rocket::Response::build()
-            .raw_status(400, "auth-request cookie not present")
+            .raw_status(500, "error while connecting to database")
             .finalize();
     }
+    let found_user = get_user_result.unwrap().and_then(|user| if user.email == login.email {
+        Some(user)
+    } else {
+        None
+    });
+    // unknown users, wrong passwords and locked accounts look the same and
+    // take as long, the answer tells nothing about the account
+    let hash = match found_user {
+        Some(User { password: Some(ref hash), .. }) => &hash[..],
+        _ => password::DUMMY_HASH,
+    };
+    let verification = password::verify_password(&login.password, hash, &state.salt);
+    let locked = match found_user {
+        Some(ref user) => {
+            match state.throttle.account_retry_after(&*state.store, &user.id, now) {
+                Ok(retry_after) => retry_after.is_some(),
+                Err(e) => return reject(OidcErr::InternalErr(e)),
+            }
+        }
+        None => false,
+    };
+    if locked {
+        // guesses while locked don't extend the lockout, right or wrong
+        if let Err(e) = record_address_failure(state.inner(), remote_addr.as_ref(), now) {
+            return reject(OidcErr::InternalErr(e));
+        }
+        return invalid_credentials();
+    }
+    if verification == Verification::Invalid {
+        let user_id = found_user.as_ref().map(|user| &user.id[..]);
+        if let Err(e) = record_login_failure(state.inner(), user_id, remote_addr.as_ref(), now) {
+            return reject(OidcErr::InternalErr(e));
+        }
+        return invalid_credentials();
+    }
+    let user = found_user.unwrap(); // safe unwrap, verified
+    if let Err(response) = check_account_ready(state.inner(), &user) {
+        return response;
+    }
+    if let Err(e) = state.store.clear_failed_attempts(&user.id) {
+        return reject(OidcErr::InternalErr(e));
+    }
+    // hashes of former versions or weaker settings are replaced on login
+    if verification == Verification::Outdated {
+        let upgraded = password::hash_password(&login.password)
+            .map_err(|e| e.to_string())
+            .and_then(|hash| {
+                state.store.set_password(&user.id, &hash).map_err(|e| e.to_string())
+            });
+        if let Err(e) = upgraded {
+            log_failure("could not upgrade a password hash", &e);
+        }
+    }
+    let client = match state.store.get_client(auth_request.client_id.trim()) {
+        Ok(Some(client)) => client,
+        Ok(None) => return reject(OidcErr::ClientErr("invalid client id")),
+        Err(e) => return reject(OidcErr::InternalErr(e)),
+    };
+
+    match auth_request.hinted_subject(state.inner()) {
+        Ok(Some(ref hinted))
+            if hinted != &subject::subject_for(&client, &user.id, &state.pairwise_secret) => {
+            return error_redirect(
+                &auth_request.redirect_uri,
+                "login_required",
+                auth_request.state.as_ref(),
+            );
+        }
+        Ok(_) => {}
+        Err(e) => return reject(e),
+    }
 
-    let cookie = possible_cookie.unwrap();
+    // users with an authenticator or security key are always asked for a
+    // second factor, those who need one but have none enroll an authenticator now.
+    let authenticator = match state.store.get_totp(&user.id) {
+        Ok(authenticator) => authenticator,
+        Err(e) => return reject(OidcErr::InternalErr(e)),
+    };
+    let security_keys = match state.store.get_webauthn_credentials(&user.id) {
+        Ok(security_keys) => security_keys,
+        Err(e) => return reject(OidcErr::InternalErr(e)),
+    };
+    let mfa_required = auth_request.required_level(&client) >= AuthLevel::MultiFactor ||
+        match state.store.requires_mfa(&user.id) {
+            Ok(required) => required,
+            Err(e) => return reject(OidcErr::InternalErr(e)),
+        };
+    if authenticator.is_some() || !security_keys.is_empty() || mfa_required {
+        // the first second factor needs more than the password, see
+        // session::MFA_ENROLLMENT_PERIOD
+        if authenticator.is_none() && security_keys.is_empty() {
+            let enrollment_until = match state.store.get_mfa_enrollment(&user.id) {
+                Ok(until) => until,
+                Err(e) => return reject(OidcErr::InternalErr(e)),
+            };
+            let may_enroll = logged_in_as.as_ref() == Some(&user.id) ||
+                enrollment_until.map_or(false, |until| until > now);
+            if !may_enroll {
+                return account_not_ready(
+                    "Your account needs a second factor. Use a password reset link or ask an \
+                     administrator to set one up.",
+                );
+            }
+        }
+        let totp_secret = match authenticator {
+            Some(_) => None,
+            None if !security_keys.is_empty() => None,
+            None => {
+                match totp::generate_secret() {
+                    Ok(secret) => Some(secret),
+                    Err(_) => {
+                        return Response::build()
+                            .raw_status(500, "could not generate a secret")
+                            .finalize()
+                    }
+                }
+            }
+        };
+        // the first second factor comes with recovery codes
+        let remaining = match state.store.count_recovery_codes(&user.id) {
+            Ok(remaining) => remaining,
+            Err(e) => return reject(OidcErr::InternalErr(e)),
+        };
+        let new_codes = if totp_secret.is_some() && remaining == 0 {
+            match recovery_codes::generate() {
+                Ok(codes) => codes,
+                Err(_) => {
+                    return Response::build()
+                        .raw_status(500, "could not generate recovery codes")
+                        .finalize()
+                }
+            }
+        } else {
+            Vec::new()
+        };
+        if let Some(session) = state.sessions.write().unwrap().get_mut(&session_id) {
+            session.pending = Some(PendingLogin {
+                subject: user.id.clone(),
+                totp_secret: totp_secret.clone(),
+                recovery_codes: new_codes.clone(),
+            });
+            session.last_seen = now;
+        }
+        let hidden = |hide: bool| if hide { "hidden" } else { "" };
+        let totp_hidden = hidden(authenticator.is_none() && totp_secret.is_none());
+        let enrollment = totp_secret.map(|secret| {
+            totp_enrollment(&secret, &iss, &user.email) + &recovery_code_list(&new_codes)
+        });
+        return templates::html_response(
+            templates::SECOND_FACTOR,
+            &[
+                ("CORS-TOKEN", &login.csrf_token[..]),
+                ("ENROLLMENT", &enrollment.unwrap_or_default()[..]),
+                ("TOTP-HIDDEN", totp_hidden),
+                ("SECURITY-KEY-HIDDEN", hidden(security_keys.is_empty())),
+                ("RECOVERY-HIDDEN", hidden(remaining == 0)),
+                ("RECOVERY-CODES-LEFT", &remaining.to_string()),
+            ],
+        );
+    }
+
+    let authentication = Authentication {
+        subject: user.id.clone(),
+        amr: vec![String::from("pwd")],
+        auth_time: now,
+        remote_addr: remote_addr.clone(),
+    };
+    finish_login(
+        state.inner(),
+        &mut cookies,
+        &session_id,
+        &auth_request,
+        authentication,
+        iss,
+    )
+}
 
-    let auth_request: authentication_request::AuthenticationRequest =
-        serde_json::from_str(cookie.value()).unwrap();
-    let auth_state = auth_request.state.unwrap();
-    if login.state != auth_state {
-        println!("{} vs {}", login.state, auth_state);
-        return Response::build().raw_status(400, "wrong state").finalize();
+
+// stores a completed login in the session and answers the authentication
+// request. The session gets a new id and csrf token, so an id planted in
+// the browser before the login is worth nothing.
+fn finish_login<'r>(
+    config: &Config,
+    cookies: &mut Cookies,
+    session_id: &str,
+    auth_request: &authentication_request::AuthenticationRequest,
+    authentication: Authentication,
+    iss: String,
+) -> Response<'r> {
+    println!("user logged in!");
+    let new_session_id = Uuid::new_v4().simple().to_string();
+    let csrf_token = session::csrf_token(&new_session_id);
+    {
+        let mut sessions = config.sessions.write().unwrap();
+        let mut session = sessions.remove(session_id).unwrap_or_else(|| {
+            Session::new(String::new(), authentication.auth_time)
+        });
+        session.csrf_token = csrf_token.clone();
+        session.authentication = Some(authentication.clone());
+        session.pending = None;
+        session.last_seen = authentication.auth_time;
+        sessions.insert(new_session_id.clone(), session);
+    }
+    cookies.add_private(config.security.cookie("session", new_session_id));
+    let sign_in = SignIn {
+        user_id: authentication.subject.clone(),
+        at: authentication.auth_time,
+        remote_addr: authentication.remote_addr.clone(),
+        amr: authentication.amr.clone(),
+    };
+    if let Err(e) = config.store.record_sign_in(&sign_in) {
+        log_failure("could not record a login", &e);
     }
-    let issuer = state.inner().issuer.clone();
-    let key_path = format!("{}/private/sign-key.pem", state.config_dir_path.clone());
-    let get_user_result = state.store.get_user(&login.email, &hashed_pwd);
+    complete_authentication(
+        config,
+        &new_session_id,
+        auth_request,
+        &authentication,
+        &csrf_token,
+        iss,
+    )
+}
 
-    if get_user_result.is_err() {
-        return rocket::Response::build()
-            .raw_status(500, "error while connecting to database")
-            .finalize();
+
+// the QR code and key to set up an authenticator app with.
+fn totp_enrollment(secret: &str, issuer: &str, account: &str) -> String {
+    let uri = totp::provisioning_uri(secret, issuer, account);
+    let image = match QrCode::new(uri.as_bytes()) {
+        Ok(code) => code.render::<svg::Color>().min_dimensions(200, 200).build(),
+        Err(_) => String::new(),
+    };
+    format!(
+        r#"        <div class="form-group">
+          <div class="col-md-8">
+            <p>Scan this code with your authenticator app or enter the key <code>{}</code>,
+            then confirm with the code it shows.</p>
+            {}
+          </div>
+        </div>"#,
+        secret,
+        image
+    )
+}
+
+
+// recovery codes to write down, shown once after they are generated.
+fn recovery_code_list(codes: &[String]) -> String {
+    if codes.is_empty() {
+        return String::new();
     }
-    let possible_user = get_user_result.unwrap();
-    if possible_user.is_none() {
-        println!("user not found!");
-        return rocket::Response::build()
-            .raw_status(404, "user not found")
-            .finalize();
+    let items: Vec<String> = codes
+        .iter()
+        .map(|code| format!("              <li><code>{}</code></li>", code))
+        .collect();
+    format!(
+        r#"        <div class="form-group">
+          <div class="col-md-8">
+            <p>Keep these recovery codes in a safe place. Each of them logs you in
+            once if you lose your second factor.</p>
+            <ul>
+{}
+            </ul>
+          </div>
+        </div>"#,
+        items.join("\n")
+    )
+}
+
+
+#[derive(FromForm)]
+struct OneTimeCode {
+    csrf_token: String,
+    code: String,
+}
+
+
+// the second step of a login, for users who have to present a TOTP code.
+#[post("/login/totp", data = "<totp_form>")]
+pub fn login_totp<'r>(
+    totp_form: Form<OneTimeCode>,
+    state: State<Config>,
+    host: RequestedHost,
+    origin: RequestOrigin,
+    remote: Option<SocketAddr>,
+    mut cookies: Cookies,
+) -> Response<'r> {
+    let iss = issuer(state.inner(), Some(host));
+    if let Err(response) = check_origin(state.inner(), &origin, &iss) {
+        return response;
     }
-    let user = possible_user.unwrap();
-    println!("user logged in!");
+    let form = totp_form.into_inner();
 
-    let iss = match issuer {
-        Some(i) => i,
-        None => host.0,
+    let auth_request = match auth_request_cookie(&mut cookies) {
+        Some(auth_request) => auth_request,
+        None => {
+            return Response::build()
+                .raw_status(400, "auth-request cookie not present")
+                .finalize()
+        }
+    };
+    let session_id = match checked_session(state.inner(), &mut cookies, &form.csrf_token) {
+        Ok(session_id) => session_id,
+        Err(response) => return response,
+    };
+    let pending = match state.sessions.read().unwrap().get(&session_id).and_then(
+        |session| session.pending.clone(),
+    ) {
+        Some(pending) => pending,
+        None => return Response::build().raw_status(400, "no pending login").finalize(),
     };
 
-    let mut pem = String::new();
+    // codes are throttled like passwords, six digits are guessed quickly
+    let now = time::get_time().sec;
+    let remote_addr = remote.map(|address| address.ip().to_string());
+    if let Some(seconds) = address_retry_after(state.inner(), remote_addr.as_ref(), now) {
+        return too_many_attempts(seconds);
+    }
+    match state.throttle.account_retry_after(&*state.store, &pending.subject, now) {
+        Ok(Some(seconds)) => return too_many_attempts(seconds),
+        Ok(None) => {}
+        Err(e) => return reject(OidcErr::InternalErr(e)),
+    }
 
-    let mut f = File::open(key_path).expect("could not open private key file");
+    let enrolled = match pending.totp_secret {
+        Some(ref secret) => {
+            Some(Totp {
+                secret: secret.clone(),
+                last_step: 0,
+            })
+        }
+        None => {
+            match state.store.get_totp(&pending.subject) {
+                Ok(totp) => totp,
+                Err(e) => return reject(OidcErr::InternalErr(e)),
+            }
+        }
+    };
+    let verified = enrolled.and_then(|authenticator| {
+        match totp::verify(&authenticator.secret, &form.code, now) {
+            Some(step) if step > authenticator.last_step => {
+                Some(Totp {
+                    secret: authenticator.secret,
+                    last_step: step,
+                })
+            }
+            _ => None,
+        }
+    });
+    let authenticator = match verified {
+        Some(authenticator) => authenticator,
+        None => {
+            let recorded =
+                record_login_failure(state.inner(), Some(&pending.subject[..]), remote_addr.as_ref(), now);
+            if let Err(e) = recorded {
+                return reject(OidcErr::InternalErr(e));
+            }
+            return Response::build().raw_status(401, "wrong code").finalize();
+        }
+    };
+    let hashes: Vec<String> = pending
+        .recovery_codes
+        .iter()
+        .map(|code| recovery_codes::hash(&pending.subject, code))
+        .collect();
+    let saved = state
+        .store
+        .set_totp(&pending.subject, Some(&authenticator))
+        .and_then(|_| if hashes.is_empty() {
+            Ok(())
+        } else {
+            state.store.set_recovery_codes(&pending.subject, &hashes)
+        })
+        .and_then(|_| if pending.totp_secret.is_some() {
+            state.store.set_mfa_enrollment(&pending.subject, None)
+        } else {
+            Ok(())
+        })
+        .and_then(|_| state.store.clear_failed_attempts(&pending.subject));
+    if let Err(e) = saved {
+        return reject(OidcErr::InternalErr(e));
+    }
 
-    f.read_to_string(&mut pem).expect(
-        "unable to read private key file",
-    );
+    let authentication = Authentication {
+        subject: pending.subject,
+        amr: vec![String::from("pwd"), String::from("otp")],
+        auth_time: now,
+        remote_addr: remote_addr.clone(),
+    };
+    finish_login(
+        state.inner(),
+        &mut cookies,
+        &session_id,
+        &auth_request,
+        authentication,
+        iss,
+    )
+}
 
-    let id_token_builder = IDToken::build(&iss, &user.email, &[&auth_request.client_id], 60 * 20)
-        .amr(&["password"])
-        .nonce(auth_request.nonce.unwrap());
 
+// the second step of a login for users who lost their second factor,
+// every recovery code is accepted once.
+#[post("/login/recovery", data = "<recovery_form>")]
+pub fn login_recovery<'r>(
+    recovery_form: Form<OneTimeCode>,
+    state: State<Config>,
+    host: RequestedHost,
+    origin: RequestOrigin,
+    remote: Option<SocketAddr>,
+    mut cookies: Cookies,
+) -> Response<'r> {
+    let iss = issuer(state.inner(), Some(host));
+    if let Err(response) = check_origin(state.inner(), &origin, &iss) {
+        return response;
+    }
+    let form = recovery_form.into_inner();
+    let auth_request = match auth_request_cookie(&mut cookies) {
+        Some(auth_request) => auth_request,
+        None => {
+            return Response::build()
+                .raw_status(400, "auth-request cookie not present")
+                .finalize()
+        }
+    };
+    let session_id = match checked_session(state.inner(), &mut cookies, &form.csrf_token) {
+        Ok(session_id) => session_id,
+        Err(response) => return response,
+    };
+    let pending = match state.sessions.read().unwrap().get(&session_id).and_then(
+        |session| session.pending.clone(),
+    ) {
+        Some(pending) => pending,
+        None => return Response::build().raw_status(400, "no pending login").finalize(),
+    };
 
-    if auth_request.response_type == "code" {
-        let code = Uuid::new_v4().simple().to_string();
-        let mut codes = state.codes.write().expect(
-            "could not aquire lock on code map",
-        );
-        let token = id_token_builder.to_token_structure(Algorithm::ES512);
-        codes.insert(code.clone(), token);
-        let location =
-            format!(
-                "{}?code={}&state={}",
-                auth_request.redirect_uri,
-                code,
-                auth_state,
+    let now = time::get_time().sec;
+    let remote_addr = remote.map(|address| address.ip().to_string());
+    if let Some(seconds) = address_retry_after(state.inner(), remote_addr.as_ref(), now) {
+        return too_many_attempts(seconds);
+    }
+    match state.throttle.account_retry_after(&*state.store, &pending.subject, now) {
+        Ok(Some(seconds)) => return too_many_attempts(seconds),
+        Ok(None) => {}
+        Err(e) => return reject(OidcErr::InternalErr(e)),
+    }
+
+    let hash = recovery_codes::hash(&pending.subject, &form.code);
+    match state.store.use_recovery_code(&pending.subject, &hash) {
+        Ok(true) => {}
+        Ok(false) => {
+            let recorded =
+                record_login_failure(state.inner(), Some(&pending.subject[..]), remote_addr.as_ref(), now);
+            if let Err(e) = recorded {
+                return reject(OidcErr::InternalErr(e));
+            }
+            return Response::build().raw_status(401, "wrong code").finalize();
+        }
+        Err(e) => return reject(OidcErr::InternalErr(e)),
+    }
+    if let Err(e) = state.store.clear_failed_attempts(&pending.subject) {
+        return reject(OidcErr::InternalErr(e));
+    }
+
+    // recorded as such, so users can tell their codes were used
+    let authentication = Authentication {
+        subject: pending.subject,
+        amr: vec![String::from("pwd"), String::from("rc")],
+        auth_time: now,
+        remote_addr: remote_addr.clone(),
+    };
+    finish_login(
+        state.inner(),
+        &mut cookies,
+        &session_id,
+        &auth_request,
+        authentication,
+        iss,
+    )
+}
+
+
+#[derive(FromForm)]
+struct WebAuthnAssertion {
+    csrf_token: String,
+    credential_id: String,
+    client_data_json: String,
+    authenticator_data: String,
+    signature: String,
+}
+
+
+// a passkey login, or the second step of a login with a security key.
+#[post("/login/webauthn", data = "<assertion_form>")]
+pub fn login_webauthn<'r>(
+    assertion_form: Form<WebAuthnAssertion>,
+    state: State<Config>,
+    host: RequestedHost,
+    origin: RequestOrigin,
+    remote: Option<SocketAddr>,
+    mut cookies: Cookies,
+) -> Response<'r> {
+    let iss = issuer(state.inner(), Some(host));
+    if let Err(response) = check_origin(state.inner(), &origin, &iss) {
+        return response;
+    }
+    let assertion = assertion_form.into_inner();
+    let auth_request = match auth_request_cookie(&mut cookies) {
+        Some(auth_request) => auth_request,
+        None => {
+            return Response::build()
+                .raw_status(400, "auth-request cookie not present")
+                .finalize()
+        }
+    };
+    let session_id = match checked_session(state.inner(), &mut cookies, &assertion.csrf_token) {
+        Ok(session_id) => session_id,
+        Err(response) => return response,
+    };
+
+    let now = time::get_time().sec;
+    let remote_addr = remote.map(|address| address.ip().to_string());
+    if let Some(seconds) = address_retry_after(state.inner(), remote_addr.as_ref(), now) {
+        return too_many_attempts(seconds);
+    }
+    let (challenge, pending) = {
+        let mut sessions = state.sessions.write().unwrap();
+        match sessions.get_mut(&session_id) {
+            Some(session) => (session.webauthn_challenge.take(), session.pending.clone()),
+            None => (None, None),
+        }
+    };
+    let challenge = match challenge {
+        Some(challenge) => challenge,
+        None => return Response::build().raw_status(400, "no challenge").finalize(),
+    };
+
+    // while a login waits for its second factor only keys of that user count
+    let credential = match state.store.get_webauthn_credential(&assertion.credential_id) {
+        Ok(credential) => {
+            credential.and_then(|credential| if pending.as_ref().map_or(true, |p| {
+                p.subject == credential.user_id
+            })
+            {
+                Some(credential)
+            } else {
+                None
+            })
+        }
+        Err(e) => return reject(OidcErr::InternalErr(e)),
+    };
+    let credential = match credential {
+        Some(credential) => credential,
+        None => {
+            if let Err(e) = record_address_failure(state.inner(), remote_addr.as_ref(), now) {
+                return reject(OidcErr::InternalErr(e));
+            }
+            return Response::build().raw_status(401, "unknown security key").finalize();
+        }
+    };
+    match state.throttle.account_retry_after(&*state.store, &credential.user_id, now) {
+        Ok(Some(seconds)) => return too_many_attempts(seconds),
+        Ok(None) => {}
+        Err(e) => return reject(OidcErr::InternalErr(e)),
+    }
+
+    // passkeys replace the password, so they have to verify the user
+    let verified = webauthn::RelyingParty::for_issuer(&iss).and_then(|rp| {
+        rp.verify_assertion(
+            &challenge,
+            &credential,
+            &webauthn::decode(&assertion.client_data_json)?,
+            &webauthn::decode(&assertion.authenticator_data)?,
+            &webauthn::decode(&assertion.signature)?,
+            pending.is_none(),
+        )
+    });
+    let sign_count = match verified {
+        Ok(sign_count) => sign_count,
+        Err(e) => {
+            println!("security key rejected: {}", e);
+            let recorded = record_login_failure(
+                state.inner(),
+                Some(&credential.user_id[..]),
+                remote_addr.as_ref(),
+                now,
             );
-        Response::build()
-            .raw_header("Location", location)
-            .raw_status(302, "Found")
-            .finalize()
+            if let Err(e) = recorded {
+                return reject(OidcErr::InternalErr(e));
+            }
+            return Response::build().raw_status(401, "security key rejected").finalize();
+        }
+    };
+    let saved = state
+        .store
+        .update_webauthn_sign_count(&credential.id, sign_count)
+        .and_then(|_| state.store.clear_failed_attempts(&credential.user_id));
+    if let Err(e) = saved {
+        return reject(OidcErr::InternalErr(e));
+    }
+
+    let amr = if pending.is_some() {
+        vec![String::from("pwd"), String::from("hwk")]
     } else {
-        //implicit flow, return token directly to callback
-        let jwt = id_token_builder
-            .sign_with_pem(pem, Algorithm::ES512)
-            .expect("could not sign token");
+        let client = match state.store.get_client(auth_request.client_id.trim()) {
+            Ok(Some(client)) => client,
+            Ok(None) => return reject(OidcErr::ClientErr("invalid client id")),
+            Err(e) => return reject(OidcErr::InternalErr(e)),
+        };
+        match auth_request.hinted_subject(state.inner()) {
+            Ok(Some(ref hinted))
+                if hinted !=
+                       &subject::subject_for(&client, &credential.user_id, &state.pairwise_secret) => {
+                return error_redirect(
+                    &auth_request.redirect_uri,
+                    "login_required",
+                    auth_request.state.as_ref(),
+                );
+            }
+            Ok(_) => {}
+            Err(e) => return reject(e),
+        }
+        // the passkey stands in for the password, so the same checks apply
+        let user = match state.store.find_user(&credential.user_id) {
+            Ok(Some(user)) => user,
+            Ok(None) => return Response::build().raw_status(401, "unknown security key").finalize(),
+            Err(e) => return reject(OidcErr::InternalErr(e)),
+        };
+        if let Err(response) = check_account_ready(state.inner(), &user) {
+            return response;
+        }
+        vec![String::from("hwk"), String::from("mfa")]
+    };
+    let authentication = Authentication {
+        subject: credential.user_id,
+        amr: amr,
+        auth_time: now,
+        remote_addr: remote_addr.clone(),
+    };
+    finish_login(
+        state.inner(),
+        &mut cookies,
+        &session_id,
+        &auth_request,
+        authentication,
+        iss,
+    )
+}
+
+
+#[derive(FromForm)]
+struct CsrfToken {
+    csrf_token: String,
+}
+
+
+// options for a passkey login or, while a login waits for its second
+// factor, for the security keys of that user.
+#[post("/webauthn/assertion-options", data = "<csrf_form>")]
+pub fn assertion_options<'r>(
+    csrf_form: Form<CsrfToken>,
+    state: State<Config>,
+    host: Option<RequestedHost>,
+    mut cookies: Cookies,
+) -> Response<'r> {
+    let csrf_token = csrf_form.into_inner().csrf_token;
+    let session_id = match checked_session(state.inner(), &mut cookies, &csrf_token) {
+        Ok(session_id) => session_id,
+        Err(response) => return response,
+    };
+    let rp = match webauthn::RelyingParty::for_issuer(&issuer(state.inner(), host)) {
+        Ok(rp) => rp,
+        Err(_) => return Response::build().raw_status(500, "invalid issuer").finalize(),
+    };
+    let pending = state.sessions.read().unwrap().get(&session_id).and_then(
+        |session| session.pending.clone(),
+    );
+    let (allowed, user_verification) = match pending {
+        Some(pending) => {
+            match state.store.get_webauthn_credentials(&pending.subject) {
+                Ok(credentials) => (credentials, "preferred"),
+                Err(_) => return database_error(),
+            }
+        }
+        None => (Vec::new(), "required"),
+    };
+    let challenge = match new_webauthn_challenge(state.inner(), &session_id) {
+        Ok(challenge) => challenge,
+        Err(response) => return response,
+    };
+    json_response(
+        Status::Ok,
+        rp.assertion_options(&challenge, &allowed, user_verification),
+    )
+}
+
 
-        let location =
+// the security keys of the logged in user, new ones are registered here.
+#[get("/webauthn")]
+pub fn security_keys<'r>(state: State<Config>, mut cookies: Cookies) -> Response<'r> {
+    let session_id = match cookies.get_private("session") {
+        Some(session_cookie) => String::from(session_cookie.value()),
+        None => return Response::build().raw_status(401, "not logged in").finalize(),
+    };
+    let user = match session_user(state.inner(), &session_id) {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let (csrf_token, fresh_codes) = match state.sessions.write().unwrap().get_mut(&session_id) {
+        Some(session) => (session.csrf_token.clone(), session.fresh_recovery_codes.take()),
+        None => return Response::build().raw_status(401, "not logged in").finalize(),
+    };
+    let remaining = match state.store.count_recovery_codes(&user.id) {
+        Ok(remaining) => remaining,
+        Err(_) => return database_error(),
+    };
+    let credentials = match state.store.get_webauthn_credentials(&user.id) {
+        Ok(credentials) => credentials,
+        Err(_) => return database_error(),
+    };
+    let keys: Vec<String> = credentials
+        .iter()
+        .map(|credential| {
+            let added = time::at_utc(time::Timespec::new(credential.created, 0));
             format!(
-                    "{}?token_type=bearer&id_token={}&expires_in={}&state={}",
-                    auth_request.redirect_uri,
-                    jwt,
-                    state.token_duration,
-                    auth_state,
-                );
-        println!("{}", location);
-        rocket::Response::build()
-            .raw_header("Location", location)
-            .raw_status(302, "Found")
+                "        <li>{} (added {})</li>",
+                escape_html(&credential.name),
+                added.rfc3339()
+            )
+        })
+        .collect();
+    templates::html_response(
+        templates::SECURITY_KEYS,
+        &[
+            ("CORS-TOKEN", &csrf_token[..]),
+            ("USER", &escape_html(&user.email)[..]),
+            ("KEYS", &keys.join("\n")[..]),
+            ("RECOVERY-CODES", &recovery_code_list(&fresh_codes.unwrap_or_default())[..]),
+            ("RECOVERY-CODES-LEFT", &remaining.to_string()),
+        ],
+    )
+}
+
+
+// a passkey logs in without the password, a session alone is not enough to add one.
+#[post("/webauthn/registration-options", data = "<confirmation_form>")]
+pub fn registration_options<'r>(
+    confirmation_form: Form<PasswordConfirmation>,
+    state: State<Config>,
+    host: Option<RequestedHost>,
+    remote: Option<SocketAddr>,
+    mut cookies: Cookies,
+) -> Response<'r> {
+    let confirmation = confirmation_form.into_inner();
+    let session_id = match checked_session(state.inner(), &mut cookies, &confirmation.csrf_token) {
+        Ok(session_id) => session_id,
+        Err(response) => return response,
+    };
+    let user = match session_user(state.inner(), &session_id) {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let confirmed = check_current_password(
+        state.inner(),
+        &session_id,
+        &user,
+        &confirmation.current_password,
+        remote,
+    );
+    if let Err(response) = confirmed {
+        return response;
+    }
+    let rp = match webauthn::RelyingParty::for_issuer(&issuer(state.inner(), host)) {
+        Ok(rp) => rp,
+        Err(_) => return Response::build().raw_status(500, "invalid issuer").finalize(),
+    };
+    let existing = match state.store.get_webauthn_credentials(&user.id) {
+        Ok(credentials) => credentials,
+        Err(_) => return database_error(),
+    };
+    let challenge = match new_webauthn_challenge(state.inner(), &session_id) {
+        Ok(challenge) => challenge,
+        Err(response) => return response,
+    };
+    json_response(
+        Status::Ok,
+        rp.registration_options(&user, &challenge, &existing),
+    )
+}
+
+
+#[derive(FromForm)]
+struct WebAuthnRegistration {
+    csrf_token: String,
+    current_password: String,
+    name: String,
+    client_data_json: String,
+    attestation_object: String,
+}
+
+
+#[post("/webauthn/register", data = "<registration_form>")]
+pub fn register_security_key<'r>(
+    registration_form: Form<WebAuthnRegistration>,
+    state: State<Config>,
+    host: Option<RequestedHost>,
+    remote: Option<SocketAddr>,
+    mut cookies: Cookies,
+) -> Response<'r> {
+    let registration = registration_form.into_inner();
+    let session_id = match checked_session(state.inner(), &mut cookies, &registration.csrf_token) {
+        Ok(session_id) => session_id,
+        Err(response) => return response,
+    };
+    let user = match session_user(state.inner(), &session_id) {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    // the challenge of a passkey login would do for the ceremony as well
+    let confirmed = check_current_password(
+        state.inner(),
+        &session_id,
+        &user,
+        &registration.current_password,
+        remote,
+    );
+    if let Err(response) = confirmed {
+        return response;
+    }
+    let challenge = state.sessions.write().unwrap().get_mut(&session_id).and_then(
+        |session| session.webauthn_challenge.take(),
+    );
+    let challenge = match challenge {
+        Some(challenge) => challenge,
+        None => return Response::build().raw_status(400, "no challenge").finalize(),
+    };
+    let verified = webauthn::RelyingParty::for_issuer(&issuer(state.inner(), host))
+        .and_then(|rp| {
+            rp.verify_registration(
+                &challenge,
+                &webauthn::decode(&registration.client_data_json)?,
+                &webauthn::decode(&registration.attestation_object)?,
+            )
+        });
+    let registered = match verified {
+        Ok(registered) => registered,
+        Err(e) => {
+            println!("security key not registered: {}", e);
+            return Response::build().raw_status(400, "invalid registration").finalize();
+        }
+    };
+    match state.store.get_webauthn_credential(&registered.id) {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            return Response::build()
+                .raw_status(400, "security key already registered")
+                .finalize()
+        }
+        Err(_) => return database_error(),
+    }
+    let credential = WebAuthnCredential {
+        id: registered.id,
+        user_id: user.id,
+        public_key: registered.public_key,
+        sign_count: registered.sign_count,
+        name: registration.name,
+        created: time::get_time().sec,
+    };
+    if state.store.save_webauthn_credential(&credential).is_err() {
+        return database_error();
+    }
+    // the first second factor comes with recovery codes
+    match state.store.count_recovery_codes(&credential.user_id) {
+        Ok(0) => {
+            let codes = match recovery_codes::generate() {
+                Ok(codes) => codes,
+                Err(_) => {
+                    return Response::build()
+                        .raw_status(500, "could not generate recovery codes")
+                        .finalize()
+                }
+            };
+            let hashes: Vec<String> = codes
+                .iter()
+                .map(|code| recovery_codes::hash(&credential.user_id, code))
+                .collect();
+            if state.store.set_recovery_codes(&credential.user_id, &hashes).is_err() {
+                return database_error();
+            }
+            if let Some(session) = state.sessions.write().unwrap().get_mut(&session_id) {
+                session.fresh_recovery_codes = Some(codes);
+            }
+        }
+        Ok(_) => {}
+        Err(_) => return database_error(),
+    }
+    Response::build()
+        .raw_header("Location", "/webauthn")
+        .raw_status(303, "See Other")
+        .finalize()
+}
+
+
+// how many logins the account page lists.
+const RECENT_SIGN_INS: i64 = 20;
+
+
+#[get("/account")]
+pub fn account<'r>(state: State<Config>, mut cookies: Cookies) -> Response<'r> {
+    let session_id = match cookies.get_private("session") {
+        Some(session_cookie) => String::from(session_cookie.value()),
+        None => return Response::build().raw_status(401, "not logged in").finalize(),
+    };
+    match session_user(state.inner(), &session_id) {
+        Ok(user) => account_page(state.inner(), &session_id, &user, ""),
+        Err(response) => response,
+    }
+}
+
+
+#[derive(FromForm)]
+struct PasswordChange {
+    csrf_token: String,
+    current_password: String,
+    password: String,
+    password_confirmation: String,
+}
+
+
+// replaces the password of the logged in user and signs out its other sessions.
+#[post("/account/password", data = "<change_form>")]
+pub fn change_password<'r>(
+    change_form: Form<PasswordChange>,
+    state: State<Config>,
+    remote: Option<SocketAddr>,
+    mut cookies: Cookies,
+) -> Response<'r> {
+    let change = change_form.into_inner();
+    let (session_id, user) = match account_user(state.inner(), &mut cookies, &change.csrf_token) {
+        Ok(logged_in) => logged_in,
+        Err(response) => return response,
+    };
+    let confirmed = check_current_password(
+        state.inner(),
+        &session_id,
+        &user,
+        &change.current_password,
+        remote,
+    );
+    if let Err(response) = confirmed {
+        return response;
+    }
+    if change.password.is_empty() || change.password != change.password_confirmation {
+        let mut response = account_page(
+            state.inner(),
+            &session_id,
+            &user,
+            "The new passwords do not match, please try again.",
+        );
+        response.set_status(Status::BadRequest);
+        return response;
+    }
+
+    let hash = match password::hash_password(&change.password) {
+        Ok(hash) => hash,
+        Err(_) => {
+            return Response::build()
+                .raw_status(500, "could not hash the password")
+                .finalize()
+        }
+    };
+    let saved = state.store.set_password(&user.id, &hash).and_then(|_| {
+        state.store.clear_failed_attempts(&user.id)
+    });
+    if let Err(e) = saved {
+        return reject(OidcErr::InternalErr(e));
+    }
+    state.sessions.write().unwrap().retain(|id, session| {
+        id == &session_id ||
+            session.authentication.as_ref().map_or(true, |authentication| {
+                authentication.subject != user.id
+            })
+    });
+    account_page(
+        state.inner(),
+        &session_id,
+        &user,
+        "Your password was changed, your other sessions were signed out.",
+    )
+}
+
+
+#[derive(FromForm)]
+struct PasswordConfirmation {
+    csrf_token: String,
+    current_password: String,
+}
+
+
+// removes the authenticator app, users who need a second factor enroll one on the next login.
+#[post("/account/totp/remove", data = "<confirmation_form>")]
+pub fn remove_totp<'r>(
+    confirmation_form: Form<PasswordConfirmation>,
+    state: State<Config>,
+    remote: Option<SocketAddr>,
+    mut cookies: Cookies,
+) -> Response<'r> {
+    let confirmation = confirmation_form.into_inner();
+    let logged_in = account_user(state.inner(), &mut cookies, &confirmation.csrf_token);
+    let (session_id, user) = match logged_in {
+        Ok(logged_in) => logged_in,
+        Err(response) => return response,
+    };
+    let confirmed = check_current_password(
+        state.inner(),
+        &session_id,
+        &user,
+        &confirmation.current_password,
+        remote,
+    );
+    if let Err(response) = confirmed {
+        return response;
+    }
+    if state.store.set_totp(&user.id, None).is_err() {
+        return database_error();
+    }
+    to_account()
+}
+
+
+#[derive(FromForm)]
+struct SecurityKeyRemoval {
+    csrf_token: String,
+    current_password: String,
+    id: String,
+}
+
+
+#[post("/account/security-keys/remove", data = "<removal_form>")]
+pub fn remove_security_key<'r>(
+    removal_form: Form<SecurityKeyRemoval>,
+    state: State<Config>,
+    remote: Option<SocketAddr>,
+    mut cookies: Cookies,
+) -> Response<'r> {
+    let removal = removal_form.into_inner();
+    let (session_id, user) = match account_user(state.inner(), &mut cookies, &removal.csrf_token) {
+        Ok(logged_in) => logged_in,
+        Err(response) => return response,
+    };
+    let confirmed = check_current_password(
+        state.inner(),
+        &session_id,
+        &user,
+        &removal.current_password,
+        remote,
+    );
+    if let Err(response) = confirmed {
+        return response;
+    }
+    if state.store.delete_webauthn_credential(&user.id, &removal.id).is_err() {
+        return database_error();
+    }
+    to_account()
+}
+
+
+// replaces the recovery codes, the new ones are shown once on the account page.
+#[post("/account/recovery-codes", data = "<confirmation_form>")]
+pub fn regenerate_recovery_codes<'r>(
+    confirmation_form: Form<PasswordConfirmation>,
+    state: State<Config>,
+    remote: Option<SocketAddr>,
+    mut cookies: Cookies,
+) -> Response<'r> {
+    let confirmation = confirmation_form.into_inner();
+    let logged_in = account_user(state.inner(), &mut cookies, &confirmation.csrf_token);
+    let (session_id, user) = match logged_in {
+        Ok(logged_in) => logged_in,
+        Err(response) => return response,
+    };
+    let confirmed = check_current_password(
+        state.inner(),
+        &session_id,
+        &user,
+        &confirmation.current_password,
+        remote,
+    );
+    if let Err(response) = confirmed {
+        return response;
+    }
+    let codes = match recovery_codes::generate() {
+        Ok(codes) => codes,
+        Err(_) => {
+            return Response::build()
+                .raw_status(500, "could not generate recovery codes")
+                .finalize()
+        }
+    };
+    let hashes: Vec<String> = codes
+        .iter()
+        .map(|code| recovery_codes::hash(&user.id, code))
+        .collect();
+    if state.store.set_recovery_codes(&user.id, &hashes).is_err() {
+        return database_error();
+    }
+    if let Some(session) = state.sessions.write().unwrap().get_mut(&session_id) {
+        session.fresh_recovery_codes = Some(codes);
+    }
+    to_account()
+}
+
+
+#[derive(FromForm)]
+struct SessionEnd {
+    csrf_token: String,
+    session: String,
+}
+
+
+// signs out one session of the user, which may be the current one.
+#[post("/account/sessions/end", data = "<end_form>")]
+pub fn end_session<'r>(
+    end_form: Form<SessionEnd>,
+    state: State<Config>,
+    mut cookies: Cookies,
+) -> Response<'r> {
+    let end = end_form.into_inner();
+    let (session_id, user) = match account_user(state.inner(), &mut cookies, &end.csrf_token) {
+        Ok(logged_in) => logged_in,
+        Err(response) => return response,
+    };
+    let ended = {
+        let mut sessions = state.sessions.write().unwrap();
+        let ended = sessions
+            .iter()
+            .find(|&(id, session)| {
+                session_handle(id) == end.session &&
+                    session.authentication.as_ref().map_or(false, |authentication| {
+                        authentication.subject == user.id
+                    })
+            })
+            .map(|(id, _)| id.clone());
+        if let Some(ref id) = ended {
+            sessions.remove(id);
+        }
+        ended
+    };
+    match ended {
+        Some(ref id) if id == &session_id => {
+            cookies.remove_private(Cookie::named("session"));
+            templates::html_response(templates::MESSAGE, &[("MESSAGE", "You are signed out.")])
+        }
+        _ => to_account(),
+    }
+}
+
+
+#[derive(FromForm)]
+struct ConsentRevocation {
+    csrf_token: String,
+    client: String,
+}
+
+
+// withdraws the consent given to a client and the tokens it holds for the user.
+#[post("/account/consents/revoke", data = "<revocation_form>")]
+pub fn revoke_consent<'r>(
+    revocation_form: Form<ConsentRevocation>,
+    state: State<Config>,
+    mut cookies: Cookies,
+) -> Response<'r> {
+    let revocation = revocation_form.into_inner();
+    let user = match account_user(state.inner(), &mut cookies, &revocation.csrf_token) {
+        Ok((_, user)) => user,
+        Err(response) => return response,
+    };
+    let client = match state.store.get_client(&revocation.client) {
+        Ok(Some(client)) => client,
+        Ok(None) => return to_account(),
+        Err(_) => return database_error(),
+    };
+    if state.store.revoke_consent(&user.id, Some(&client.name[..])).is_err() {
+        return database_error();
+    }
+    // grants name the client the way the authentication request did
+    let revoked = |grant: &AccessGrant| {
+        grant.subject == user.id && (grant.client_id == client.id || grant.client_id == client.name)
+    };
+    state.access_tokens.write().unwrap().retain(|_, grant| !revoked(&*grant));
+    state.refresh_tokens.write().unwrap().retain(|_, grant| !revoked(&*grant));
+    to_account()
+}
+
+
+// checks the current password a form on the account page carries, it guards
+// against anyone who finds the session open. Wrong ones count like failed logins.
+fn check_current_password<'r>(
+    config: &Config,
+    session_id: &str,
+    user: &User,
+    current_password: &str,
+    remote: Option<SocketAddr>,
+) -> Result<(), Response<'r>> {
+    let now = time::get_time().sec;
+    let remote_addr = remote.map(|address| address.ip().to_string());
+    if let Some(seconds) = address_retry_after(config, remote_addr.as_ref(), now) {
+        return Err(too_many_attempts(seconds));
+    }
+    match config.throttle.account_retry_after(&*config.store, &user.id, now) {
+        Ok(Some(seconds)) => return Err(too_many_attempts(seconds)),
+        Ok(None) => {}
+        Err(e) => return Err(reject(OidcErr::InternalErr(e))),
+    }
+    let verification = match user.password {
+        Some(ref hash) => password::verify_password(current_password, hash, &config.salt),
+        None => Verification::Invalid,
+    };
+    if verification == Verification::Invalid {
+        let failure = record_login_failure(config, Some(&user.id[..]), remote_addr.as_ref(), now);
+        if let Err(e) = failure {
+            return Err(reject(OidcErr::InternalErr(e)));
+        }
+        let mut response = account_page(config, session_id, user, "The current password is wrong.");
+        response.set_status(Status::BadRequest);
+        return Err(response);
+    }
+    config.store.clear_failed_attempts(&user.id).map_err(|e| reject(OidcErr::InternalErr(e)))
+}
+
+
+// the session and user of a form posted from the account page.
+fn account_user<'r>(
+    config: &Config,
+    cookies: &mut Cookies,
+    csrf_token: &str,
+) -> Result<(String, User), Response<'r>> {
+    let session_id = checked_session(config, cookies, csrf_token)?;
+    let user = session_user(config, &session_id)?;
+    Ok((session_id, user))
+}
+
+
+fn to_account<'r>() -> Response<'r> {
+    Response::build()
+        .raw_header("Location", "/account")
+        .raw_status(303, "See Other")
+        .finalize()
+}
+
+
+// identifies a session on the account page, its id stays in the cookie.
+fn session_handle(session_id: &str) -> String {
+    base64::encode_config(
+        &openssl::sha::sha256(session_id.as_bytes()),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+
+fn format_time(seconds: i64) -> String {
+    time::at_utc(time::Timespec::new(seconds, 0)).rfc3339().to_string()
+}
+
+
+// a form with a single button, it posts the csrf token and `field` to `action`.
+fn button_form(action: &str, csrf_token: &str, field: Option<(&str, &str)>, label: &str) -> String {
+    inline_form(action, csrf_token, &hidden_field(field), label)
+}
+
+
+// a button form that asks for the current password as well, for changes to
+// the second factors.
+fn confirmed_button_form(
+    action: &str,
+    csrf_token: &str,
+    field: Option<(&str, &str)>,
+    label: &str,
+) -> String {
+    let inputs = format!(
+        r#"{}<input name="current_password" type="password" autocomplete="current-password" placeholder="Current password" class="form-control input-sm" required="" />"#,
+        hidden_field(field)
+    );
+    inline_form(action, csrf_token, &inputs, label)
+}
+
+
+fn hidden_field(field: Option<(&str, &str)>) -> String {
+    field
+        .map(|(name, value)| {
+            format!(
+                r#"<input type="hidden" name="{}" value="{}" />"#,
+                name,
+                escape_html(value)
+            )
+        })
+        .unwrap_or_default()
+}
+
+
+fn inline_form(action: &str, csrf_token: &str, inputs: &str, label: &str) -> String {
+    format!(
+        r#"<form class="form-inline" action="{}" method="post"><input type="hidden" name="csrf_token" value="{}" />{}<button class="btn btn-default btn-xs" type="submit">{}</button></form>"#,
+        action,
+        csrf_token,
+        inputs,
+        label
+    )
+}
+
+
+// how a login was done, in words.
+fn amr_description(amr: &[String]) -> String {
+    let methods: Vec<&str> = amr.iter()
+        .filter_map(|method| match &method[..] {
+            "pwd" => Some("password"),
+            "otp" => Some("one-time code"),
+            "hwk" => Some("security key"),
+            "rc" => Some("recovery code"),
+            _ => None,
+        })
+        .collect();
+    methods.join(" and ")
+}
+
+
+// the account page of `user`, `message` tells how the last change went.
+fn account_page<'r>(config: &Config, session_id: &str, user: &User, message: &str) -> Response<'r> {
+    let now = time::get_time().sec;
+    let (csrf_token, fresh_codes) = match config.sessions.write().unwrap().get_mut(session_id) {
+        Some(session) => (session.csrf_token.clone(), session.fresh_recovery_codes.take()),
+        None => return Response::build().raw_status(401, "not logged in").finalize(),
+    };
+    let loaded = config.store.get_totp(&user.id).and_then(|authenticator| {
+        let credentials = config.store.get_webauthn_credentials(&user.id)?;
+        let remaining = config.store.count_recovery_codes(&user.id)?;
+        let consents = config.store.get_consents(&user.id)?;
+        let sign_ins = config.store.get_sign_ins(&user.id, RECENT_SIGN_INS)?;
+        Ok((authenticator, credentials, remaining, consents, sign_ins))
+    });
+    let (authenticator, credentials, remaining, consents, sign_ins) = match loaded {
+        Ok(loaded) => loaded,
+        Err(_) => return database_error(),
+    };
+    let address = |remote_addr: &Option<String>| {
+        escape_html(remote_addr.as_ref().map_or("an unknown address", |address| &address[..]))
+    };
+
+    let totp_status = match authenticator {
+        Some(_) => {
+            format!(
+                "An authenticator app is set up. {}",
+                confirmed_button_form("/account/totp/remove", &csrf_token, None, "Remove")
+            )
+        }
+        None => String::from("No authenticator app is set up."),
+    };
+    let keys: Vec<String> = credentials
+        .iter()
+        .map(|credential| {
+            format!(
+                "        <li>{} (added {}) {}</li>",
+                escape_html(&credential.name),
+                format_time(credential.created),
+                confirmed_button_form(
+                    "/account/security-keys/remove",
+                    &csrf_token,
+                    Some(("id", &credential.id)),
+                    "Remove",
+                )
+            )
+        })
+        .collect();
+    let regenerate = if authenticator.is_some() || !credentials.is_empty() {
+        confirmed_button_form(
+            "/account/recovery-codes",
+            &csrf_token,
+            None,
+            "New recovery codes",
+        )
+    } else {
+        String::new()
+    };
+
+    let current = session_handle(session_id);
+    let mut sessions: Vec<(String, Authentication, i64)> = config
+        .sessions
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|&(_, session)| !session.is_expired(&config.session_policy, now))
+        .filter_map(|(id, session)| {
+            session.authentication.as_ref().and_then(|authentication| {
+                if authentication.subject == user.id {
+                    Some((session_handle(id), authentication.clone(), session.last_seen))
+                } else {
+                    None
+                }
+            })
+        })
+        .collect();
+    sessions.sort_by(|a, b| b.1.auth_time.cmp(&a.1.auth_time));
+    let session_items: Vec<String> = sessions
+        .iter()
+        .map(|&(ref handle, ref authentication, last_seen)| {
+            format!(
+                "        <li>Logged in {} from {}, last seen {}{} {}</li>",
+                format_time(authentication.auth_time),
+                address(&authentication.remote_addr),
+                format_time(last_seen),
+                if handle == &current { " (this session)" } else { "" },
+                button_form(
+                    "/account/sessions/end",
+                    &csrf_token,
+                    Some(("session", handle)),
+                    "Sign out",
+                )
+            )
+        })
+        .collect();
+
+    let mut client_names: Vec<&String> = consents.keys().collect();
+    client_names.sort();
+    let consent_items: Vec<String> = client_names
+        .iter()
+        .map(|name| {
+            let consent = &consents[*name];
+            let granted: Vec<&str> = consent
+                .scopes
+                .iter()
+                .chain(&consent.claims)
+                .map(|name| &name[..])
+                .collect();
+            format!(
+                "        <li>{} may read {} since {} {}</li>",
+                escape_html(name),
+                escape_html(&granted.join(", ")),
+                format_time(consent.granted_at),
+                button_form(
+                    "/account/consents/revoke",
+                    &csrf_token,
+                    Some(("client", name)),
+                    "Revoke",
+                )
+            )
+        })
+        .collect();
+    let sign_in_items: Vec<String> = sign_ins
+        .iter()
+        .map(|sign_in| {
+            format!(
+                "        <li>{} from {} with {}</li>",
+                format_time(sign_in.at),
+                address(&sign_in.remote_addr),
+                amr_description(&sign_in.amr)
+            )
+        })
+        .collect();
+
+    templates::html_response(
+        templates::ACCOUNT,
+        &[
+            ("CORS-TOKEN", &csrf_token[..]),
+            ("USER", &escape_html(&user.email)[..]),
+            ("MESSAGE", &escape_html(message)[..]),
+            ("TOTP", &totp_status[..]),
+            ("KEYS", &keys.join("\n")[..]),
+            ("RECOVERY-CODES", &recovery_code_list(&fresh_codes.unwrap_or_default())[..]),
+            ("RECOVERY-CODES-LEFT", &remaining.to_string()),
+            ("REGENERATE-RECOVERY-CODES", &regenerate[..]),
+            ("SESSIONS", &session_items.join("\n")[..]),
+            ("CONSENTS", &consent_items.join("\n")[..]),
+            ("SIGN-INS", &sign_in_items.join("\n")[..]),
+        ],
+    )
+}
+
+
+#[get("/password/forgot")]
+pub fn forgot_password_form<'r>(state: State<Config>) -> Response<'r> {
+    if state.mailer.is_none() {
+        return Response::build().status(Status::NotFound).finalize();
+    }
+    templates::html_response(templates::FORGOT_PASSWORD, &[])
+}
+
+
+#[derive(FromForm)]
+struct ForgotPassword {
+    email: String,
+}
+
+
+// mails a reset link to the address, if it belongs to a user. The answer
+// is the same either way, it does not tell which addresses have accounts.
+#[post("/password/forgot", data = "<forgot_form>")]
+pub fn forgot_password<'r>(
+    forgot_form: Form<ForgotPassword>,
+    state: State<Config>,
+    host: Option<RequestedHost>,
+    remote: Option<SocketAddr>,
+) -> Response<'r> {
+    let mailer = match state.mailer {
+        Some(ref mailer) => mailer,
+        None => return Response::build().status(Status::NotFound).finalize(),
+    };
+    let email = forgot_form.into_inner().email;
+
+    // every request counts, so that an address can not flood mailboxes
+    let now = time::get_time().sec;
+    let remote_addr = remote.map(|address| address.ip().to_string());
+    if let Some(seconds) = mail_retry_after(state.inner(), remote_addr.as_ref(), now) {
+        return too_many_attempts(seconds);
+    }
+    if let Err(e) = record_mail_request(state.inner(), remote_addr.as_ref(), now) {
+        return reject(OidcErr::InternalErr(e));
+    }
+
+    let user = match state.store.find_user(&email) {
+        Ok(user) => user.and_then(|user| if user.email == email { Some(user) } else { None }),
+        Err(_) => return database_error(),
+    };
+    if let Some(user) = user {
+        let iss = issuer(state.inner(), host);
+        let token = {
+            let keys = state.keys.current();
+            password_reset::issue(&keys, &user, &iss, now)
+        };
+        let token = match token {
+            Ok(token) => token,
+            Err(e) => {
+                log_failure("could not issue a password reset token", &e);
+                return Response::build()
+                    .raw_status(500, "could not issue a token")
+                    .finalize();
+            }
+        };
+        let link = format!("{}/password/reset?token={}", base_url(&iss), token);
+        let mail = Mail {
+            to: user.email.clone(),
+            subject: String::from("Reset your password"),
+            body: format!(
+                "Hello,\n\nsomeone, hopefully you, asked to reset the password of your \
+                 account at {}.\nOpen this link within an hour to choose a new one:\n\n{}\n\n\
+                 If it was not you, ignore this mail. Your password stays unchanged.\n",
+                iss,
+                link
+            ),
+        };
+        send_in_background(mailer, mail, "could not send a password reset link");
+    }
+
+    let message = format!(
+        "If {} belongs to an account, a link to reset its password is on its way.",
+        email
+    );
+    templates::html_response(templates::MESSAGE, &[("MESSAGE", &escape_html(&message)[..])])
+}
+
+
+#[derive(FromForm)]
+struct TokenLink {
+    token: String,
+}
+
+
+#[get("/password/reset?<link>")]
+pub fn reset_password_form<'r>(link: TokenLink, state: State<Config>) -> Response<'r> {
+    match valid_reset_token(state.inner(), &link.token) {
+        Ok(_) => {
+            templates::html_response(
+                templates::RESET_PASSWORD,
+                &[
+                    ("TOKEN", &escape_html(&link.token)[..]),
+                    ("MESSAGE", "Choose a new password."),
+                ],
+            )
+        }
+        Err(response) => response,
+    }
+}
+
+
+#[derive(FromForm)]
+struct PasswordReset {
+    token: String,
+    password: String,
+    password_confirmation: String,
+}
+
+
+#[post("/password/reset", data = "<reset_form>")]
+pub fn reset_password<'r>(
+    reset_form: Form<PasswordReset>,
+    state: State<Config>,
+    remote: Option<SocketAddr>,
+) -> Response<'r> {
+    let reset = reset_form.into_inner();
+    let now = time::get_time().sec;
+    let remote_addr = remote.map(|address| address.ip().to_string());
+    if let Some(seconds) = address_retry_after(state.inner(), remote_addr.as_ref(), now) {
+        return too_many_attempts(seconds);
+    }
+    let user = match valid_reset_token(state.inner(), &reset.token) {
+        Ok(user) => user,
+        Err(response) => {
+            if let Err(e) = record_address_failure(state.inner(), remote_addr.as_ref(), now) {
+                return reject(OidcErr::InternalErr(e));
+            }
+            return response;
+        }
+    };
+    if reset.password.is_empty() || reset.password != reset.password_confirmation {
+        let mut response = templates::html_response(
+            templates::RESET_PASSWORD,
+            &[
+                ("TOKEN", &escape_html(&reset.token)[..]),
+                ("MESSAGE", "The passwords do not match, please try again."),
+            ],
+        );
+        response.set_status(Status::BadRequest);
+        return response;
+    }
+
+    let hash = match password::hash_password(&reset.password) {
+        Ok(hash) => hash,
+        Err(_) => {
+            return Response::build()
+                .raw_status(500, "could not hash the password")
+                .finalize()
+        }
+    };
+    // a reset also lifts a lockout and ends the sessions of the user, the
+    // link proves the address as well, stands in for an open invitation and
+    // lets the user enroll a second factor
+    let saved = state
+        .store
+        .set_password(&user.id, &hash)
+        .and_then(|_| state.store.clear_failed_attempts(&user.id))
+        .and_then(|_| state.store.set_email_verified(&user.id, true))
+        .and_then(|_| state.store.delete_invitation(&user.id))
+        .and_then(|_| {
+            state.store.set_mfa_enrollment(&user.id, Some(now + session::MFA_ENROLLMENT_PERIOD))
+        });
+    if let Err(e) = saved {
+        return reject(OidcErr::InternalErr(e));
+    }
+    state.sessions.write().unwrap().retain(|_, session| {
+        session.authentication.as_ref().map_or(true, |authentication| {
+            authentication.subject != user.id
+        })
+    });
+    templates::html_response(
+        templates::MESSAGE,
+        &[("MESSAGE", "Your password was changed, you can log in with it now.")],
+    )
+}
+
+
+// the user whose password the token resets, if it is still valid.
+fn valid_reset_token<'r>(config: &Config, token: &str) -> Result<User, Response<'r>> {
+    let now = time::get_time().sec;
+    let verified = {
+        let keys = config.keys.current();
+        password_reset::verify(token, &keys, now)
+    };
+    let user = match verified {
+        Ok(reset) => {
+            match config.store.find_user(&reset.user_id) {
+                Ok(user) => user.and_then(|user| if reset.is_unused_for(&user) {
+                    Some(user)
+                } else {
+                    None
+                }),
+                Err(_) => return Err(database_error()),
+            }
+        }
+        Err(e) => {
+            log_failure("rejected a password reset token", &e);
+            None
+        }
+    };
+    user.ok_or_else(|| {
+        let mut response = templates::html_response(
+            templates::MESSAGE,
+            &[("MESSAGE", "This link is expired or was already used.")],
+        );
+        response.set_status(Status::BadRequest);
+        response
+    })
+}
+
+
+fn registration_enabled(config: &Config) -> bool {
+    config.registration.is_some() && config.mailer.is_some()
+}
+
+
+// self-registered users have to verify their address and may need approval.
+fn check_account_ready<'r>(config: &Config, user: &User) -> Result<(), Response<'r>> {
+    if !user.email_verified {
+        return Err(account_not_ready(
+            "Please verify your email address first, we sent you a link.",
+        ));
+    }
+    match config.store.awaits_approval(&user.id) {
+        Ok(true) => Err(account_not_ready("Your account awaits approval by an administrator.")),
+        Ok(false) => Ok(()),
+        Err(e) => Err(reject(OidcErr::InternalErr(e))),
+    }
+}
+
+
+fn account_not_ready<'r>(message: &str) -> Response<'r> {
+    let mut response = templates::html_response(templates::MESSAGE, &[("MESSAGE", message)]);
+    response.set_status(Status::Forbidden);
+    response
+}
+
+
+#[get("/register")]
+pub fn registration_form<'r>(state: State<Config>) -> Response<'r> {
+    if !registration_enabled(state.inner()) {
+        return Response::build().status(Status::NotFound).finalize();
+    }
+    templates::html_response(
+        templates::REGISTER,
+        &[
+            ("MESSAGE", "Create an account, we send you a link to verify your address."),
+            ("EMAIL", ""),
+            ("NAME", ""),
+        ],
+    )
+}
+
+
+#[derive(FromForm)]
+struct Registration {
+    email: String,
+    name: String,
+    password: String,
+    password_confirmation: String,
+}
+
+
+// creates an unverified user and mails the link that verifies it. Taken
+// addresses get the same answer, without a mail.
+#[post("/register", data = "<registration_form>")]
+pub fn register<'r>(
+    registration_form: Form<Registration>,
+    state: State<Config>,
+    host: Option<RequestedHost>,
+    remote: Option<SocketAddr>,
+) -> Response<'r> {
+    let (policy, mailer) = match (state.registration.as_ref(), state.mailer.as_ref()) {
+        (Some(policy), Some(mailer)) => (policy, mailer),
+        _ => return Response::build().status(Status::NotFound).finalize(),
+    };
+    let registration = registration_form.into_inner();
+    let email = String::from(registration.email.trim());
+    let name = String::from(registration.name.trim());
+
+    // every sign up counts, so that an address can not flood mailboxes
+    let now = time::get_time().sec;
+    let remote_addr = remote.map(|address| address.ip().to_string());
+    if let Some(seconds) = mail_retry_after(state.inner(), remote_addr.as_ref(), now) {
+        return too_many_attempts(seconds);
+    }
+    if let Err(e) = record_mail_request(state.inner(), remote_addr.as_ref(), now) {
+        return reject(OidcErr::InternalErr(e));
+    }
+
+    let invalid = |message: &str| {
+        let mut response = templates::html_response(
+            templates::REGISTER,
+            &[
+                ("MESSAGE", message),
+                ("EMAIL", &escape_html(&email)[..]),
+                ("NAME", &escape_html(&name)[..]),
+            ],
+        );
+        response.set_status(Status::BadRequest);
+        response
+    };
+    if !policy.allows(&email) {
+        return invalid("Accounts can not be created for this address.");
+    }
+    if registration.password.is_empty() ||
+        registration.password != registration.password_confirmation
+    {
+        return invalid("The passwords do not match, please try again.");
+    }
+
+    let message = format!(
+        "We sent a link to {}, open it to verify your address.",
+        email
+    );
+    let message_page = templates::html_response(
+        templates::MESSAGE,
+        &[("MESSAGE", &escape_html(&message)[..])],
+    );
+    // the password is hashed for taken addresses as well, the answer takes
+    // as long either way
+    let hash = match password::hash_password(&registration.password) {
+        Ok(hash) => hash,
+        Err(_) => {
+            return Response::build()
+                .raw_status(500, "could not hash the password")
+                .finalize()
+        }
+    };
+    match state.store.find_user(&email) {
+        Ok(None) => {}
+        Ok(Some(_)) => return message_page,
+        Err(_) => return database_error(),
+    }
+    let user = User {
+        id: Uuid::new_v4().to_string(),
+        email: email.clone(),
+        password: Some(hash),
+        groups: Vec::new(),
+        profile: Profile {
+            name: if name.is_empty() { None } else { Some(name.clone()) },
+            ..Profile::default()
+        },
+        email_verified: false,
+    };
+    let saved = state.store.save_user(&user).and_then(|_| if policy.approval_required {
+        state.store.request_approval(&user.id, now)
+    } else {
+        Ok(())
+    });
+    if saved.is_err() {
+        return database_error();
+    }
+
+    let iss = issuer(state.inner(), host);
+    let token = {
+        let keys = state.keys.current();
+        registration::issue_verification(&keys, &user, &iss, now)
+    };
+    let token = match token {
+        Ok(token) => token,
+        Err(e) => {
+            log_failure("could not issue an email verification token", &e);
+            return Response::build()
+                .raw_status(500, "could not issue a token")
+                .finalize();
+        }
+    };
+    let mail = Mail {
+        to: user.email.clone(),
+        subject: String::from("Verify your email address"),
+        body: format!(
+            "Hello,\n\nan account was created for this address at {}.\n\
+             Open this link within a day to verify it:\n\n{}/register/verify?token={}\n\n\
+             If it was not you, ignore this mail.\n",
+            iss,
+            base_url(&iss),
+            token
+        ),
+    };
+    send_in_background(mailer, mail, "could not send an email verification link");
+    message_page
+}
+
+
+#[get("/register/verify?<link>")]
+pub fn verify_email<'r>(link: TokenLink, state: State<Config>) -> Response<'r> {
+    let now = time::get_time().sec;
+    let verified = {
+        let keys = state.keys.current();
+        registration::verify_verification(&link.token, &keys, now)
+    };
+    // a link for an address the user no longer has verifies nothing
+    let user = match verified {
+        Ok((user_id, email)) => {
+            match state.store.find_user(&user_id) {
+                Ok(user) => user.and_then(|user| if user.email == email {
+                    Some(user)
+                } else {
+                    None
+                }),
+                Err(_) => return database_error(),
+            }
+        }
+        Err(e) => {
+            log_failure("rejected an email verification token", &e);
+            None
+        }
+    };
+    let user = match user {
+        Some(user) => user,
+        None => {
+            let mut response = templates::html_response(
+                templates::MESSAGE,
+                &[("MESSAGE", "This link is expired or invalid.")],
+            );
+            response.set_status(Status::BadRequest);
+            return response;
+        }
+    };
+    if state.store.set_email_verified(&user.id, true).is_err() {
+        return database_error();
+    }
+    let message = match state.store.awaits_approval(&user.id) {
+        Ok(true) => "Your address is verified. An administrator has to approve your account before you can log in.",
+        Ok(false) => "Your address is verified, you can log in now.",
+        Err(_) => return database_error(),
+    };
+    templates::html_response(templates::MESSAGE, &[("MESSAGE", message)])
+}
+
+
+#[get("/invitation?<link>")]
+pub fn invitation_form<'r>(link: TokenLink, state: State<Config>) -> Response<'r> {
+    match valid_invitation(state.inner(), &link.token) {
+        Ok(invitation) => {
+            let message = format!("Choose the password for {}.", invitation.email);
+            templates::html_response(
+                templates::INVITATION,
+                &[
+                    ("TOKEN", &escape_html(&link.token)[..]),
+                    ("MESSAGE", &escape_html(&message)[..]),
+                ],
+            )
+        }
+        Err(response) => response,
+    }
+}
+
+
+// sets the first password of an invited user, which uses up the invitation.
+#[post("/invitation", data = "<acceptance_form>")]
+pub fn accept_invitation<'r>(
+    acceptance_form: Form<PasswordReset>,
+    state: State<Config>,
+    remote: Option<SocketAddr>,
+) -> Response<'r> {
+    let acceptance = acceptance_form.into_inner();
+    let now = time::get_time().sec;
+    let remote_addr = remote.map(|address| address.ip().to_string());
+    if let Some(seconds) = address_retry_after(state.inner(), remote_addr.as_ref(), now) {
+        return too_many_attempts(seconds);
+    }
+    let invitation = match valid_invitation(state.inner(), &acceptance.token) {
+        Ok(invitation) => invitation,
+        Err(response) => {
+            if let Err(e) = record_address_failure(state.inner(), remote_addr.as_ref(), now) {
+                return reject(OidcErr::InternalErr(e));
+            }
+            return response;
+        }
+    };
+    if acceptance.password.is_empty() ||
+        acceptance.password != acceptance.password_confirmation
+    {
+        let mut response = templates::html_response(
+            templates::INVITATION,
+            &[
+                ("TOKEN", &escape_html(&acceptance.token)[..]),
+                ("MESSAGE", "The passwords do not match, please try again."),
+            ],
+        );
+        response.set_status(Status::BadRequest);
+        return response;
+    }
+
+    let hash = match password::hash_password(&acceptance.password) {
+        Ok(hash) => hash,
+        Err(_) => {
+            return Response::build()
+                .raw_status(500, "could not hash the password")
+                .finalize()
+        }
+    };
+    // the link was mailed to the user, so it proves the address as well and
+    // lets the user enroll a second factor
+    let saved = state
+        .store
+        .set_password(&invitation.user_id, &hash)
+        .and_then(|_| state.store.set_email_verified(&invitation.user_id, true))
+        .and_then(|_| state.store.delete_invitation(&invitation.user_id))
+        .and_then(|_| {
+            state.store.set_mfa_enrollment(
+                &invitation.user_id,
+                Some(now + session::MFA_ENROLLMENT_PERIOD),
+            )
+        });
+    if saved.is_err() {
+        return database_error();
+    }
+    templates::html_response(
+        templates::MESSAGE,
+        &[("MESSAGE", "Your password is set, you can log in now.")],
+    )
+}
+
+
+// the open invitation the token of a link belongs to, if it has not expired.
+fn valid_invitation<'r>(config: &Config, token: &str) -> Result<Invitation, Response<'r>> {
+    let now = time::get_time().sec;
+    match config.store.find_invitation(&invitation::hash(token)) {
+        Ok(Some(invitation)) => {
+            if invitation.expires > now {
+                return Ok(invitation);
+            }
+        }
+        Ok(None) => {}
+        Err(_) => return Err(database_error()),
+    }
+    let mut response = templates::html_response(
+        templates::MESSAGE,
+        &[("MESSAGE", "This invitation is expired or invalid, ask for a new one.")],
+    );
+    response.set_status(Status::BadRequest);
+    Err(response)
+}
+
+
+fn auth_request_cookie(cookies: &mut Cookies) -> Option<authentication_request::AuthenticationRequest> {
+    cookies.get_private("auth-request").and_then(|cookie| {
+        serde_json::from_str(cookie.value()).ok()
+    })
+}
+
+
+// the id of the session whose csrf token the form carries.
+fn checked_session<'r>(
+    config: &Config,
+    cookies: &mut Cookies,
+    csrf_token: &str,
+) -> Result<String, Response<'r>> {
+    let session_id = match cookies.get_private("session") {
+        Some(session_cookie) => String::from(session_cookie.value()),
+        None => return Err(Response::build().raw_status(400, "no session").finalize()),
+    };
+    if !csrf_token_matches(config, &session_id, csrf_token) {
+        return Err(Response::build().raw_status(400, "wrong csrf token").finalize());
+    }
+    Ok(session_id)
+}
+
+
+// the user logged in with the session.
+fn session_user<'r>(config: &Config, session_id: &str) -> Result<User, Response<'r>> {
+    let now = time::get_time().sec;
+    let subject = match logged_in_subject(config, session_id, now) {
+        Some(subject) => subject,
+        None => return Err(Response::build().raw_status(401, "not logged in").finalize()),
+    };
+    match config.store.find_user(&subject) {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(Response::build().raw_status(401, "not logged in").finalize()),
+        Err(_) => Err(database_error()),
+    }
+}
+
+
+// a new challenge for the session, the next ceremony has to answer it.
+fn new_webauthn_challenge<'r>(config: &Config, session_id: &str) -> Result<String, Response<'r>> {
+    let challenge = webauthn::new_challenge().map_err(|_| {
+        Response::build()
+            .raw_status(500, "could not generate a challenge")
+            .finalize()
+    })?;
+    let now = time::get_time().sec;
+    store_session(config, session_id, now);
+    if let Some(session) = config.sessions.write().unwrap().get_mut(session_id) {
+        session.webauthn_challenge = Some(challenge.clone());
+    }
+    Ok(challenge)
+}
+
+
+// the login of the session, if it is still valid.
+fn session_authentication(config: &Config, session_id: &str, now: i64) -> Option<Authentication> {
+    let sessions = config.sessions.read().unwrap();
+    let session = sessions.get(session_id)?;
+    if session.is_expired(&config.session_policy, now) {
+        return None;
+    }
+    session.authentication.clone()
+}
+
+
+// the user the session is logged in with, if it is still valid.
+fn logged_in_subject(config: &Config, session_id: &str, now: i64) -> Option<String> {
+    session_authentication(config, session_id, now).map(|authentication| authentication.subject)
+}
+
+
+// stores the session of a visitor who starts to log in.
+fn store_session(config: &Config, session_id: &str, now: i64) {
+    config
+        .sessions
+        .write()
+        .unwrap()
+        .entry(String::from(session_id))
+        .or_insert_with(|| Session::new(session::csrf_token(session_id), now));
+}
+
+
+fn csrf_token_matches(config: &Config, session_id: &str, csrf_token: &str) -> bool {
+    let expected = match config.sessions.read().unwrap().get(session_id) {
+        Some(session) => session.csrf_token.clone(),
+        None => session::csrf_token(session_id),
+    };
+    expected.len() == csrf_token.len() &&
+        openssl::memcmp::eq(expected.as_bytes(), csrf_token.as_bytes())
+}
+
+
+fn database_error<'r>() -> Response<'r> {
+    Response::build()
+        .raw_status(500, "error while connecting to database")
+        .finalize()
+}
+
+
+// whether `authentication` is strong and recent enough for the request.
+fn login_satisfies(
+    auth_request: &authentication_request::AuthenticationRequest,
+    client: &Client,
+    authentication: &Authentication,
+    now: i64,
+) -> bool {
+    authentication.level() >= auth_request.required_level(client) &&
+        auth_request.max_age_allows(authentication.auth_time, now)
+}
+
+
+// asks for consent if the client needs it, answers the authentication request otherwise.
+fn complete_authentication<'r>(
+    config: &Config,
+    session_id: &str,
+    auth_request: &authentication_request::AuthenticationRequest,
+    authentication: &Authentication,
+    csrf_token: &str,
+    iss: String,
+) -> Response<'r> {
+    let user = match config.store.find_user(&authentication.subject) {
+        Ok(Some(user)) => user,
+        Ok(None) => return Response::build().raw_status(404, "user not found").finalize(),
+        Err(_) => return database_error(),
+    };
+    let client = match config.store.get_client(auth_request.client_id.trim()) {
+        Ok(Some(client)) => client,
+        Ok(None) => return reject(OidcErr::ClientErr("invalid client id")),
+        Err(_) => return database_error(),
+    };
+
+    if !client.first_party {
+        let scopes = auth_request.scopes();
+        // claims asked for one by one need consent as well as scopes
+        let requested_claims = claims::requested_user_claims(&auth_request.claims_request());
+        let consented = match config.store.get_consent(&user.id, &client.id) {
+            Ok(Some(consent)) => {
+                scopes.iter().all(|scope| consent.scopes.contains(scope)) &&
+                    requested_claims.iter().all(|name| {
+                        claims::scopes_cover(&consent.scopes, name) || consent.claims.contains(name)
+                    })
+            }
+            Ok(None) => false,
+            Err(_) => return database_error(),
+        };
+
+        if !consented || auth_request.prompts("consent") {
+            if auth_request.prompts("none") {
+                return error_redirect(
+                    &auth_request.redirect_uri,
+                    "consent_required",
+                    auth_request.state.as_ref(),
+                );
+            }
+            let descriptions = scopes
+                .iter()
+                .map(|scope| claims::scope_description(scope))
+                .chain(
+                    requested_claims
+                        .iter()
+                        .filter(|name| !claims::scopes_cover(&scopes, name))
+                        .map(|name| claims::claim_description(name)),
+                );
+            let scope_list: Vec<String> = descriptions
+                .map(|description| {
+                    format!("              <li>{}</li>", escape_html(description))
+                })
+                .collect();
+            let request_string = serde_json::to_string(auth_request).unwrap();
+            if let Some(session) = config.sessions.write().unwrap().get_mut(session_id) {
+                session.pending_consent = Some(request_string);
+            }
+            return templates::html_response(
+                templates::CONSENT,
+                &[
+                    ("CORS-TOKEN", csrf_token),
+                    ("CLIENT", &escape_html(&client.name)[..]),
+                    ("SCOPES", &scope_list.join("\n")[..]),
+                ],
+            );
+        }
+    }
+
+    authentication_response(config, auth_request, &client, &user, authentication, iss)
+}
+
+
+#[derive(FromForm)]
+struct ConsentDecision {
+    csrf_token: String,
+    decision: String,
+}
+
+
+#[post("/consent", data = "<consent_form>")]
+pub fn consent<'r>(
+    consent_form: Form<ConsentDecision>,
+    state: State<Config>,
+    host: RequestedHost,
+    mut cookies: Cookies,
+) -> Response<'r> {
+    let decision = consent_form.into_inner();
+
+    let auth_request = match auth_request_cookie(&mut cookies) {
+        Some(auth_request) => auth_request,
+        None => {
+            return Response::build()
+                .raw_status(400, "auth-request cookie not present")
+                .finalize()
+        }
+    };
+    let session_id = match checked_session(state.inner(), &mut cookies, &decision.csrf_token) {
+        Ok(session_id) => session_id,
+        Err(response) => return response,
+    };
+
+    // the decision counts for the request the consent page was shown for only
+    let asked = state.sessions.write().unwrap().get_mut(&session_id).and_then(|session| {
+        session.pending_consent.take()
+    });
+    if asked != Some(serde_json::to_string(&auth_request).unwrap()) {
+        return Response::build().raw_status(400, "consent was not asked for").finalize();
+    }
+    let now = time::get_time().sec;
+    let authentication = match session_authentication(state.inner(), &session_id, now) {
+        Some(authentication) => authentication,
+        None => return Response::build().raw_status(400, "not logged in").finalize(),
+    };
+
+    if decision.decision != "allow" {
+        return error_redirect(
+            &auth_request.redirect_uri,
+            "access_denied",
+            auth_request.state.as_ref(),
+        );
+    }
+
+    let user = match state.store.find_user(&authentication.subject) {
+        Ok(Some(user)) => user,
+        Ok(None) => return Response::build().raw_status(404, "user not found").finalize(),
+        Err(_) => return database_error(),
+    };
+    let client = match state.store.get_client(auth_request.client_id.trim()) {
+        Ok(Some(client)) => client,
+        Ok(None) => return reject(OidcErr::ClientErr("invalid client id")),
+        Err(_) => return database_error(),
+    };
+    // the login may have aged or the client may ask for more by now
+    if !login_satisfies(&auth_request, &client, &authentication, now) {
+        return error_redirect(
+            &auth_request.redirect_uri,
+            "login_required",
+            auth_request.state.as_ref(),
+        );
+    }
+
+    // remember everything the user ever agreed to for this client, claims
+    // asked for one by one as far as the scopes don't release them
+    let mut scopes = auth_request.scopes();
+    let mut granted_claims: Vec<String> =
+        claims::requested_user_claims(&auth_request.claims_request())
+            .into_iter()
+            .filter(|name| !claims::scopes_cover(&scopes, name))
+            .collect();
+    if let Ok(Some(previous)) = state.store.get_consent(&user.id, &client.id) {
+        for scope in previous.scopes {
+            if !scopes.contains(&scope) {
+                scopes.push(scope);
+            }
+        }
+        for name in previous.claims {
+            if !granted_claims.contains(&name) {
+                granted_claims.push(name);
+            }
+        }
+    }
+    scopes.sort();
+    granted_claims.sort();
+    let consent = Consent {
+        user_id: user.id.clone(),
+        client_id: client.id.clone(),
+        scopes: scopes,
+        claims: granted_claims,
+        granted_at: now,
+    };
+    if state.store.save_consent(&consent).is_err() {
+        return database_error();
+    }
+
+    let iss = issuer(state.inner(), Some(host));
+    authentication_response(state.inner(), &auth_request, &client, &user, &authentication, iss)
+}
+
+
+// answers a successful authentication request, either with a code or
+// with the id token itself.
+fn authentication_response<'r>(
+    config: &Config,
+    auth_request: &authentication_request::AuthenticationRequest,
+    client: &Client,
+    user: &User,
+    authentication: &Authentication,
+    iss: String,
+) -> Response<'r> {
+    let now = time::get_time().sec;
+    let scopes = auth_request.scopes();
+    let lifetimes = config.lifetimes.for_client(client);
+
+    let claims_request = auth_request.claims_request();
+    let mut claims = claims::scope_claims(user, &scopes);
+    claims.extend(claims::requested_claims(user, &claims_request.id_token));
+    claims.insert(String::from("iss"), json!(iss));
+    claims.insert(
+        String::from("sub"),
+        json!(subject::subject_for(client, &user.id, &config.pairwise_secret)),
+    );
+    claims.insert(String::from("aud"), json!([auth_request.client_id]));
+    claims.insert(String::from("iat"), json!(now));
+    claims.insert(String::from("exp"), json!(now + lifetimes.id_token));
+    claims.insert(String::from("auth_time"), json!(authentication.auth_time));
+    claims.insert(String::from("acr"), json!(authentication.level().acr()));
+    claims.insert(String::from("amr"), json!(authentication.amr));
+    if let Some(ref nonce) = auth_request.nonce {
+        claims.insert(String::from("nonce"), json!(nonce));
+    }
+
+    let keys = config.keys.current();
+    let alg = keys.algorithm_for(client);
+    let signing_key = match keys.signing_key(alg) {
+        Some(key) => key,
+        None => {
+            return Response::build()
+                .raw_status(500, "no key for signing algorithm")
+                .finalize()
+        }
+    };
+    // keys on a PKCS#11 token may fail to sign, e.g. when it is removed
+    let signed = match jwt::sign(&claims, signing_key, alg) {
+        Ok(signed) => signed,
+        Err(e) => {
+            println!("could not sign id token for {}: {}", client.name, e);
+            return Response::build()
+                .raw_status(500, "could not sign id token")
+                .finalize();
+        }
+    };
+
+    // nested, the signed token is wrapped in a JWE for clients that ask for it
+    let encryption = &client.encryption;
+    let encrypted = jwe::encrypt_for(
+        signed.as_bytes(),
+        encryption.key.as_ref(),
+        encryption.id_token_encrypted_response_alg.as_ref(),
+        encryption.id_token_encrypted_response_enc.as_ref(),
+        Some("JWT"),
+    );
+    let jwt = match encrypted {
+        Ok(Some(encrypted)) => encrypted,
+        Ok(None) => signed,
+        Err(e) => {
+            println!("could not encrypt id token for {}: {}", client.name, e);
+            return Response::build()
+                .raw_status(500, "could not encrypt id token")
+                .finalize();
+        }
+    };
+
+    if auth_request.response_type == "code" {
+        let code = Uuid::new_v4().simple().to_string();
+        let mut codes = config.codes.write().expect(
+            "could not aquire lock on code map",
+        );
+        codes.insert(
+            code.clone(),
+            CodeGrant {
+                client_id: auth_request.client_id.clone(),
+                redirect_uri: auth_request.redirect_uri.clone(),
+                subject: authentication.subject.clone(),
+                scopes: scopes,
+                userinfo_claims: claims_request.userinfo,
+                id_token: jwt,
+                code_challenge: auth_request.code_challenge.clone(),
+                expires: now + lifetimes.code,
+            },
+        );
+        redirect_with(
+            &auth_request.redirect_uri,
+            &[("code", &code[..])],
+            auth_request.state.as_ref(),
+        )
+    } else {
+        //implicit flow, return token directly to callback
+        redirect_with(
+            &auth_request.redirect_uri,
+            &[
+                ("token_type", "bearer"),
+                ("id_token", &jwt[..]),
+                ("expires_in", &lifetimes.access_token.to_string()[..]),
+            ],
+            auth_request.state.as_ref(),
+        )
+    }
+}
+
+
+
+#[derive(FromForm)]
+struct TokenRequest {
+    grant_type: String,
+    code: Option<String>,
+    redirect_uri: Option<String>,
+    refresh_token: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    code_verifier: Option<String>,
+}
+
+
+// client credentials sent with http basic authentication.
+struct BasicCredentials {
+    client_id: String,
+    client_secret: String,
+}
+
+
+impl<'a, 'r> FromRequest<'a, 'r> for BasicCredentials {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<BasicCredentials, ()> {
+        let header = match request.headers().get_one("Authorization") {
+            Some(header) if header.starts_with("Basic ") => &header[6..],
+            _ => return Outcome::Forward(()),
+        };
+        let decoded = match base64::decode(header.trim()).ok().and_then(
+            |raw| String::from_utf8(raw).ok(),
+        ) {
+            Some(decoded) => decoded,
+            None => return Outcome::Failure((Status::BadRequest, ())),
+        };
+        let mut parts = decoded.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(id), Some(secret)) => {
+                Outcome::Success(BasicCredentials {
+                    client_id: form_decode(id),
+                    client_secret: form_decode(secret),
+                })
+            }
+            _ => Outcome::Failure((Status::BadRequest, ())),
+        }
+    }
+}
+
+
+fn form_decode(raw: &str) -> String {
+    url::form_urlencoded::parse(format!("v={}", raw).as_bytes())
+        .next()
+        .map(|(_, value)| value.into_owned())
+        .unwrap_or_default()
+}
+
+
+struct BearerToken(String);
+
+
+impl<'a, 'r> FromRequest<'a, 'r> for BearerToken {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<BearerToken, ()> {
+        match request.headers().get_one("Authorization") {
+            Some(header) if header.starts_with("Bearer ") => {
+                Outcome::Success(BearerToken(header[7..].trim().to_string()))
+            }
+            _ => Outcome::Forward(()),
+        }
+    }
+}
+
+
+fn json_response<'r>(status: Status, body: serde_json::Value) -> Response<'r> {
+    Response::build()
+        .status(status)
+        .header(ContentType::JSON)
+        .raw_header("Cache-Control", "no-store")
+        .raw_header("Pragma", "no-cache")
+        .sized_body(Cursor::new(body.to_string()))
+        .finalize()
+}
+
+
+fn token_error<'r>(status: Status, error: &str) -> Response<'r> {
+    json_response(status, json!({ "error": error }))
+}
+
+
+fn client_throttled<'r>(retry_after: i64) -> Response<'r> {
+    let mut response = token_error(Status::TooManyRequests, "invalid_client");
+    response.set_raw_header("Retry-After", retry_after.to_string());
+    response
+}
+
+
+#[post("/token", data = "<token_form>")]
+pub fn token<'r>(
+    token_form: Form<TokenRequest>,
+    credentials: Option<BasicCredentials>,
+    remote: Option<SocketAddr>,
+    state: State<Config>,
+) -> Response<'r> {
+    let token_request = token_form.into_inner();
+
+    if token_request.grant_type != "authorization_code" &&
+        token_request.grant_type != "refresh_token"
+    {
+        return token_error(Status::BadRequest, "unsupported_grant_type");
+    }
+
+    let (client_id, client_secret) = match credentials {
+        Some(credentials) => (credentials.client_id, Some(credentials.client_secret)),
+        None => {
+            match token_request.client_id {
+                Some(client_id) => (client_id, token_request.client_secret),
+                None => return token_error(Status::Unauthorized, "invalid_client"),
+            }
+        }
+    };
+
+    let now = time::get_time().sec;
+    let remote_addr = remote.map(|address| address.ip().to_string());
+    if let Some(seconds) = address_retry_after(state.inner(), remote_addr.as_ref(), now) {
+        return client_throttled(seconds);
+    }
+
+    let client = match state.store.get_client(&client_id) {
+        Ok(Some(client)) => client,
+        Ok(None) => {
+            return match record_address_failure(state.inner(), remote_addr.as_ref(), now) {
+                Ok(_) => token_error(Status::Unauthorized, "invalid_client"),
+                Err(_) => token_error(Status::InternalServerError, "server_error"),
+            };
+        }
+        Err(_) => return token_error(Status::InternalServerError, "server_error"),
+    };
+
+    if let Some(ref secret_hash) = client.secret {
+        let client_key = throttle::client_key(&client.name);
+        if let Some(seconds) = state.throttle.retry_after(&client_key, now) {
+            return client_throttled(seconds);
+        }
+        let authenticated = match client_secret {
+            Some(ref secret) => verify_secret(secret, secret_hash),
+            None => false,
+        };
+        if !authenticated {
+            let recorded = record_address_failure(state.inner(), remote_addr.as_ref(), now)
+                .and_then(|_| {
+                    state.throttle.record_failure(
+                        &*state.store,
+                        &client_key,
+                        state.throttle.policy.account_threshold,
+                        remote_addr.as_ref().map(|address| &address[..]),
+                        now,
+                    )
+                });
+            return match recorded {
+                Ok(_) => token_error(Status::Unauthorized, "invalid_client"),
+                Err(_) => token_error(Status::InternalServerError, "server_error"),
+            };
+        }
+        state.throttle.clear(&client_key);
+    }
+
+    let lifetimes = state.lifetimes.for_client(&client);
+
+    if token_request.grant_type == "refresh_token" {
+        let refresh_token = match token_request.refresh_token {
+            Some(refresh_token) => refresh_token,
+            None => return token_error(Status::BadRequest, "invalid_request"),
+        };
+        // refresh tokens are used once, the new one expires with the old one
+        let grant = match state.refresh_tokens.write().unwrap().remove(&refresh_token) {
+            Some(grant) => grant,
+            None => return token_error(Status::BadRequest, "invalid_grant"),
+        };
+        if grant.client_id != client.name || grant.expires < now {
+            return token_error(Status::BadRequest, "invalid_grant");
+        }
+        return issue_tokens(state.inner(), &lifetimes, grant, None);
+    }
+
+    let code = match token_request.code {
+        Some(code) => code,
+        None => return token_error(Status::BadRequest, "invalid_request"),
+    };
+
+    let grant = match state.codes.write().unwrap().remove(&code) {
+        Some(grant) => grant,
+        None => return token_error(Status::BadRequest, "invalid_grant"),
+    };
+
+    if grant.client_id != client.name || grant.expires < now ||
+        token_request.redirect_uri.as_ref() != Some(&grant.redirect_uri)
+    {
+        return token_error(Status::BadRequest, "invalid_grant");
+    }
+    // public clients have no secret, their codes are only redeemed with the
+    // verifier of the challenge they sent along with the authentication request
+    let verified = match grant.code_challenge {
+        Some(ref challenge) => {
+            code_verifier_matches(token_request.code_verifier.as_ref(), challenge)
+        }
+        None => client.secret.is_some(),
+    };
+    if !verified {
+        return token_error(Status::BadRequest, "invalid_grant");
+    }
+
+    // refresh tokens come with offline access only, OpenID Connect Core
+    // section 11, the grant has expired at once otherwise
+    let offline = grant.scopes.iter().any(|scope| scope == "offline_access");
+    let refresh_grant = AccessGrant {
+        client_id: grant.client_id,
+        subject: grant.subject,
+        scopes: grant.scopes,
+        userinfo_claims: grant.userinfo_claims,
+        expires: if offline { now + lifetimes.refresh_token } else { now },
+    };
+    issue_tokens(state.inner(), &lifetimes, refresh_grant, Some(grant.id_token))
+}
+
+
+// whether `verifier` is the one the S256 PKCE `challenge` was derived from.
+fn code_verifier_matches(verifier: Option<&String>, challenge: &str) -> bool {
+    let verifier = match verifier {
+        Some(verifier) => verifier,
+        None => return false,
+    };
+    let digest = openssl::sha::sha256(verifier.as_bytes());
+    let computed = base64::encode_config(&digest, base64::URL_SAFE_NO_PAD);
+    computed.len() == challenge.len() &&
+        openssl::memcmp::eq(computed.as_bytes(), challenge.as_bytes())
+}
+
+
+// answers the token request with a new access token and, while the refresh
+// grant has not expired, a refresh token carrying it.
+fn issue_tokens<'r>(
+    config: &Config,
+    lifetimes: &Lifetimes,
+    refresh_grant: AccessGrant,
+    id_token: Option<String>,
+) -> Response<'r> {
+    let now = time::get_time().sec;
+    let access_token = Uuid::new_v4().simple().to_string();
+    let mut access_grant = refresh_grant.clone();
+    access_grant.expires = now + lifetimes.access_token;
+    config.access_tokens.write().unwrap().insert(
+        access_token.clone(),
+        access_grant,
+    );
+
+    let mut response = json!({
+        "access_token": access_token,
+        "token_type": "Bearer",
+        "expires_in": lifetimes.access_token,
+    });
+    if refresh_grant.expires > now {
+        let refresh_token = Uuid::new_v4().simple().to_string();
+        config.refresh_tokens.write().unwrap().insert(
+            refresh_token.clone(),
+            refresh_grant,
+        );
+        response["refresh_token"] = json!(refresh_token);
+    }
+    if let Some(id_token) = id_token {
+        response["id_token"] = json!(id_token);
+    }
+    json_response(Status::Ok, response)
+}
+
+
+#[get("/userinfo")]
+pub fn userinfo<'r>(token: Option<BearerToken>, state: State<Config>) -> Response<'r> {
+    userinfo_response(token, state.inner())
+}
+
+
+#[post("/userinfo")]
+pub fn userinfo_post<'r>(token: Option<BearerToken>, state: State<Config>) -> Response<'r> {
+    userinfo_response(token, state.inner())
+}
+
+
+fn userinfo_response<'r>(token: Option<BearerToken>, config: &Config) -> Response<'r> {
+    let invalid_token = || {
+        Response::build()
+            .status(Status::Unauthorized)
+            .raw_header("WWW-Authenticate", "Bearer error=\"invalid_token\"")
             .finalize()
+    };
+
+    let (client_id, subject, scopes, requested) = {
+        let access_tokens = config.access_tokens.read().unwrap();
+        match token.and_then(|token| access_tokens.get(&token.0)) {
+            Some(grant) if grant.expires > time::get_time().sec => {
+                (
+                    grant.client_id.clone(),
+                    grant.subject.clone(),
+                    grant.scopes.clone(),
+                    grant.userinfo_claims.clone(),
+                )
+            }
+            _ => return invalid_token(),
+        }
+    };
+
+    let user = match config.store.find_user(&subject) {
+        Ok(Some(user)) => user,
+        Ok(None) => return invalid_token(),
+        Err(_) => return token_error(Status::InternalServerError, "server_error"),
+    };
+
+    let client = match config.store.get_client(&client_id) {
+        Ok(Some(client)) => client,
+        Ok(None) => return invalid_token(),
+        Err(_) => return token_error(Status::InternalServerError, "server_error"),
+    };
+
+    let mut claims = claims::scope_claims(&user, &scopes);
+    claims.extend(claims::requested_claims(&user, &requested));
+    claims.insert(
+        String::from("sub"),
+        json!(subject::subject_for(&client, &user.id, &config.pairwise_secret)),
+    );
+    let claims = serde_json::Value::Object(claims);
+    let encryption = &client.encryption;
+    let encrypted = jwe::encrypt_for(
+        claims.to_string().as_bytes(),
+        encryption.key.as_ref(),
+        encryption.userinfo_encrypted_response_alg.as_ref(),
+        encryption.userinfo_encrypted_response_enc.as_ref(),
+        None,
+    );
+    match encrypted {
+        Ok(Some(encrypted)) => {
+            Response::build()
+                .header(ContentType::new("application", "jwt"))
+                .raw_header("Cache-Control", "no-store")
+                .raw_header("Pragma", "no-cache")
+                .sized_body(Cursor::new(encrypted))
+                .finalize()
+        }
+        Ok(None) => json_response(Status::Ok, claims),
+        Err(e) => {
+            println!("could not encrypt userinfo for {}: {}", client.name, e);
+            token_error(Status::InternalServerError, "server_error")
+        }
     }
 }
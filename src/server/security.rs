@@ -0,0 +1,116 @@
+//! Browser facing hardening: security headers on every response, the
+//! attributes of the private cookies and the same origin check of forms.
+
+use rocket::{Request, Response};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Cookie, SameSite};
+use url::Url;
+
+// pages load their stylesheet and script from the server only, no inline
+// code, and must not be framed by other sites.
+const CONTENT_SECURITY_POLICY: &str = "default-src 'none'; script-src 'self'; style-src 'self'; \
+                                       img-src 'self' data:; connect-src 'self'; \
+                                       base-uri 'none'; frame-ancestors 'none'";
+
+/// How the server is reached by browsers.
+#[derive(Clone)]
+pub struct SecurityPolicy {
+    /// served over https, directly or through a proxy. Cookies are marked
+    /// secure and HSTS is sent only then.
+    pub https: bool,
+    /// seconds browsers keep to https once they saw the server, 0 sends no HSTS.
+    pub hsts_max_age: i64,
+    /// SameSite attribute of the session and auth-request cookies.
+    pub same_site: SameSite,
+}
+
+impl Default for SecurityPolicy {
+    fn default() -> SecurityPolicy {
+        SecurityPolicy {
+            https: true,
+            hsts_max_age: 365 * 24 * 60 * 60,
+            same_site: SameSite::Lax,
+        }
+    }
+}
+
+impl SecurityPolicy {
+    /// A private cookie with the configured attributes.
+    pub fn cookie(&self, name: &'static str, value: String) -> Cookie<'static> {
+        Cookie::build(name, value)
+            .path("/")
+            .http_only(true)
+            .secure(self.https)
+            .same_site(self.same_site)
+            .finish()
+    }
+
+    /// Whether a form posted with these Origin and Referer headers comes from
+    /// a page of the server at `base_url`. Browsers that send neither are let
+    /// through, the csrf token still protects them.
+    pub fn is_same_origin(&self, base_url: &str, origin: Option<&str>, referer: Option<&str>) -> bool {
+        let expected = match Url::parse(base_url) {
+            Ok(url) => url,
+            Err(_) => return false,
+        };
+        let sent = match (origin, referer) {
+            // privacy settings may send a null origin, the referer tells more then
+            (Some(origin), _) if origin != "null" => origin,
+            (_, Some(referer)) => referer,
+            (Some(_), None) => return false,
+            (None, None) => return true,
+        };
+        let sent = match Url::parse(sent) {
+            Ok(url) => url,
+            Err(_) => return false,
+        };
+        // without https the server may be reached on plain http as well
+        let scheme_matches = sent.scheme() == expected.scheme() ||
+            (!self.https && sent.scheme() == "http");
+        scheme_matches && sent.host_str() == expected.host_str() &&
+            sent.port_or_known_default() == expected.port_or_known_default()
+    }
+}
+
+
+/// Adds the security headers to every response. Responses that set their
+/// own Cache-Control keep it, all others must not be stored, as they may
+/// carry codes, tokens or csrf tokens.
+pub struct SecurityHeaders {
+    hsts: Option<String>,
+}
+
+impl SecurityHeaders {
+    pub fn new(policy: &SecurityPolicy) -> SecurityHeaders {
+        SecurityHeaders {
+            hsts: if policy.https && policy.hsts_max_age > 0 {
+                Some(format!("max-age={}", policy.hsts_max_age))
+            } else {
+                None
+            },
+        }
+    }
+}
+
+impl Fairing for SecurityHeaders {
+    fn info(&self) -> Info {
+        Info {
+            name: "Security Headers",
+            kind: Kind::Response,
+        }
+    }
+
+    fn on_response(&self, _request: &Request, response: &mut Response) {
+        response.set_raw_header("Content-Security-Policy", CONTENT_SECURITY_POLICY);
+        response.set_raw_header("X-Frame-Options", "DENY");
+        response.set_raw_header("X-Content-Type-Options", "nosniff");
+        response.set_raw_header("Referrer-Policy", "same-origin");
+        if !response.headers().contains("Cache-Control") {
+            response.set_raw_header("Cache-Control", "no-store");
+            response.set_raw_header("Pragma", "no-cache");
+        }
+        if let Some(ref hsts) = self.hsts {
+            response.set_raw_header("Strict-Transport-Security", hsts.clone());
+        }
+    }
+}
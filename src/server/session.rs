@@ -0,0 +1,101 @@
+use base64;
+use openssl;
+use server::acr::AuthLevel;
+
+/// Server side state of a browser session, referenced by the private
+/// `session` cookie. Sessions are stored once a visitor starts to log in,
+/// showing the login form leaves nothing behind.
+pub struct Session {
+    /// anti csrf token of the forms shown to this session.
+    pub csrf_token: String,
+    /// set once a user logged in with this session.
+    pub authentication: Option<Authentication>,
+    /// a login that still lacks its second factor.
+    pub pending: Option<PendingLogin>,
+    /// challenge of the WebAuthn ceremony in progress, used once.
+    pub webauthn_challenge: Option<String>,
+    /// recovery codes generated for the user, shown on the next page only.
+    pub fresh_recovery_codes: Option<Vec<String>>,
+    /// the authentication request the consent page was shown for, only a
+    /// decision on it is taken.
+    pub pending_consent: Option<String>,
+    pub created: i64,
+    pub last_seen: i64,
+}
+
+/// Who authenticated, how and when.
+#[derive(Clone)]
+pub struct Authentication {
+    /// id of the user, clients see it as computed by server::subject.
+    pub subject: String,
+    /// authentication method references as defined in RFC 8176.
+    pub amr: Vec<String>,
+    pub auth_time: i64,
+    /// the address the login came from, if known.
+    pub remote_addr: Option<String>,
+}
+
+/// A user who passed the password check and has to present a second factor.
+#[derive(Clone)]
+pub struct PendingLogin {
+    pub subject: String,
+    /// a secret shown for enrollment, stored once a code for it is verified.
+    pub totp_secret: Option<String>,
+    /// recovery codes shown with the enrollment, stored along with the secret.
+    pub recovery_codes: Vec<String>,
+}
+
+/// Seconds a user may enroll a first second factor at login after an
+/// invitation or password reset link was used, or after an administrator
+/// reset or required one. Otherwise only a browser the user is logged in
+/// with already may enroll, a stolen password alone must not be enough to
+/// set up the second factor.
+pub const MFA_ENROLLMENT_PERIOD: i64 = 7 * 24 * 60 * 60;
+
+/// How long sessions are kept, in seconds.
+pub struct SessionPolicy {
+    /// maximum age of a login, regardless of activity.
+    pub lifetime: i64,
+    /// sessions that are not used for this long are dropped.
+    pub idle_timeout: i64,
+}
+
+/// The anti csrf token of the session `session_id`. It is derived from the
+/// id, which only the encrypted cookie carries, so the forms of sessions
+/// that are not stored yet can be checked and it stays the same as long
+/// as the session.
+pub fn csrf_token(session_id: &str) -> String {
+    let digest = openssl::sha::sha256(format!("csrf:{}", session_id).as_bytes());
+    base64::encode_config(&digest, base64::URL_SAFE_NO_PAD)
+}
+
+impl Session {
+    pub fn new(csrf_token: String, now: i64) -> Session {
+        Session {
+            csrf_token: csrf_token,
+            authentication: None,
+            pending: None,
+            webauthn_challenge: None,
+            fresh_recovery_codes: None,
+            pending_consent: None,
+            created: now,
+            last_seen: now,
+        }
+    }
+
+    pub fn is_expired(&self, policy: &SessionPolicy, now: i64) -> bool {
+        if now - self.last_seen > policy.idle_timeout {
+            return true;
+        }
+        match self.authentication {
+            Some(ref authentication) => now - authentication.auth_time > policy.lifetime,
+            None => now - self.created > policy.lifetime,
+        }
+    }
+}
+
+impl Authentication {
+    pub fn level(&self) -> AuthLevel {
+        AuthLevel::achieved_by(&self.amr)
+    }
+}
@@ -0,0 +1,59 @@
+use openssl::pkey::{PKey, Private, Public};
+use server::jwt::{self, JwtError};
+use server::keys::KeyError;
+use std::fs;
+use std::io::prelude::*;
+use std::path::Path;
+
+#[cfg(feature = "pkcs11")]
+pub mod pkcs11;
+
+/// Creates the signatures of issued tokens. The private key may live
+/// outside of this process, e.g. on a hardware token or in a remote key
+/// management service, only its public key has to be known.
+pub trait Signer: Send + Sync {
+    /// The public key, published in the jwks and used to verify tokens.
+    fn public_key(&self) -> &PKey<Public>;
+
+    /// The JWS signature of `input` with `alg`, as it goes into the token.
+    fn sign(&self, alg: &str, input: &[u8]) -> Result<Vec<u8>, JwtError>;
+}
+
+
+/// A private key held in memory, kept as PKCS#8 pem file.
+pub struct FileSigner {
+    key: PKey<Private>,
+    public_key: PKey<Public>,
+}
+
+impl FileSigner {
+    pub fn new(key: PKey<Private>) -> Result<FileSigner, KeyError> {
+        let public_key = PKey::public_key_from_der(&key.public_key_to_der()?)?;
+        Ok(FileSigner {
+            key: key,
+            public_key: public_key,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<FileSigner, KeyError> {
+        let mut content = Vec::new();
+        fs::File::open(path)?.read_to_end(&mut content)?;
+        FileSigner::new(PKey::private_key_from_pem(&content)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), KeyError> {
+        let mut key_file = fs::File::create(path)?;
+        key_file.write_all(&self.key.private_key_to_pem_pkcs8()?)?;
+        Ok(())
+    }
+}
+
+impl Signer for FileSigner {
+    fn public_key(&self) -> &PKey<Public> {
+        &self.public_key
+    }
+
+    fn sign(&self, alg: &str, input: &[u8]) -> Result<Vec<u8>, JwtError> {
+        jwt::sign_bytes(&self.key, alg, input)
+    }
+}
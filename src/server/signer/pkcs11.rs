@@ -0,0 +1,215 @@
+//! Signing keys kept on a PKCS#11 token like a hardware security module,
+//! or SoftHSM for testing. The private keys never leave the token.
+
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::ec::{EcGroup, EcKey, EcPoint};
+use openssl::hash::{hash, MessageDigest};
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Public};
+use openssl::rsa::Rsa;
+use pkcs11::Ctx;
+use pkcs11::errors::Error as Pkcs11Error;
+use pkcs11::types::*;
+use server::jwt::JwtError;
+use server::keys::KeyError;
+use server::signer::Signer;
+use std::mem;
+use std::ptr;
+use std::sync::{Arc, Mutex};
+
+// DER encoded object identifiers of the curves, as found in CKA_EC_PARAMS.
+static P256_PARAMS: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+static P384_PARAMS: &[u8] = &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x22];
+
+fn token_error(err: Pkcs11Error) -> KeyError {
+    KeyError::TokenError(format!("{:?}", err))
+}
+
+fn signing_error(err: Pkcs11Error) -> JwtError {
+    JwtError::SigningFailed(format!("{:?}", err))
+}
+
+
+/// A logged in session with a token, shared by the signers of its keys.
+pub struct Pkcs11Token {
+    ctx: Ctx,
+    session: Mutex<CK_SESSION_HANDLE>,
+}
+
+// the module is initialized for use by several threads and all calls with
+// the session are serialized by the mutex.
+unsafe impl Send for Pkcs11Token {}
+unsafe impl Sync for Pkcs11Token {}
+
+impl Pkcs11Token {
+    /// Loads the PKCS#11 `module`, opens a session with the token labeled
+    /// `token_label` and logs in with the user `pin`.
+    pub fn open(module: &str, token_label: &str, pin: &str) -> Result<Arc<Pkcs11Token>, KeyError> {
+        let ctx = Ctx::new_and_initialize(module).map_err(token_error)?;
+        let mut found = None;
+        for slot in ctx.get_slot_list(true).map_err(token_error)? {
+            let info = ctx.get_token_info(slot).map_err(token_error)?;
+            // labels are padded with blanks to 32 bytes
+            if String::from_utf8_lossy(&info.label).trim_right() == token_label {
+                found = Some(slot);
+                break;
+            }
+        }
+        let slot = found.ok_or_else(|| {
+            KeyError::TokenError(format!("no token labeled {}", token_label))
+        })?;
+        let session = ctx.open_session(slot, CKF_SERIAL_SESSION, None, None)
+            .map_err(token_error)?;
+        ctx.login(session, CKU_USER, Some(pin)).map_err(token_error)?;
+        Ok(Arc::new(Pkcs11Token {
+            ctx: ctx,
+            session: Mutex::new(session),
+        }))
+    }
+
+    /// The signer of the key pair labeled `label`. Its public key is read
+    /// from the public key object with the same label.
+    pub fn signer(token: &Arc<Pkcs11Token>, label: &str) -> Result<Pkcs11Signer, KeyError> {
+        let session = token.session.lock().unwrap();
+        let private_key = token.find(*session, CKO_PRIVATE_KEY, label)?;
+        let public_object = token.find(*session, CKO_PUBLIC_KEY, label)?;
+
+        let key_type: CK_KEY_TYPE = 0;
+        let mut template = vec![CK_ATTRIBUTE::new(CKA_KEY_TYPE).with_ck_ulong(&key_type)];
+        token
+            .ctx
+            .get_attribute_value(*session, public_object, &mut template)
+            .map_err(token_error)?;
+
+        let public_key = if key_type == CKK_EC {
+            let params = token.attribute(*session, public_object, CKA_EC_PARAMS)?;
+            let (nid, size) = if params == P256_PARAMS {
+                (Nid::X9_62_PRIME256V1, 32)
+            } else if params == P384_PARAMS {
+                (Nid::SECP384R1, 48)
+            } else {
+                return Err(KeyError::UnsupportedKey);
+            };
+            let encoded = token.attribute(*session, public_object, CKA_EC_POINT)?;
+            // most modules wrap the uncompressed point in a DER octet string
+            let point = if encoded.len() == 2 * size + 3 {
+                &encoded[2..]
+            } else {
+                &encoded[..]
+            };
+            let group = EcGroup::from_curve_name(nid)?;
+            let mut ctx = BigNumContext::new()?;
+            let point = EcPoint::from_bytes(&group, point, &mut ctx)?;
+            PKey::from_ec_key(EcKey::from_public_key(&group, &point)?)?
+        } else if key_type == CKK_RSA {
+            let n = token.attribute(*session, public_object, CKA_MODULUS)?;
+            let e = token.attribute(*session, public_object, CKA_PUBLIC_EXPONENT)?;
+            let rsa = Rsa::from_public_components(BigNum::from_slice(&n)?, BigNum::from_slice(&e)?)?;
+            PKey::from_rsa(rsa)?
+        } else {
+            return Err(KeyError::UnsupportedKey);
+        };
+
+        Ok(Pkcs11Signer {
+            token: token.clone(),
+            key: private_key,
+            public_key: public_key,
+        })
+    }
+
+    // the object of `class` labeled `label`.
+    fn find(
+        &self,
+        session: CK_SESSION_HANDLE,
+        class: CK_OBJECT_CLASS,
+        label: &str,
+    ) -> Result<CK_OBJECT_HANDLE, KeyError> {
+        let label = String::from(label);
+        let template = vec![
+            CK_ATTRIBUTE::new(CKA_CLASS).with_ck_ulong(&class),
+            CK_ATTRIBUTE::new(CKA_LABEL).with_string(&label),
+        ];
+        self.ctx.find_objects_init(session, &template).map_err(
+            token_error,
+        )?;
+        let found = self.ctx.find_objects(session, 1);
+        self.ctx.find_objects_final(session).map_err(token_error)?;
+        match found.map_err(token_error)?.first() {
+            Some(object) => Ok(*object),
+            None => Err(KeyError::TokenError(format!("no key labeled {}", label))),
+        }
+    }
+
+    // the value of a variable length attribute, its size is queried first.
+    fn attribute(
+        &self,
+        session: CK_SESSION_HANDLE,
+        object: CK_OBJECT_HANDLE,
+        attribute: CK_ATTRIBUTE_TYPE,
+    ) -> Result<Vec<u8>, KeyError> {
+        let mut template = vec![CK_ATTRIBUTE::new(attribute)];
+        let length = {
+            let (_, result) = self.ctx
+                .get_attribute_value(session, object, &mut template)
+                .map_err(token_error)?;
+            result[0].ulValueLen as usize
+        };
+        let value = vec![0; length];
+        let mut template = vec![CK_ATTRIBUTE::new(attribute).with_bytes(&value)];
+        self.ctx
+            .get_attribute_value(session, object, &mut template)
+            .map_err(token_error)?;
+        Ok(value)
+    }
+}
+
+
+/// Signs with a private key on a PKCS#11 token.
+pub struct Pkcs11Signer {
+    token: Arc<Pkcs11Token>,
+    key: CK_OBJECT_HANDLE,
+    public_key: PKey<Public>,
+}
+
+impl Signer for Pkcs11Signer {
+    fn public_key(&self) -> &PKey<Public> {
+        &self.public_key
+    }
+
+    fn sign(&self, alg: &str, input: &[u8]) -> Result<Vec<u8>, JwtError> {
+        // the ECDSA mechanism signs a digest and returns r || s, which is
+        // already the JWS form of the signature.
+        let (mechanism, data) = match alg {
+            "ES256" => (CKM_ECDSA, hash(MessageDigest::sha256(), input)?.to_vec()),
+            "ES384" => (CKM_ECDSA, hash(MessageDigest::sha384(), input)?.to_vec()),
+            "RS256" => (CKM_SHA256_RSA_PKCS, input.to_vec()),
+            "PS256" => (CKM_SHA256_RSA_PKCS_PSS, input.to_vec()),
+            _ => return Err(JwtError::UnsupportedAlgorithm(String::from(alg))),
+        };
+        let mut pss_params = CK_RSA_PKCS_PSS_PARAMS {
+            hashAlg: CKM_SHA256,
+            mgf: CKG_MGF1_SHA256,
+            sLen: 32,
+        };
+        let mechanism = if mechanism == CKM_SHA256_RSA_PKCS_PSS {
+            CK_MECHANISM {
+                mechanism: mechanism,
+                pParameter: &mut pss_params as *mut CK_RSA_PKCS_PSS_PARAMS as CK_VOID_PTR,
+                ulParameterLen: mem::size_of::<CK_RSA_PKCS_PSS_PARAMS>() as CK_ULONG,
+            }
+        } else {
+            CK_MECHANISM {
+                mechanism: mechanism,
+                pParameter: ptr::null_mut(),
+                ulParameterLen: 0,
+            }
+        };
+
+        let session = self.token.session.lock().unwrap();
+        self.token
+            .ctx
+            .sign_init(*session, &mechanism, self.key)
+            .map_err(signing_error)?;
+        self.token.ctx.sign(*session, &data).map_err(signing_error)
+    }
+}
@@ -0,0 +1,82 @@
+use base64;
+use openssl::sha::sha256;
+use serde_json::{self, Value};
+use store::Client;
+use url::Url;
+
+/// Subject types clients can choose from, see section 8 of OpenID Connect Core.
+pub static SUBJECT_TYPES: &[&str] = &["public", "pairwise"];
+
+/// The `sub` of a user as seen by `client`. Public subjects are the stable
+/// user id, pairwise subjects differ between sectors so that unrelated
+/// clients can't correlate their users. They are derived with the
+/// provider's pairwise `secret`.
+pub fn subject_for(client: &Client, user_id: &str, secret: &str) -> String {
+    if client.subject_type != "pairwise" {
+        return String::from(user_id);
+    }
+    // clients are validated to have a sector, the client id is a safe fallback
+    let sector = sector_identifier(client).unwrap_or_else(|| client.id.clone());
+    // length prefixes keep the fields apart, sector "a.b" and user "c"
+    // don't hash like sector "a.bc" and an empty user
+    let mut input = String::new();
+    for field in &[&sector[..], user_id, secret] {
+        input.push_str(&format!("{}:{}", field.len(), field));
+    }
+    base64::encode_config(&sha256(input.as_bytes()), base64::URL_SAFE_NO_PAD)
+}
+
+/// The host pairwise subjects of `client` are derived from, the one of its
+/// sector identifier uri or else of its redirect urls.
+pub fn sector_identifier(client: &Client) -> Option<String> {
+    let uri = match client.sector_identifier_uri {
+        Some(ref uri) => uri,
+        None => client.redirect_urls.first()?,
+    };
+    host(uri)
+}
+
+fn host(url: &str) -> Option<String> {
+    Url::parse(url).ok().and_then(
+        |url| url.host_str().map(String::from),
+    )
+}
+
+/// Without sector identifier uri, pairwise subjects are only stable if all
+/// redirect urls share one host.
+pub fn validate_redirect_hosts(redirect_urls: &[String]) -> Result<(), &'static str> {
+    let first = match redirect_urls.first() {
+        Some(url) => host(url),
+        None => return Err("pairwise subjects require a redirect url or a sector identifier uri"),
+    };
+    if first.is_none() || redirect_urls.iter().any(|url| host(url) != first) {
+        return Err("redirect urls of different hosts require a sector identifier uri");
+    }
+    Ok(())
+}
+
+/// Checks the `document` served at `sector_identifier_uri`, a JSON array
+/// that has to contain every redirect url of the client.
+pub fn validate_sector_document(
+    sector_identifier_uri: &str,
+    document: &str,
+    redirect_urls: &[String],
+) -> Result<(), &'static str> {
+    match Url::parse(sector_identifier_uri) {
+        Ok(ref url) if url.scheme() == "https" && url.host_str().is_some() => {}
+        _ => return Err("the sector identifier uri must be an https url"),
+    }
+    let listed: Vec<String> = match serde_json::from_str(document) {
+        Ok(Value::Array(urls)) => {
+            urls.iter()
+                .filter_map(|url| url.as_str())
+                .map(String::from)
+                .collect()
+        }
+        _ => return Err("the sector identifier document must be a JSON array of urls"),
+    };
+    if redirect_urls.iter().any(|url| !listed.contains(url)) {
+        return Err("the sector identifier document does not list all redirect urls");
+    }
+    Ok(())
+}
@@ -0,0 +1,37 @@
+use rocket::Response;
+use rocket::http::ContentType;
+use std::io::Cursor;
+
+/// Served as /static/style.css and /static/webauthn.js, pages link them.
+pub static STYLE: &'static str = include_str!("style.css");
+pub static WEBAUTHN_SCRIPT: &'static str = include_str!("webauthn.js");
+
+pub static LOGIN: &'static str = include_str!("form.html");
+pub static CONSENT: &'static str = include_str!("consent.html");
+pub static SECOND_FACTOR: &'static str = include_str!("second_factor.html");
+pub static SECURITY_KEYS: &'static str = include_str!("security_keys.html");
+pub static ACCOUNT: &'static str = include_str!("account.html");
+pub static FORGOT_PASSWORD: &'static str = include_str!("forgot_password.html");
+pub static RESET_PASSWORD: &'static str = include_str!("reset_password.html");
+pub static REGISTER: &'static str = include_str!("register.html");
+pub static INVITATION: &'static str = include_str!("invitation.html");
+pub static MESSAGE: &'static str = include_str!("message.html");
+
+
+/// Fills the `{{NAME}}` placeholders of a template. Values are inserted
+/// as they are, escaping them is up to the caller.
+pub fn render(template: &str, values: &[(&str, &str)]) -> String {
+    let mut page = String::from(template);
+    for &(name, value) in values {
+        page = page.replace(&format!("{{{{{}}}}}", name), value);
+    }
+    page
+}
+
+
+pub fn html_response<'r>(template: &str, values: &[(&str, &str)]) -> Response<'r> {
+    Response::build()
+        .header(ContentType::HTML)
+        .sized_body(Cursor::new(render(template, values)))
+        .finalize()
+}
@@ -0,0 +1,195 @@
+//! Slows down guessing of passwords and client secrets. Every failure to
+//! authenticate doubles the wait before the next attempt is accepted, enough
+//! failures in a row lock out for a while. Failures are counted per account,
+//! per client and per remote address.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use store::{FailedAttempts, LockoutEvent, Store};
+use store::error::StoreError;
+
+/// Thresholds and durations, in seconds.
+pub struct ThrottlePolicy {
+    /// consecutive failures that lock an account or a client.
+    pub account_threshold: i64,
+    /// consecutive failures that lock out a remote address.
+    pub address_threshold: i64,
+    /// how long a lockout lasts, also the longest backoff.
+    pub lockout: i64,
+}
+
+impl Default for ThrottlePolicy {
+    fn default() -> ThrottlePolicy {
+        ThrottlePolicy {
+            account_threshold: 5,
+            address_threshold: 20,
+            lockout: 15 * 60,
+        }
+    }
+}
+
+impl ThrottlePolicy {
+    /// Seconds until the next attempt is accepted, None if right away.
+    pub fn retry_after(&self, attempts: &FailedAttempts, now: i64) -> Option<i64> {
+        let until = match attempts.locked_until {
+            Some(locked_until) => locked_until,
+            None if attempts.count > 0 => attempts.last_failure + self.backoff(attempts.count),
+            None => return None,
+        };
+        if until > now {
+            Some(until - now)
+        } else {
+            None
+        }
+    }
+
+    /// Counts a failure, returns true if it starts a lockout.
+    pub fn record_failure(&self, attempts: &mut FailedAttempts, threshold: i64, now: i64) -> bool {
+        // failures are forgotten after a lockout or a quiet period as long
+        let lockout_over = attempts.locked_until.map_or(false, |until| until <= now);
+        if lockout_over || now - attempts.last_failure > self.lockout {
+            *attempts = FailedAttempts::default();
+        }
+        attempts.count += 1;
+        attempts.last_failure = now;
+        if attempts.count >= threshold && attempts.locked_until.is_none() {
+            attempts.locked_until = Some(now + self.lockout);
+            return true;
+        }
+        false
+    }
+
+    // a single typo is not slowed down, from the second failure on the wait
+    // starts at one second and doubles with every further one.
+    fn backoff(&self, count: i64) -> i64 {
+        if count < 2 {
+            0
+        } else if count > 32 {
+            self.lockout
+        } else {
+            cmp::min(1 << (count - 2), self.lockout)
+        }
+    }
+}
+
+
+/// The failures of accounts are stored, so that lockouts survive restarts
+/// and can be lifted with the users unlock command. Those of clients and
+/// remote addresses are kept in memory.
+pub struct Throttle {
+    pub policy: ThrottlePolicy,
+    attempts: Mutex<HashMap<String, FailedAttempts>>,
+}
+
+pub fn user_key(user_id: &str) -> String {
+    format!("user:{}", user_id)
+}
+
+pub fn client_key(client_name: &str) -> String {
+    format!("client:{}", client_name)
+}
+
+pub fn address_key(address: &str) -> String {
+    format!("address:{}", address)
+}
+
+/// Mails sent on request of a remote address are counted apart from its
+/// failed logins.
+pub fn mail_key(address: &str) -> String {
+    format!("mail:{}", address)
+}
+
+impl Throttle {
+    pub fn new(policy: ThrottlePolicy) -> Throttle {
+        Throttle {
+            policy: policy,
+            attempts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Seconds until `key` may try again, None if right away.
+    pub fn retry_after(&self, key: &str, now: i64) -> Option<i64> {
+        let attempts = self.attempts.lock().unwrap();
+        attempts.get(key).and_then(
+            |attempts| self.policy.retry_after(attempts, now),
+        )
+    }
+
+    /// Counts a failure of `key`, a lockout it starts is recorded.
+    pub fn record_failure(
+        &self,
+        store: &Store,
+        key: &str,
+        threshold: i64,
+        remote_addr: Option<&str>,
+        now: i64,
+    ) -> Result<(), StoreError> {
+        let locked = {
+            let mut attempts = self.attempts.lock().unwrap();
+            let lockout = self.policy.lockout;
+            attempts.retain(|_, attempts| {
+                now - attempts.last_failure <= lockout || attempts.locked_until > Some(now)
+            });
+            let entry = attempts.entry(key.to_string()).or_insert_with(
+                FailedAttempts::default,
+            );
+            self.policy.record_failure(entry, threshold, now)
+        };
+        if locked {
+            record_lockout(store, key, remote_addr, now)?;
+        }
+        Ok(())
+    }
+
+    pub fn clear(&self, key: &str) {
+        self.attempts.lock().unwrap().remove(key);
+    }
+
+    /// Seconds until a login of the user is accepted again.
+    pub fn account_retry_after(
+        &self,
+        store: &Store,
+        user_id: &str,
+        now: i64,
+    ) -> Result<Option<i64>, StoreError> {
+        let attempts = store.get_failed_attempts(user_id)?;
+        Ok(self.policy.retry_after(&attempts, now))
+    }
+
+    /// Counts a failed login of the user until the next successful one.
+    pub fn record_account_failure(
+        &self,
+        store: &Store,
+        user_id: &str,
+        remote_addr: Option<&str>,
+        now: i64,
+    ) -> Result<(), StoreError> {
+        let mut attempts = store.get_failed_attempts(user_id)?;
+        let locked = self.policy.record_failure(
+            &mut attempts,
+            self.policy.account_threshold,
+            now,
+        );
+        store.save_failed_attempts(user_id, &attempts)?;
+        if locked {
+            record_lockout(store, &user_key(user_id), remote_addr, now)?;
+        }
+        Ok(())
+    }
+}
+
+
+fn record_lockout(
+    store: &Store,
+    subject: &str,
+    remote_addr: Option<&str>,
+    now: i64,
+) -> Result<(), StoreError> {
+    store.record_lockout_event(&LockoutEvent {
+        subject: String::from(subject),
+        event: String::from("locked"),
+        remote_addr: remote_addr.map(String::from),
+        at: now,
+    })
+}
@@ -0,0 +1,81 @@
+function base64urlToBytes(value) {
+  var base64 = value.replace(/-/g, '+').replace(/_/g, '/');
+  while (base64.length % 4) {
+    base64 += '=';
+  }
+  return Uint8Array.from(atob(base64), function (c) { return c.charCodeAt(0); });
+}
+
+function bytesToBase64url(buffer) {
+  var binary = String.fromCharCode.apply(null, new Uint8Array(buffer));
+  return btoa(binary).replace(/\+/g, '-').replace(/\//g, '_').replace(/=+$/, '');
+}
+
+function fetchOptions(url, form) {
+  var body = 'csrf_token=' + encodeURIComponent(form.csrf_token.value);
+  if (form.current_password) {
+    body += '&current_password=' + encodeURIComponent(form.current_password.value);
+  }
+  return fetch(url, {
+    method: 'POST',
+    credentials: 'same-origin',
+    headers: { 'Content-Type': 'application/x-www-form-urlencoded' },
+    body: body
+  }).then(function (response) {
+    if (!response.ok) {
+      throw new Error(response.statusText);
+    }
+    return response.json();
+  });
+}
+
+function decodeCredentialIds(credentials) {
+  credentials.forEach(function (credential) {
+    credential.id = base64urlToBytes(credential.id);
+  });
+}
+
+function webauthnLogin(form) {
+  fetchOptions('/webauthn/assertion-options', form).then(function (options) {
+    options.challenge = base64urlToBytes(options.challenge);
+    decodeCredentialIds(options.allowCredentials);
+    return navigator.credentials.get({ publicKey: options });
+  }).then(function (credential) {
+    form.credential_id.value = bytesToBase64url(credential.rawId);
+    form.client_data_json.value = bytesToBase64url(credential.response.clientDataJSON);
+    form.authenticator_data.value = bytesToBase64url(credential.response.authenticatorData);
+    form.signature.value = bytesToBase64url(credential.response.signature);
+    form.submit();
+  }).catch(function (error) {
+    alert(error.message);
+  });
+  return false;
+}
+
+function webauthnRegister(form) {
+  fetchOptions('/webauthn/registration-options', form).then(function (options) {
+    options.challenge = base64urlToBytes(options.challenge);
+    options.user.id = base64urlToBytes(options.user.id);
+    decodeCredentialIds(options.excludeCredentials);
+    return navigator.credentials.create({ publicKey: options });
+  }).then(function (credential) {
+    form.client_data_json.value = bytesToBase64url(credential.response.clientDataJSON);
+    form.attestation_object.value = bytesToBase64url(credential.response.attestationObject);
+    form.submit();
+  }).catch(function (error) {
+    alert(error.message);
+  });
+  return false;
+}
+
+// forms are bound here, the content security policy forbids inline handlers
+Array.prototype.forEach.call(document.querySelectorAll('form[data-webauthn]'), function (form) {
+  form.addEventListener('submit', function (event) {
+    event.preventDefault();
+    if (form.getAttribute('data-webauthn') === 'register') {
+      webauthnRegister(form);
+    } else {
+      webauthnLogin(form);
+    }
+  });
+});
@@ -0,0 +1,373 @@
+//! The relying party side of WebAuthn, for security keys and passkeys.
+//! Registrations are accepted with any attestation format but the statement
+//! is not checked, so nothing is known about the authenticator model.
+//! Credentials with ES256 or RS256 keys are supported.
+
+use {base64, openssl, serde_json, url};
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::ec::{EcGroup, EcKey, EcPoint};
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Public};
+use openssl::rsa::Rsa;
+use openssl::sha::sha256;
+use openssl::sign::Verifier;
+use serde_json::Value;
+use std::error::Error;
+use std::fmt;
+use store::{User, WebAuthnCredential};
+use utils::cbor;
+
+// COSE algorithm identifiers.
+const ES256: i64 = -7;
+const RS256: i64 = -257;
+
+// authenticator data flags.
+const USER_PRESENT: u8 = 0x01;
+const USER_VERIFIED: u8 = 0x04;
+const ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+/// Milliseconds the browser waits for the authenticator.
+const TIMEOUT: u64 = 120_000;
+
+#[derive(Debug)]
+pub enum WebAuthnError {
+    Malformed(&'static str),
+    ChallengeMismatch,
+    OriginMismatch,
+    RelyingPartyMismatch,
+    UserNotPresent,
+    UserNotVerified,
+    UnsupportedKey,
+    InvalidSignature,
+    /// the signature counter went backwards, the credential may be cloned.
+    CounterRegression,
+    OpensslError(ErrorStack),
+}
+
+impl From<ErrorStack> for WebAuthnError {
+    fn from(err: ErrorStack) -> WebAuthnError {
+        WebAuthnError::OpensslError(err)
+    }
+}
+
+impl From<cbor::CborError> for WebAuthnError {
+    fn from(_: cbor::CborError) -> WebAuthnError {
+        WebAuthnError::Malformed("invalid cbor")
+    }
+}
+
+impl Error for WebAuthnError {
+    fn description(&self) -> &str {
+        match *self {
+            WebAuthnError::Malformed(m) => m,
+            WebAuthnError::ChallengeMismatch => "wrong challenge",
+            WebAuthnError::OriginMismatch => "wrong origin",
+            WebAuthnError::RelyingPartyMismatch => "credential of another relying party",
+            WebAuthnError::UserNotPresent => "user presence was not tested",
+            WebAuthnError::UserNotVerified => "user was not verified",
+            WebAuthnError::UnsupportedKey => "unsupported credential key",
+            WebAuthnError::InvalidSignature => "invalid assertion signature",
+            WebAuthnError::CounterRegression => "signature counter went backwards",
+            WebAuthnError::OpensslError(ref err) => err.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            WebAuthnError::OpensslError(ref err) => Some(err as &Error),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for WebAuthnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WebAuthnError::OpensslError(ref err) => fmt::Display::fmt(err, f),
+            _ => f.write_str(self.description()),
+        }
+    }
+}
+
+
+pub fn encode(raw: &[u8]) -> String {
+    base64::encode_config(raw, base64::URL_SAFE_NO_PAD)
+}
+
+pub fn decode(encoded: &str) -> Result<Vec<u8>, WebAuthnError> {
+    base64::decode_config(encoded, base64::URL_SAFE_NO_PAD).map_err(|_| {
+        WebAuthnError::Malformed("invalid base64url")
+    })
+}
+
+/// A random challenge for one ceremony, base64url encoded.
+pub fn new_challenge() -> Result<String, ErrorStack> {
+    let mut challenge = [0; 32];
+    openssl::rand::rand_bytes(&mut challenge)?;
+    Ok(encode(&challenge))
+}
+
+
+/// A credential that passed registration.
+pub struct RegisteredCredential {
+    /// base64url encoded credential id.
+    pub id: String,
+    /// pem encoded public key.
+    pub public_key: String,
+    pub sign_count: i64,
+}
+
+
+/// The parts of authenticator data both ceremonies check.
+struct AuthenticatorData<'a> {
+    rp_id_hash: &'a [u8],
+    flags: u8,
+    sign_count: i64,
+    /// attested credential data and extensions, if any.
+    rest: &'a [u8],
+}
+
+fn parse_authenticator_data(data: &[u8]) -> Result<AuthenticatorData, WebAuthnError> {
+    if data.len() < 37 {
+        return Err(WebAuthnError::Malformed("authenticator data too short"));
+    }
+    let sign_count = data[33..37].iter().fold(0i64, |count, &byte| {
+        (count << 8) | byte as i64
+    });
+    Ok(AuthenticatorData {
+        rp_id_hash: &data[..32],
+        flags: data[32],
+        sign_count: sign_count,
+        rest: &data[37..],
+    })
+}
+
+
+/// The server as seen by authenticators. Credentials are scoped to the
+/// host name of the issuer, the origin of the browser has to match it.
+pub struct RelyingParty {
+    pub id: String,
+    pub origin: String,
+}
+
+impl RelyingParty {
+    pub fn for_issuer(iss: &str) -> Result<RelyingParty, WebAuthnError> {
+        let base = if iss.starts_with("https://") || iss.starts_with("http://") {
+            String::from(iss)
+        } else {
+            format!("https://{}", iss)
+        };
+        let parsed = url::Url::parse(&base).map_err(|_| {
+            WebAuthnError::Malformed("issuer is no url")
+        })?;
+        let id = parsed.host_str().map(String::from).ok_or(
+            WebAuthnError::Malformed(
+                "issuer has no host",
+            ),
+        )?;
+        Ok(RelyingParty {
+            id: id,
+            origin: parsed.origin().ascii_serialization(),
+        })
+    }
+
+    /// The `publicKey` options of `navigator.credentials.create`.
+    pub fn registration_options(
+        &self,
+        user: &User,
+        challenge: &str,
+        existing: &[WebAuthnCredential],
+    ) -> Value {
+        let exclude: Vec<Value> = existing
+            .iter()
+            .map(|credential| json!({"type": "public-key", "id": credential.id}))
+            .collect();
+        json!({
+            "rp": {"id": self.id, "name": self.id},
+            "user": {
+                "id": encode(user.id.as_bytes()),
+                "name": user.email,
+                "displayName": user.profile.name.as_ref().unwrap_or(&user.email),
+            },
+            "challenge": challenge,
+            "pubKeyCredParams": [
+                {"type": "public-key", "alg": ES256},
+                {"type": "public-key", "alg": RS256},
+            ],
+            "timeout": TIMEOUT,
+            "excludeCredentials": exclude,
+            "authenticatorSelection": {
+                "residentKey": "preferred",
+                "userVerification": "preferred",
+            },
+            "attestation": "none",
+        })
+    }
+
+    /// The `publicKey` options of `navigator.credentials.get`. Without
+    /// allowed credentials any passkey for this relying party may answer.
+    pub fn assertion_options(
+        &self,
+        challenge: &str,
+        allowed: &[WebAuthnCredential],
+        user_verification: &str,
+    ) -> Value {
+        let allow: Vec<Value> = allowed
+            .iter()
+            .map(|credential| json!({"type": "public-key", "id": credential.id}))
+            .collect();
+        json!({
+            "rpId": self.id,
+            "challenge": challenge,
+            "timeout": TIMEOUT,
+            "allowCredentials": allow,
+            "userVerification": user_verification,
+        })
+    }
+
+    // the client data has to be of the ceremony, for the challenge and from our origin.
+    fn check_client_data(
+        &self,
+        client_data_json: &[u8],
+        ceremony: &str,
+        challenge: &str,
+    ) -> Result<(), WebAuthnError> {
+        let client_data: Value = serde_json::from_slice(client_data_json).map_err(|_| {
+            WebAuthnError::Malformed("client data is no json")
+        })?;
+        if client_data["type"].as_str() != Some(ceremony) {
+            return Err(WebAuthnError::Malformed("wrong client data type"));
+        }
+        let received = client_data["challenge"].as_str().unwrap_or("");
+        if received.len() != challenge.len() ||
+            !openssl::memcmp::eq(received.as_bytes(), challenge.as_bytes())
+        {
+            return Err(WebAuthnError::ChallengeMismatch);
+        }
+        if client_data["origin"].as_str() != Some(&self.origin[..]) {
+            return Err(WebAuthnError::OriginMismatch);
+        }
+        Ok(())
+    }
+
+    fn check_authenticator_data(
+        &self,
+        data: &AuthenticatorData,
+        require_verification: bool,
+    ) -> Result<(), WebAuthnError> {
+        if data.rp_id_hash != &sha256(self.id.as_bytes())[..] {
+            return Err(WebAuthnError::RelyingPartyMismatch);
+        }
+        if data.flags & USER_PRESENT == 0 {
+            return Err(WebAuthnError::UserNotPresent);
+        }
+        if require_verification && data.flags & USER_VERIFIED == 0 {
+            return Err(WebAuthnError::UserNotVerified);
+        }
+        Ok(())
+    }
+
+    /// Checks the response to `navigator.credentials.create` for `challenge`.
+    pub fn verify_registration(
+        &self,
+        challenge: &str,
+        client_data_json: &[u8],
+        attestation_object: &[u8],
+    ) -> Result<RegisteredCredential, WebAuthnError> {
+        self.check_client_data(client_data_json, "webauthn.create", challenge)?;
+        let (attestation, _) = cbor::decode(attestation_object)?;
+        let auth_data_bytes = attestation
+            .get_text("authData")
+            .and_then(|value| value.as_bytes())
+            .ok_or(WebAuthnError::Malformed("no authenticator data"))?;
+        let auth_data = parse_authenticator_data(auth_data_bytes)?;
+        self.check_authenticator_data(&auth_data, false)?;
+        if auth_data.flags & ATTESTED_CREDENTIAL_DATA == 0 || auth_data.rest.len() < 18 {
+            return Err(WebAuthnError::Malformed("no attested credential"));
+        }
+
+        // aaguid, the length of the credential id, the id and its COSE key
+        let rest = auth_data.rest;
+        let id_len = ((rest[16] as usize) << 8) | rest[17] as usize;
+        if rest.len() < 18 + id_len {
+            return Err(WebAuthnError::Malformed("truncated credential id"));
+        }
+        let credential_id = &rest[18..18 + id_len];
+        let (cose_key, _) = cbor::decode(&rest[18 + id_len..])?;
+        let public_key = cose_public_key(&cose_key)?;
+        Ok(RegisteredCredential {
+            id: encode(credential_id),
+            public_key: String::from_utf8(public_key.public_key_to_pem()?).unwrap(), // safe unwrap, pem
+            sign_count: auth_data.sign_count,
+        })
+    }
+
+    /// Checks the response to `navigator.credentials.get` for `challenge`
+    /// made with `credential`, returns the new signature counter.
+    pub fn verify_assertion(
+        &self,
+        challenge: &str,
+        credential: &WebAuthnCredential,
+        client_data_json: &[u8],
+        authenticator_data: &[u8],
+        signature: &[u8],
+        require_verification: bool,
+    ) -> Result<i64, WebAuthnError> {
+        self.check_client_data(client_data_json, "webauthn.get", challenge)?;
+        let auth_data = parse_authenticator_data(authenticator_data)?;
+        self.check_authenticator_data(&auth_data, require_verification)?;
+
+        let public_key = PKey::public_key_from_pem(credential.public_key.as_bytes())?;
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &public_key)?;
+        verifier.update(authenticator_data)?;
+        verifier.update(&sha256(client_data_json))?;
+        if !verifier.verify(signature).unwrap_or(false) {
+            return Err(WebAuthnError::InvalidSignature);
+        }
+
+        // authenticators without a counter always report 0
+        if (auth_data.sign_count != 0 || credential.sign_count != 0) &&
+            auth_data.sign_count <= credential.sign_count
+        {
+            return Err(WebAuthnError::CounterRegression);
+        }
+        Ok(auth_data.sign_count)
+    }
+}
+
+
+// the public key of a COSE_Key (RFC 8152), EC2 on P-256 or RSA.
+fn cose_public_key(key: &cbor::Value) -> Result<PKey<Public>, WebAuthnError> {
+    let int = |label: i64| key.get_int(label).and_then(|value| value.as_integer());
+    let bytes = |label: i64| {
+        key.get_int(label).and_then(|value| value.as_bytes()).ok_or(
+            WebAuthnError::Malformed("incomplete credential key"),
+        )
+    };
+    match (int(1), int(3)) {
+        (Some(2), Some(ES256)) => {
+            if int(-1) != Some(1) {
+                return Err(WebAuthnError::UnsupportedKey);
+            }
+            let mut point = vec![0x04];
+            point.extend_from_slice(bytes(-2)?);
+            point.extend_from_slice(bytes(-3)?);
+            let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+            let mut ctx = BigNumContext::new()?;
+            let point = EcPoint::from_bytes(&group, &point, &mut ctx).map_err(|_| {
+                WebAuthnError::Malformed("invalid credential key")
+            })?;
+            Ok(PKey::from_ec_key(EcKey::from_public_key(&group, &point)?)?)
+        }
+        (Some(3), Some(RS256)) => {
+            let rsa = Rsa::from_public_components(
+                BigNum::from_slice(bytes(-1)?)?,
+                BigNum::from_slice(bytes(-2)?)?,
+            )?;
+            Ok(PKey::from_rsa(rsa)?)
+        }
+        _ => Err(WebAuthnError::UnsupportedKey),
+    }
+}
@@ -5,8 +5,56 @@ pub mod sqlite_store;
 use self::error::StoreError;
 use std::collections::HashMap;
 
+/// Logins kept in the history of each user.
+pub const KEPT_SIGN_INS: i64 = 100;
+
 pub trait Store {
     fn get_user(&self, &str, &str) -> Result<Option<User>, StoreError>;
+    /// looks up a user by id or email, including the stored password hash.
+    fn find_user(&self, reference: &str) -> Result<Option<User>, StoreError>;
+    fn update_profile(&self, reference: &str, profile: &Profile) -> Result<(), StoreError>;
+    /// replaces the password hash of a user, see utils::password.
+    fn set_password(&self, reference: &str, password: &str) -> Result<(), StoreError>;
+    /// the TOTP authenticator a user enrolled, see utils::totp.
+    fn get_totp(&self, user_id: &str) -> Result<Option<Totp>, StoreError>;
+    /// enrolls an authenticator for a user or, if None, removes it.
+    fn set_totp(&self, reference: &str, totp: Option<&Totp>) -> Result<(), StoreError>;
+    /// whether the user or one of its groups has to log in with a second factor.
+    fn requires_mfa(&self, user_id: &str) -> Result<bool, StoreError>;
+    fn set_user_mfa_required(&self, reference: &str, required: bool) -> Result<(), StoreError>;
+    fn set_group_mfa_required(&self, group_name: &str, required: bool) -> Result<(), StoreError>;
+    /// until when a user may enroll a first second factor at login, see
+    /// server::session::MFA_ENROLLMENT_PERIOD.
+    fn get_mfa_enrollment(&self, user_id: &str) -> Result<Option<i64>, StoreError>;
+    fn set_mfa_enrollment(&self, reference: &str, until: Option<i64>) -> Result<(), StoreError>;
+    /// security keys and passkeys of a user, oldest first.
+    fn get_webauthn_credentials(
+        &self,
+        user_reference: &str,
+    ) -> Result<Vec<WebAuthnCredential>, StoreError>;
+    fn get_webauthn_credential(&self, id: &str) -> Result<Option<WebAuthnCredential>, StoreError>;
+    fn save_webauthn_credential(&self, credential: &WebAuthnCredential) -> Result<(), StoreError>;
+    fn update_webauthn_sign_count(&self, id: &str, sign_count: i64) -> Result<(), StoreError>;
+    fn delete_webauthn_credential(&self, user_reference: &str, id: &str) -> Result<(), StoreError>;
+    /// replaces the recovery codes of a user by the given hashes.
+    fn set_recovery_codes(&self, reference: &str, hashes: &[String]) -> Result<(), StoreError>;
+    /// removes the recovery code with `hash`, true if the user had it.
+    fn use_recovery_code(&self, user_id: &str, hash: &str) -> Result<bool, StoreError>;
+    fn count_recovery_codes(&self, reference: &str) -> Result<i64, StoreError>;
+    fn set_email_verified(&self, reference: &str, verified: bool) -> Result<(), StoreError>;
+    /// holds back logins of a self-registered user until an admin approves.
+    fn request_approval(&self, reference: &str, now: i64) -> Result<(), StoreError>;
+    fn approve_user(&self, reference: &str) -> Result<(), StoreError>;
+    fn awaits_approval(&self, user_id: &str) -> Result<bool, StoreError>;
+    /// emails of the users awaiting approval and since when, oldest first.
+    fn get_pending_approvals(&self) -> Result<Vec<(String, i64)>, StoreError>;
+    /// invites a user or replaces the open invitation of the user.
+    fn save_invitation(&self, invitation: &Invitation) -> Result<(), StoreError>;
+    /// the invitation with the given token hash, expired or not.
+    fn find_invitation(&self, token_hash: &str) -> Result<Option<Invitation>, StoreError>;
+    /// all open invitations, oldest first.
+    fn get_invitations(&self) -> Result<Vec<Invitation>, StoreError>;
+    fn delete_invitation(&self, user_reference: &str) -> Result<(), StoreError>;
     fn get_client(&self, &str) -> Result<Option<Client>, StoreError>;
     fn save_user(&self, user: &User) -> Result<(), StoreError>;
     fn save_client(&self, client: &Client) -> Result<(), StoreError>;
@@ -22,12 +70,118 @@ pub trait Store {
 
     fn add_redirect_url(&self, reference: &str, redirect_url: &str) -> Result<(), StoreError>;
     fn remove_redirect_url(&self, reference: &str, redirect_url: &str) -> Result<(), StoreError>;
+
+    fn set_required_acr(&self, reference: &str, acr: Option<&str>) -> Result<(), StoreError>;
+    fn set_first_party(&self, reference: &str, first_party: bool) -> Result<(), StoreError>;
+    /// replaces the secret of a client with the hash of a new one.
+    fn set_client_secret(&self, reference: &str, secret_hash: &str) -> Result<(), StoreError>;
+    /// sets the algorithm id tokens for a client are signed with, None for the default.
+    fn set_signing_alg(&self, reference: &str, alg: Option<&str>) -> Result<(), StoreError>;
+    fn set_encryption(
+        &self,
+        reference: &str,
+        encryption: &EncryptionSettings,
+    ) -> Result<(), StoreError>;
+    /// sets how subject identifiers for a client are derived, see server::subject.
+    fn set_subject_type(
+        &self,
+        reference: &str,
+        subject_type: &str,
+        sector_identifier_uri: Option<&str>,
+    ) -> Result<(), StoreError>;
+    fn set_lifetimes(&self, reference: &str, lifetimes: &TokenLifetimes) -> Result<(), StoreError>;
+
+    fn get_consent(&self, user_id: &str, client_id: &str) -> Result<Option<Consent>, StoreError>;
+    fn save_consent(&self, consent: &Consent) -> Result<(), StoreError>;
+    /// consents of a user, keyed by client name.
+    fn get_consents(&self, user_reference: &str) -> Result<HashMap<String, Consent>, StoreError>;
+    /// revokes the consent given to one client or, if None, to all clients.
+    fn revoke_consent(
+        &self,
+        user_reference: &str,
+        client_reference: Option<&str>,
+    ) -> Result<(), StoreError>;
+    fn revoke_client_consents(&self, client_reference: &str) -> Result<(), StoreError>;
+
+    /// all signing keys ever created, oldest first.
+    fn get_signing_keys(&self) -> Result<Vec<SigningKeyRecord>, StoreError>;
+    fn save_signing_key(&self, key: &SigningKeyRecord) -> Result<(), StoreError>;
+    fn update_signing_key_state(
+        &self,
+        kid: &str,
+        state: &str,
+        state_changed: i64,
+    ) -> Result<(), StoreError>;
+    /// changes the state of the keys by kid and saves the new ones in one
+    /// transaction, see server::keys::rotate.
+    fn rotate_signing_keys(
+        &self,
+        state_changes: &[(String, String)],
+        new_keys: &[SigningKeyRecord],
+        state_changed: i64,
+    ) -> Result<(), StoreError>;
+
+    /// failed logins of a user since the last successful one, see server::throttle.
+    fn get_failed_attempts(&self, user_id: &str) -> Result<FailedAttempts, StoreError>;
+    fn save_failed_attempts(
+        &self,
+        user_id: &str,
+        attempts: &FailedAttempts,
+    ) -> Result<(), StoreError>;
+    /// forgets the failed logins of a user, which lifts a lockout.
+    fn clear_failed_attempts(&self, reference: &str) -> Result<(), StoreError>;
+    fn record_lockout_event(&self, event: &LockoutEvent) -> Result<(), StoreError>;
+    /// all lockout events, oldest first.
+    fn get_lockout_events(&self) -> Result<Vec<LockoutEvent>, StoreError>;
+    /// adds a login to the history of the user, the oldest beyond KEPT_SIGN_INS are dropped.
+    fn record_sign_in(&self, sign_in: &SignIn) -> Result<(), StoreError>;
+    /// the latest logins of a user, newest first.
+    fn get_sign_ins(&self, user_reference: &str, limit: i64) -> Result<Vec<SignIn>, StoreError>;
 }
 
 pub struct Client {
     pub id: String,
     pub name: String,
     pub redirect_urls: Vec<String>,
+    /// acr every authentication for this client has to reach, see server::acr.
+    pub required_acr: Option<String>,
+    /// hash of the client secret, None for public clients. Public clients
+    /// redeem their codes with PKCE.
+    pub secret: Option<String>,
+    /// first party clients are trusted, users are not asked for consent.
+    pub first_party: bool,
+    /// algorithm its id tokens are signed with, the server default if None.
+    pub id_token_signed_response_alg: Option<String>,
+    pub encryption: EncryptionSettings,
+    /// "public" or "pairwise", see server::subject.
+    pub subject_type: String,
+    /// lists the redirect urls of all clients sharing pairwise subjects with this one.
+    pub sector_identifier_uri: Option<String>,
+    pub lifetimes: TokenLifetimes,
+}
+
+
+/// How responses for a client are encrypted, see server::jwe.
+/// Nothing is encrypted while the algorithms are None.
+#[derive(Default, Clone)]
+pub struct EncryptionSettings {
+    /// pem encoded public key of the client.
+    pub key: Option<String>,
+    pub id_token_encrypted_response_alg: Option<String>,
+    pub id_token_encrypted_response_enc: Option<String>,
+    pub userinfo_encrypted_response_alg: Option<String>,
+    pub userinfo_encrypted_response_enc: Option<String>,
+}
+
+
+/// Lifetimes of the tokens issued to a client in seconds, the server
+/// defaults apply where None. See server::grants::Lifetimes.
+#[derive(Default, Clone)]
+pub struct TokenLifetimes {
+    pub id_token: Option<i64>,
+    pub access_token: Option<i64>,
+    pub refresh_token: Option<i64>,
+    pub code: Option<i64>,
 }
 
 
@@ -36,4 +190,107 @@ pub struct User {
     pub email: String,
     pub password: Option<String>,
     pub groups: Vec<String>,
+    pub profile: Profile,
+    /// users who registered themselves can not log in before they verified their email.
+    pub email_verified: bool,
+}
+
+/// A TOTP authenticator of a user.
+pub struct Totp {
+    /// base32 encoded shared secret.
+    pub secret: String,
+    /// the last time step a code was accepted for, codes are used only once.
+    pub last_step: i64,
+}
+
+/// A security key or passkey of a user, see server::webauthn.
+pub struct WebAuthnCredential {
+    /// base64url encoded credential id.
+    pub id: String,
+    pub user_id: String,
+    /// pem encoded public key.
+    pub public_key: String,
+    /// signature counter of the authenticator, 0 if it keeps none.
+    pub sign_count: i64,
+    pub name: String,
+    pub created: i64,
+}
+
+/// An invited user who has not chosen a password yet, see server::invitation.
+pub struct Invitation {
+    pub user_id: String,
+    pub email: String,
+    /// digest of the token in the mailed link.
+    pub token_hash: String,
+    pub created: i64,
+    pub expires: i64,
+}
+
+/// Optional attributes released with the standard scopes.
+#[derive(Default, Clone)]
+pub struct Profile {
+    pub name: Option<String>,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+    pub picture: Option<String>,
+    pub phone_number: Option<String>,
+    pub locale: Option<String>,
+    /// formatted postal address, lines separated by newlines.
+    pub address: Option<String>,
+}
+
+
+/// Scopes and claims a user agreed to release to a client.
+pub struct Consent {
+    pub user_id: String,
+    pub client_id: String,
+    pub scopes: Vec<String>,
+    /// claims granted one by one, outside of the scopes.
+    pub claims: Vec<String>,
+    pub granted_at: i64,
+}
+
+
+/// Lifecycle of a signing key, the private key itself is kept on disk.
+/// See server::keys for the possible types and states.
+pub struct SigningKeyRecord {
+    pub kid: String,
+    pub key_type: String,
+    pub state: String,
+    pub created: i64,
+    /// when the key entered its current state.
+    pub state_changed: i64,
+}
+
+
+/// Consecutive failures to authenticate, see server::throttle.
+#[derive(Default, Clone)]
+pub struct FailedAttempts {
+    pub count: i64,
+    pub last_failure: i64,
+    /// no attempts are accepted before then.
+    pub locked_until: Option<i64>,
+}
+
+
+/// A lockout or its lifting, kept for auditing.
+pub struct LockoutEvent {
+    /// what was locked: "user:<id>", "client:<name>" or "address:<ip>".
+    pub subject: String,
+    /// "locked" or "unlocked".
+    pub event: String,
+    /// where the failures came from, None if unknown or unlocked by an admin.
+    pub remote_addr: Option<String>,
+    pub at: i64,
+}
+
+
+/// A successful login, kept for the history users see on their account page.
+pub struct SignIn {
+    pub user_id: String,
+    pub at: i64,
+    /// where the login came from, None if unknown.
+    pub remote_addr: Option<String>,
+    /// how the user authenticated, see server::session::Authentication.
+    pub amr: Vec<String>,
 }
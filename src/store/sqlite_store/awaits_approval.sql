@@ -0,0 +1 @@
+SELECT count(*) > 0 FROM pending_approvals WHERE user_id = ?1
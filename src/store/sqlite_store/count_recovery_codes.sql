@@ -0,0 +1 @@
+SELECT count(*) FROM recovery_codes r INNER JOIN users u ON r.user_id = u.id WHERE u.id = ?1 OR u.email = ?1
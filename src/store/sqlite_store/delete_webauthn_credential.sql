@@ -0,0 +1 @@
+DELETE FROM webauthn_credentials WHERE id = ?2 AND user_id = (SELECT id FROM users WHERE id = ?1 OR email = ?1)
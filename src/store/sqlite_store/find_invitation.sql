@@ -0,0 +1,3 @@
+SELECT i.user_id, u.email, i.token_hash, i.created, i.expires
+FROM invitations i INNER JOIN users u ON i.user_id = u.id
+WHERE i.token_hash = ?1
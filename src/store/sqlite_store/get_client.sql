@@ -1,4 +1,8 @@
-SELECT c.id,c.name, cr.url
+SELECT c.id,c.name, cr.url, c.required_acr, c.secret, c.first_party, c.id_token_signed_response_alg,
+c.encryption_key, c.id_token_encrypted_response_alg, c.id_token_encrypted_response_enc,
+c.userinfo_encrypted_response_alg, c.userinfo_encrypted_response_enc, c.subject_type,
+c.sector_identifier_uri, c.id_token_lifetime, c.access_token_lifetime, c.refresh_token_lifetime,
+c.code_lifetime
 FROM clients c INNER JOIN client_redirects cr
 ON c.id = cr.client_id
 WHERE c.name = ?1
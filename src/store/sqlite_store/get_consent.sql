@@ -0,0 +1 @@
+SELECT user_id, client_id, scopes, granted_at, claims FROM consents WHERE user_id = ?1 AND client_id = ?2
@@ -0,0 +1 @@
+SELECT count, last_failure, locked_until FROM failed_logins WHERE user_id = ?1
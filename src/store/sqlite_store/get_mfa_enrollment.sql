@@ -0,0 +1 @@
+SELECT mfa_enrollment_until FROM users WHERE id = ?1
@@ -1,4 +1,6 @@
-SELECT u.id, u.email, ug.user_group
-FROM users u INNER JOIN user_groups ug
+SELECT u.id, u.email, ug.user_group, u.password,
+u.name, u.given_name, u.family_name, u.picture, u.phone_number, u.locale, u.address,
+u.email_verified
+FROM users u LEFT OUTER JOIN user_groups ug
 ON u.id = ug.user_id
 WHERE u.email = ?1 AND u.password = ?2
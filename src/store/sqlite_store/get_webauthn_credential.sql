@@ -0,0 +1 @@
+SELECT id, user_id, public_key, sign_count, name, created FROM webauthn_credentials WHERE id = ?1
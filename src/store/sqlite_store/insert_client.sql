@@ -1 +1 @@
-INSERT INTO clients(id,name) values (?1,?2)
+INSERT INTO clients(id,name,required_acr,secret,first_party,id_token_signed_response_alg,encryption_key,id_token_encrypted_response_alg,id_token_encrypted_response_enc,userinfo_encrypted_response_alg,userinfo_encrypted_response_enc,subject_type,sector_identifier_uri,id_token_lifetime,access_token_lifetime,refresh_token_lifetime,code_lifetime) values (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11,?12,?13,?14,?15,?16,?17)
@@ -0,0 +1 @@
+INSERT INTO lockout_events(subject, event, remote_addr, at) values (?1,?2,?3,?4)
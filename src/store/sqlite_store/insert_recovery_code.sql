@@ -0,0 +1 @@
+INSERT INTO recovery_codes(user_id, code_hash) SELECT id, ?2 FROM users WHERE id = ?1 OR email = ?1
@@ -0,0 +1 @@
+INSERT INTO sign_ins(user_id, at, remote_addr, amr) VALUES (?1, ?2, ?3, ?4)
@@ -0,0 +1 @@
+INSERT INTO signing_keys(kid,key_type,state,created,state_changed) values (?1,?2,?3,?4,?5)
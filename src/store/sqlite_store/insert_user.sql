@@ -1 +1 @@
-INSERT INTO users(id,email,password) values (?1,?2,?3)
+INSERT INTO users(id,email,password,name,given_name,family_name,picture,phone_number,locale,address,email_verified) values (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11)
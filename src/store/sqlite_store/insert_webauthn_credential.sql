@@ -0,0 +1 @@
+INSERT INTO webauthn_credentials(id, user_id, public_key, sign_count, name, created) values (?1,?2,?3,?4,?5,?6)
@@ -1 +1 @@
-select c.name, cr.url from clients c left outer join client_redirects cr on c.id = cr.client_id
+select c.id, c.name, cr.url, c.required_acr, c.secret, c.first_party, c.id_token_signed_response_alg, c.encryption_key, c.id_token_encrypted_response_alg, c.id_token_encrypted_response_enc, c.userinfo_encrypted_response_alg, c.userinfo_encrypted_response_enc, c.subject_type, c.sector_identifier_uri, c.id_token_lifetime, c.access_token_lifetime, c.refresh_token_lifetime, c.code_lifetime from clients c left outer join client_redirects cr on c.id = cr.client_id
@@ -0,0 +1 @@
+SELECT subject, event, remote_addr, at FROM lockout_events ORDER BY at, rowid
@@ -0,0 +1,3 @@
+SELECT u.email, p.requested
+FROM pending_approvals p INNER JOIN users u ON p.user_id = u.id
+ORDER BY p.requested
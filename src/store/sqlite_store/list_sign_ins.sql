@@ -0,0 +1,5 @@
+SELECT s.user_id, s.at, s.remote_addr, s.amr
+FROM sign_ins s INNER JOIN users u ON s.user_id = u.id
+WHERE u.id = ?1 OR u.email = ?1
+ORDER BY s.at DESC, s.rowid DESC
+LIMIT ?2
@@ -0,0 +1 @@
+SELECT kid, key_type, state, created, state_changed FROM signing_keys ORDER BY created, rowid
@@ -0,0 +1,4 @@
+SELECT c.user_id, c.client_id, c.scopes, c.granted_at, c.claims, cl.name
+FROM consents c INNER JOIN clients cl ON c.client_id = cl.id
+INNER JOIN users u ON c.user_id = u.id
+WHERE u.id = ?1 OR u.email = ?1
@@ -0,0 +1,4 @@
+SELECT w.id, w.user_id, w.public_key, w.sign_count, w.name, w.created
+FROM webauthn_credentials w INNER JOIN users u ON w.user_id = u.id
+WHERE u.id = ?1 OR u.email = ?1
+ORDER BY w.created, w.rowid
@@ -0,0 +1 @@
+ALTER TABLE clients ADD COLUMN required_acr text;
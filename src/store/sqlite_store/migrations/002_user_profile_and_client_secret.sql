@@ -0,0 +1,8 @@
+ALTER TABLE users ADD COLUMN name text;
+ALTER TABLE users ADD COLUMN given_name text;
+ALTER TABLE users ADD COLUMN family_name text;
+ALTER TABLE users ADD COLUMN picture text;
+ALTER TABLE users ADD COLUMN phone_number text;
+ALTER TABLE users ADD COLUMN locale text;
+ALTER TABLE users ADD COLUMN address text;
+ALTER TABLE clients ADD COLUMN secret text;
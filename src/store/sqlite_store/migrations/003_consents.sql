@@ -0,0 +1,2 @@
+ALTER TABLE clients ADD COLUMN first_party integer not null default 0;
+CREATE TABLE IF NOT EXISTS consents (user_id text, client_id text, scopes text not null, granted_at integer not null, PRIMARY KEY (user_id, client_id) FOREIGN KEY(user_id) references users(id) ON DELETE CASCADE FOREIGN KEY(client_id) references clients(id) ON DELETE CASCADE);
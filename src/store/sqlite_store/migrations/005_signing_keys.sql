@@ -0,0 +1 @@
+CREATE TABLE IF NOT EXISTS signing_keys (kid text PRIMARY KEY, key_type text not null, state text not null, created integer not null, state_changed integer not null);
@@ -0,0 +1,5 @@
+ALTER TABLE clients ADD COLUMN encryption_key text;
+ALTER TABLE clients ADD COLUMN id_token_encrypted_response_alg text;
+ALTER TABLE clients ADD COLUMN id_token_encrypted_response_enc text;
+ALTER TABLE clients ADD COLUMN userinfo_encrypted_response_alg text;
+ALTER TABLE clients ADD COLUMN userinfo_encrypted_response_enc text;
@@ -0,0 +1,2 @@
+ALTER TABLE clients ADD COLUMN subject_type text NOT NULL DEFAULT 'public';
+ALTER TABLE clients ADD COLUMN sector_identifier_uri text;
@@ -0,0 +1,4 @@
+ALTER TABLE clients ADD COLUMN id_token_lifetime integer;
+ALTER TABLE clients ADD COLUMN access_token_lifetime integer;
+ALTER TABLE clients ADD COLUMN refresh_token_lifetime integer;
+ALTER TABLE clients ADD COLUMN code_lifetime integer;
@@ -0,0 +1,2 @@
+CREATE TABLE IF NOT EXISTS failed_logins (user_id text PRIMARY KEY, count integer not null, last_failure integer not null, locked_until integer, FOREIGN KEY(user_id) references users(id) ON DELETE CASCADE);
+CREATE TABLE IF NOT EXISTS lockout_events (subject text not null, event text not null, remote_addr text, at integer not null);
@@ -0,0 +1,4 @@
+ALTER TABLE users ADD COLUMN totp_secret text;
+ALTER TABLE users ADD COLUMN totp_last_step integer;
+ALTER TABLE users ADD COLUMN mfa_required integer not null default 0;
+CREATE TABLE IF NOT EXISTS mfa_groups (user_group text PRIMARY KEY);
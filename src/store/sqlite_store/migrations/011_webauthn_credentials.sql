@@ -0,0 +1 @@
+CREATE TABLE IF NOT EXISTS webauthn_credentials (id text PRIMARY KEY, user_id text not null, public_key text not null, sign_count integer not null, name text not null, created integer not null, FOREIGN KEY(user_id) references users(id) ON DELETE CASCADE);
@@ -0,0 +1 @@
+CREATE TABLE IF NOT EXISTS recovery_codes (user_id text not null, code_hash text not null, PRIMARY KEY(user_id, code_hash), FOREIGN KEY(user_id) references users(id) ON DELETE CASCADE);
@@ -0,0 +1,2 @@
+ALTER TABLE users ADD COLUMN email_verified integer not null default 1;
+CREATE TABLE IF NOT EXISTS pending_approvals (user_id text PRIMARY KEY, requested integer not null, FOREIGN KEY(user_id) references users(id) ON DELETE CASCADE);
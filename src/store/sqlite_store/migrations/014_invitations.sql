@@ -0,0 +1 @@
+CREATE TABLE IF NOT EXISTS invitations (user_id text PRIMARY KEY, token_hash text not null UNIQUE, created integer not null, expires integer not null, FOREIGN KEY(user_id) references users(id) ON DELETE CASCADE);
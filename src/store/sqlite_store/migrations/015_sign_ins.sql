@@ -0,0 +1,2 @@
+CREATE TABLE IF NOT EXISTS sign_ins (user_id text not null, at integer not null, remote_addr text, amr text not null, FOREIGN KEY(user_id) references users(id) ON DELETE CASCADE);
+CREATE INDEX IF NOT EXISTS sign_ins_user_at ON sign_ins(user_id, at);
@@ -0,0 +1 @@
+ALTER TABLE consents ADD COLUMN claims text not null default '';
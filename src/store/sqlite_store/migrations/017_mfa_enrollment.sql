@@ -0,0 +1 @@
+ALTER TABLE users ADD COLUMN mfa_enrollment_until integer;
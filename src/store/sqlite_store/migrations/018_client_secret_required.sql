@@ -0,0 +1,3 @@
+-- clients added before public clients had to use PKCE get a secret nothing
+-- matches, `clients rotate-secret` gives them a usable one
+UPDATE clients SET secret = '!' WHERE secret IS NULL;
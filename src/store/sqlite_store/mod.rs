@@ -18,6 +18,81 @@ static DELETE_CLIENT_SQL: &str = include_str!("delete_client.sql");
 static DELETE_USER_SQL: &str = include_str!("delete_user.sql");
 static LIST_USERS_SQL: &str = include_str!("list_users.sql");
 static LIST_CLIENTS_SQL: &str = include_str!("list_clients.sql");
+static FIND_USER_SQL: &str = include_str!("find_user.sql");
+static UPDATE_USER_PROFILE_SQL: &str = include_str!("update_user_profile.sql");
+static SET_USER_PASSWORD_SQL: &str = include_str!("set_user_password.sql");
+static GET_TOTP_SQL: &str = include_str!("get_totp.sql");
+static SET_TOTP_SQL: &str = include_str!("set_totp.sql");
+static REQUIRES_MFA_SQL: &str = include_str!("requires_mfa.sql");
+static SET_USER_MFA_REQUIRED_SQL: &str = include_str!("set_user_mfa_required.sql");
+static GET_MFA_ENROLLMENT_SQL: &str = include_str!("get_mfa_enrollment.sql");
+static SET_MFA_ENROLLMENT_SQL: &str = include_str!("set_mfa_enrollment.sql");
+static REQUIRE_GROUP_MFA_SQL: &str = include_str!("require_group_mfa.sql");
+static UNREQUIRE_GROUP_MFA_SQL: &str = include_str!("unrequire_group_mfa.sql");
+static LIST_WEBAUTHN_CREDENTIALS_SQL: &str = include_str!("list_webauthn_credentials.sql");
+static GET_WEBAUTHN_CREDENTIAL_SQL: &str = include_str!("get_webauthn_credential.sql");
+static INSERT_WEBAUTHN_CREDENTIAL_SQL: &str = include_str!("insert_webauthn_credential.sql");
+static UPDATE_WEBAUTHN_SIGN_COUNT_SQL: &str = include_str!("update_webauthn_sign_count.sql");
+static DELETE_WEBAUTHN_CREDENTIAL_SQL: &str = include_str!("delete_webauthn_credential.sql");
+static DELETE_RECOVERY_CODES_SQL: &str = include_str!("delete_recovery_codes.sql");
+static INSERT_RECOVERY_CODE_SQL: &str = include_str!("insert_recovery_code.sql");
+static USE_RECOVERY_CODE_SQL: &str = include_str!("use_recovery_code.sql");
+static COUNT_RECOVERY_CODES_SQL: &str = include_str!("count_recovery_codes.sql");
+static SET_EMAIL_VERIFIED_SQL: &str = include_str!("set_email_verified.sql");
+static REQUEST_APPROVAL_SQL: &str = include_str!("request_approval.sql");
+static APPROVE_USER_SQL: &str = include_str!("approve_user.sql");
+static AWAITS_APPROVAL_SQL: &str = include_str!("awaits_approval.sql");
+static LIST_PENDING_APPROVALS_SQL: &str = include_str!("list_pending_approvals.sql");
+static SAVE_INVITATION_SQL: &str = include_str!("save_invitation.sql");
+static FIND_INVITATION_SQL: &str = include_str!("find_invitation.sql");
+static LIST_INVITATIONS_SQL: &str = include_str!("list_invitations.sql");
+static DELETE_INVITATION_SQL: &str = include_str!("delete_invitation.sql");
+static SET_CLIENT_REQUIRED_ACR_SQL: &str = include_str!("set_client_required_acr.sql");
+static SET_CLIENT_FIRST_PARTY_SQL: &str = include_str!("set_client_first_party.sql");
+static SET_CLIENT_SECRET_SQL: &str = include_str!("set_client_secret.sql");
+static SET_CLIENT_SIGNING_ALG_SQL: &str = include_str!("set_client_signing_alg.sql");
+static SET_CLIENT_ENCRYPTION_SQL: &str = include_str!("set_client_encryption.sql");
+static SET_CLIENT_SUBJECT_TYPE_SQL: &str = include_str!("set_client_subject_type.sql");
+static SET_CLIENT_LIFETIMES_SQL: &str = include_str!("set_client_lifetimes.sql");
+static GET_CONSENT_SQL: &str = include_str!("get_consent.sql");
+static SAVE_CONSENT_SQL: &str = include_str!("save_consent.sql");
+static LIST_USER_CONSENTS_SQL: &str = include_str!("list_user_consents.sql");
+static REVOKE_CONSENT_SQL: &str = include_str!("revoke_consent.sql");
+static REVOKE_USER_CONSENTS_SQL: &str = include_str!("revoke_user_consents.sql");
+static REVOKE_CLIENT_CONSENTS_SQL: &str = include_str!("revoke_client_consents.sql");
+static INSERT_SIGNING_KEY_SQL: &str = include_str!("insert_signing_key.sql");
+static UPDATE_SIGNING_KEY_STATE_SQL: &str = include_str!("update_signing_key_state.sql");
+static LIST_SIGNING_KEYS_SQL: &str = include_str!("list_signing_keys.sql");
+static GET_FAILED_LOGINS_SQL: &str = include_str!("get_failed_logins.sql");
+static SAVE_FAILED_LOGINS_SQL: &str = include_str!("save_failed_logins.sql");
+static CLEAR_FAILED_LOGINS_SQL: &str = include_str!("clear_failed_logins.sql");
+static INSERT_LOCKOUT_EVENT_SQL: &str = include_str!("insert_lockout_event.sql");
+static LIST_LOCKOUT_EVENTS_SQL: &str = include_str!("list_lockout_events.sql");
+static INSERT_SIGN_IN_SQL: &str = include_str!("insert_sign_in.sql");
+static PRUNE_SIGN_INS_SQL: &str = include_str!("prune_sign_ins.sql");
+static LIST_SIGN_INS_SQL: &str = include_str!("list_sign_ins.sql");
+
+// applied in order, the index + 1 of the last applied migration is kept in user_version.
+static MIGRATIONS: &[&str] = &[
+    include_str!("migrations/001_client_required_acr.sql"),
+    include_str!("migrations/002_user_profile_and_client_secret.sql"),
+    include_str!("migrations/003_consents.sql"),
+    include_str!("migrations/004_client_signing_alg.sql"),
+    include_str!("migrations/005_signing_keys.sql"),
+    include_str!("migrations/006_client_encryption.sql"),
+    include_str!("migrations/007_client_subject_type.sql"),
+    include_str!("migrations/008_client_lifetimes.sql"),
+    include_str!("migrations/009_failed_logins.sql"),
+    include_str!("migrations/010_totp.sql"),
+    include_str!("migrations/011_webauthn_credentials.sql"),
+    include_str!("migrations/012_recovery_codes.sql"),
+    include_str!("migrations/013_self_registration.sql"),
+    include_str!("migrations/014_invitations.sql"),
+    include_str!("migrations/015_sign_ins.sql"),
+    include_str!("migrations/016_consent_claims.sql"),
+    include_str!("migrations/017_mfa_enrollment.sql"),
+    include_str!("migrations/018_client_secret_required.sql"),
+];
 
 impl SqliteStore {
     fn get_connection(&self) -> Result<rusqlite::Connection, StoreError> {
@@ -52,12 +127,24 @@ impl SqliteStore {
         let con = result.get_connection()?;
         let create_result = con.execute_batch(CREATE_TABLES_SQL);
         match create_result {
-            Ok(_) => Ok(result),
+            Ok(_) => {
+                migrate(&con)?;
+                Ok(result)
+            }
             Err(e) => Err(StoreError::InternalError(Box::new(e))),
         }
     }
 }
 
+fn migrate(con: &rusqlite::Connection) -> Result<(), StoreError> {
+    let version: i64 = con.query_row("PRAGMA user_version", &[], |row| row.get(0))?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        con.execute_batch(migration)?;
+        con.execute_batch(&format!("PRAGMA user_version = {}", index + 1))?;
+    }
+    Ok(())
+}
+
 fn rows_to_user(mut rows: rusqlite::Rows) -> Result<Option<User>, StoreError> {
     let mut user = None;
     while let Some(result_row) = rows.next() {
@@ -66,14 +153,24 @@ fn rows_to_user(mut rows: rusqlite::Rows) -> Result<Option<User>, StoreError> {
             let inner = User {
                 id: row.get(0),
                 email: row.get(1),
-                password: None,
-                groups: vec![row.get(2)],
+                password: row.get(3),
+                groups: Vec::new(),
+                profile: Profile {
+                    name: row.get(4),
+                    given_name: row.get(5),
+                    family_name: row.get(6),
+                    picture: row.get(7),
+                    phone_number: row.get(8),
+                    locale: row.get(9),
+                    address: row.get(10),
+                },
+                email_verified: row.get(11),
             };
             user = Some(inner);
-        } else {
-            let mut inner = user.unwrap(); // safe unwrap
-            inner.groups.push(row.get(1));
-            user = Some(inner);
+        }
+        let possible_group: Option<String> = row.get(2);
+        if let Some(group) = possible_group {
+            user.as_mut().unwrap().groups.push(group); // safe unwrap
         }
     }
     Ok(user)
@@ -81,6 +178,63 @@ fn rows_to_user(mut rows: rusqlite::Rows) -> Result<Option<User>, StoreError> {
 
 
 
+fn row_to_webauthn_credential(row: &rusqlite::Row) -> WebAuthnCredential {
+    WebAuthnCredential {
+        id: row.get(0),
+        user_id: row.get(1),
+        public_key: row.get(2),
+        sign_count: row.get(3),
+        name: row.get(4),
+        created: row.get(5),
+    }
+}
+
+fn row_to_invitation(row: &rusqlite::Row) -> Invitation {
+    Invitation {
+        user_id: row.get(0),
+        email: row.get(1),
+        token_hash: row.get(2),
+        created: row.get(3),
+        expires: row.get(4),
+    }
+}
+
+// the encryption settings of a client row, starting at column 7.
+fn row_to_encryption(row: &rusqlite::Row) -> EncryptionSettings {
+    EncryptionSettings {
+        key: row.get(7),
+        id_token_encrypted_response_alg: row.get(8),
+        id_token_encrypted_response_enc: row.get(9),
+        userinfo_encrypted_response_alg: row.get(10),
+        userinfo_encrypted_response_enc: row.get(11),
+    }
+}
+
+// the token lifetimes of a client row, starting at column 14.
+fn row_to_lifetimes(row: &rusqlite::Row) -> TokenLifetimes {
+    TokenLifetimes {
+        id_token: row.get(14),
+        access_token: row.get(15),
+        refresh_token: row.get(16),
+        code: row.get(17),
+    }
+}
+
+
+fn row_to_consent(row: &rusqlite::Row) -> Consent {
+    let scopes: String = row.get(2);
+    let claims: String = row.get(4);
+    Consent {
+        user_id: row.get(0),
+        client_id: row.get(1),
+        scopes: scopes.split_whitespace().map(String::from).collect(),
+        claims: claims.split_whitespace().map(String::from).collect(),
+        granted_at: row.get(3),
+    }
+}
+
+
+
 impl Store for SqliteStore {
     fn get_user(&self, email: &str, pwd: &str) -> Result<Option<User>, StoreError> {
         let con = self.get_connection()?;
@@ -89,6 +243,231 @@ impl Store for SqliteStore {
         rows_to_user(rs)
     }
 
+    fn find_user(&self, reference: &str) -> Result<Option<User>, StoreError> {
+        let con = self.get_connection()?;
+        let mut stmt = con.prepare(FIND_USER_SQL)?;
+        let rs = stmt.query(&[&reference])?;
+        rows_to_user(rs)
+    }
+
+    fn update_profile(&self, reference: &str, profile: &Profile) -> Result<(), StoreError> {
+        self.execute(
+            UPDATE_USER_PROFILE_SQL,
+            &[
+                &reference,
+                &profile.name,
+                &profile.given_name,
+                &profile.family_name,
+                &profile.picture,
+                &profile.phone_number,
+                &profile.locale,
+                &profile.address,
+            ],
+        )
+    }
+
+
+    fn set_password(&self, reference: &str, password: &str) -> Result<(), StoreError> {
+        self.execute(SET_USER_PASSWORD_SQL, &[&reference, &password])
+    }
+
+    fn get_totp(&self, user_id: &str) -> Result<Option<Totp>, StoreError> {
+        let con = self.get_connection()?;
+        let mut stmt = con.prepare(GET_TOTP_SQL)?;
+        let mut rs = stmt.query(&[&user_id])?;
+        let totp = match rs.next() {
+            Some(result_row) => {
+                let row = result_row?;
+                let last_step: Option<i64> = row.get(1);
+                Some(Totp {
+                    secret: row.get(0),
+                    last_step: last_step.unwrap_or(0),
+                })
+            }
+            None => None,
+        };
+        Ok(totp)
+    }
+
+    fn set_totp(&self, reference: &str, totp: Option<&Totp>) -> Result<(), StoreError> {
+        let secret = totp.map(|totp| totp.secret.clone());
+        let last_step = totp.map(|totp| totp.last_step);
+        self.execute(SET_TOTP_SQL, &[&reference, &secret, &last_step])
+    }
+
+    fn requires_mfa(&self, user_id: &str) -> Result<bool, StoreError> {
+        let con = self.get_connection()?;
+        let required = con.query_row(REQUIRES_MFA_SQL, &[&user_id], |row| row.get(0))?;
+        Ok(required)
+    }
+
+    fn set_user_mfa_required(&self, reference: &str, required: bool) -> Result<(), StoreError> {
+        self.execute(SET_USER_MFA_REQUIRED_SQL, &[&reference, &required])
+    }
+
+    fn get_mfa_enrollment(&self, user_id: &str) -> Result<Option<i64>, StoreError> {
+        let con = self.get_connection()?;
+        let mut stmt = con.prepare(GET_MFA_ENROLLMENT_SQL)?;
+        let mut rs = stmt.query(&[&user_id])?;
+        match rs.next() {
+            Some(result_row) => Ok(result_row?.get(0)),
+            None => Ok(None),
+        }
+    }
+
+    fn set_mfa_enrollment(&self, reference: &str, until: Option<i64>) -> Result<(), StoreError> {
+        self.execute(SET_MFA_ENROLLMENT_SQL, &[&reference, &until])
+    }
+
+    fn set_group_mfa_required(&self, group_name: &str, required: bool) -> Result<(), StoreError> {
+        if required {
+            self.execute(REQUIRE_GROUP_MFA_SQL, &[&group_name])
+        } else {
+            self.execute(UNREQUIRE_GROUP_MFA_SQL, &[&group_name])
+        }
+    }
+
+    fn get_webauthn_credentials(
+        &self,
+        user_reference: &str,
+    ) -> Result<Vec<WebAuthnCredential>, StoreError> {
+        let con = self.get_connection()?;
+        let mut stmt = con.prepare(LIST_WEBAUTHN_CREDENTIALS_SQL)?;
+        let mut rs = stmt.query(&[&user_reference])?;
+        let mut credentials = Vec::new();
+        while let Some(result_row) = rs.next() {
+            credentials.push(row_to_webauthn_credential(&result_row?));
+        }
+        Ok(credentials)
+    }
+
+    fn get_webauthn_credential(&self, id: &str) -> Result<Option<WebAuthnCredential>, StoreError> {
+        let con = self.get_connection()?;
+        let mut stmt = con.prepare(GET_WEBAUTHN_CREDENTIAL_SQL)?;
+        let mut rs = stmt.query(&[&id])?;
+        let credential = match rs.next() {
+            Some(result_row) => Some(row_to_webauthn_credential(&result_row?)),
+            None => None,
+        };
+        Ok(credential)
+    }
+
+    fn save_webauthn_credential(&self, credential: &WebAuthnCredential) -> Result<(), StoreError> {
+        self.execute(
+            INSERT_WEBAUTHN_CREDENTIAL_SQL,
+            &[
+                &credential.id,
+                &credential.user_id,
+                &credential.public_key,
+                &credential.sign_count,
+                &credential.name,
+                &credential.created,
+            ],
+        )
+    }
+
+    fn update_webauthn_sign_count(&self, id: &str, sign_count: i64) -> Result<(), StoreError> {
+        self.execute(UPDATE_WEBAUTHN_SIGN_COUNT_SQL, &[&id, &sign_count])
+    }
+
+    fn delete_webauthn_credential(&self, user_reference: &str, id: &str) -> Result<(), StoreError> {
+        self.execute(DELETE_WEBAUTHN_CREDENTIAL_SQL, &[&user_reference, &id])
+    }
+
+    fn set_recovery_codes(&self, reference: &str, hashes: &[String]) -> Result<(), StoreError> {
+        let mut con = self.get_connection()?;
+        let tx = con.transaction()?;
+        tx.execute(DELETE_RECOVERY_CODES_SQL, &[&reference])?;
+        {
+            let mut insert_stmt = tx.prepare(INSERT_RECOVERY_CODE_SQL)?;
+            for hash in hashes {
+                insert_stmt.execute(&[&reference, hash])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn use_recovery_code(&self, user_id: &str, hash: &str) -> Result<bool, StoreError> {
+        let con = self.get_connection()?;
+        let deleted = con.execute(USE_RECOVERY_CODE_SQL, &[&user_id, &hash])?;
+        Ok(deleted > 0)
+    }
+
+    fn count_recovery_codes(&self, reference: &str) -> Result<i64, StoreError> {
+        let con = self.get_connection()?;
+        let count = con.query_row(COUNT_RECOVERY_CODES_SQL, &[&reference], |row| row.get(0))?;
+        Ok(count)
+    }
+
+    fn set_email_verified(&self, reference: &str, verified: bool) -> Result<(), StoreError> {
+        self.execute(SET_EMAIL_VERIFIED_SQL, &[&reference, &verified])
+    }
+
+    fn request_approval(&self, reference: &str, now: i64) -> Result<(), StoreError> {
+        self.execute(REQUEST_APPROVAL_SQL, &[&reference, &now])
+    }
+
+    fn approve_user(&self, reference: &str) -> Result<(), StoreError> {
+        self.execute(APPROVE_USER_SQL, &[&reference])
+    }
+
+    fn awaits_approval(&self, user_id: &str) -> Result<bool, StoreError> {
+        let con = self.get_connection()?;
+        let awaits = con.query_row(AWAITS_APPROVAL_SQL, &[&user_id], |row| row.get(0))?;
+        Ok(awaits)
+    }
+
+    fn get_pending_approvals(&self) -> Result<Vec<(String, i64)>, StoreError> {
+        let con = self.get_connection()?;
+        let mut stmt = con.prepare(LIST_PENDING_APPROVALS_SQL)?;
+        let mut rs = stmt.query(&[])?;
+        let mut pending = Vec::new();
+        while let Some(result_row) = rs.next() {
+            let row = result_row?;
+            pending.push((row.get(0), row.get(1)));
+        }
+        Ok(pending)
+    }
+
+    fn save_invitation(&self, invitation: &Invitation) -> Result<(), StoreError> {
+        self.execute(
+            SAVE_INVITATION_SQL,
+            &[
+                &invitation.user_id,
+                &invitation.token_hash,
+                &invitation.created,
+                &invitation.expires,
+            ],
+        )
+    }
+
+    fn find_invitation(&self, token_hash: &str) -> Result<Option<Invitation>, StoreError> {
+        let con = self.get_connection()?;
+        let mut stmt = con.prepare(FIND_INVITATION_SQL)?;
+        let mut rs = stmt.query(&[&token_hash])?;
+        let invitation = match rs.next() {
+            Some(result_row) => Some(row_to_invitation(&result_row?)),
+            None => None,
+        };
+        Ok(invitation)
+    }
+
+    fn get_invitations(&self) -> Result<Vec<Invitation>, StoreError> {
+        let con = self.get_connection()?;
+        let mut stmt = con.prepare(LIST_INVITATIONS_SQL)?;
+        let mut rs = stmt.query(&[])?;
+        let mut invitations = Vec::new();
+        while let Some(result_row) = rs.next() {
+            invitations.push(row_to_invitation(&result_row?));
+        }
+        Ok(invitations)
+    }
+
+    fn delete_invitation(&self, user_reference: &str) -> Result<(), StoreError> {
+        self.execute(DELETE_INVITATION_SQL, &[&user_reference])
+    }
+
 
     fn get_clients(&self) -> Result<HashMap<String, Client>, StoreError> {
         let con = self.get_connection()?;
@@ -103,6 +482,14 @@ impl Store for SqliteStore {
                 id: row.get(0),
                 name: row.get(1),
                 redirect_urls: Vec::new(),
+                required_acr: row.get(3),
+                secret: row.get(4),
+                first_party: row.get(5),
+                id_token_signed_response_alg: row.get(6),
+                encryption: row_to_encryption(&row),
+                subject_type: row.get(12),
+                sector_identifier_uri: row.get(13),
+                lifetimes: row_to_lifetimes(&row),
             });
             if possible_redirect_url.is_ok() {
                 client.redirect_urls.push(row.get(2));
@@ -128,6 +515,8 @@ impl Store for SqliteStore {
                 email: row.get(1),
                 password: None,
                 groups: Vec::new(),
+                profile: Profile::default(),
+                email_verified: row.get(3),
             });
 
             let possible_group: rusqlite::Result<String> = row.get_checked(2);
@@ -151,6 +540,14 @@ impl Store for SqliteStore {
                     id: row.get(0),
                     name: row.get(1),
                     redirect_urls: vec![row.get(2)],
+                    required_acr: row.get(3),
+                    secret: row.get(4),
+                    first_party: row.get(5),
+                    id_token_signed_response_alg: row.get(6),
+                    encryption: row_to_encryption(&row),
+                    subject_type: row.get(12),
+                    sector_identifier_uri: row.get(13),
+                    lifetimes: row_to_lifetimes(&row),
                 };
                 client = Some(inner);
             } else {
@@ -171,6 +568,14 @@ impl Store for SqliteStore {
                 &user.id,
                 &user.email,
                 &user.password,
+                &user.profile.name,
+                &user.profile.given_name,
+                &user.profile.family_name,
+                &user.profile.picture,
+                &user.profile.phone_number,
+                &user.profile.locale,
+                &user.profile.address,
+                &user.email_verified,
             ],
         )?;
         {
@@ -188,7 +593,25 @@ impl Store for SqliteStore {
         let mut con = self.get_connection()?;
         let tx = con.transaction()?;
         tx.prepare(INSERT_CLIENT_SQL)?.execute(
-            &[&client.id, &client.name],
+            &[
+                &client.id,
+                &client.name,
+                &client.required_acr,
+                &client.secret,
+                &client.first_party,
+                &client.id_token_signed_response_alg,
+                &client.encryption.key,
+                &client.encryption.id_token_encrypted_response_alg,
+                &client.encryption.id_token_encrypted_response_enc,
+                &client.encryption.userinfo_encrypted_response_alg,
+                &client.encryption.userinfo_encrypted_response_enc,
+                &client.subject_type,
+                &client.sector_identifier_uri,
+                &client.lifetimes.id_token,
+                &client.lifetimes.access_token,
+                &client.lifetimes.refresh_token,
+                &client.lifetimes.code,
+            ],
         )?;
         {
             let sql = "INSERT INTO client_redirects(client_id,url) values(?1,?2)";
@@ -218,6 +641,283 @@ impl Store for SqliteStore {
         self.execute(REMOVE_CLIENT_REDIRECT_SQL, &[&reference, &redirect_url])
     }
 
+    fn set_required_acr(&self, reference: &str, acr: Option<&str>) -> Result<(), StoreError> {
+        self.execute(SET_CLIENT_REQUIRED_ACR_SQL, &[&reference, &acr])
+    }
+
+    fn set_first_party(&self, reference: &str, first_party: bool) -> Result<(), StoreError> {
+        self.execute(SET_CLIENT_FIRST_PARTY_SQL, &[&reference, &first_party])
+    }
+
+    fn set_client_secret(&self, reference: &str, secret_hash: &str) -> Result<(), StoreError> {
+        self.execute(SET_CLIENT_SECRET_SQL, &[&reference, &secret_hash])
+    }
+
+    fn set_signing_alg(&self, reference: &str, alg: Option<&str>) -> Result<(), StoreError> {
+        self.execute(SET_CLIENT_SIGNING_ALG_SQL, &[&reference, &alg])
+    }
+
+    fn set_encryption(
+        &self,
+        reference: &str,
+        encryption: &EncryptionSettings,
+    ) -> Result<(), StoreError> {
+        self.execute(
+            SET_CLIENT_ENCRYPTION_SQL,
+            &[
+                &reference,
+                &encryption.key,
+                &encryption.id_token_encrypted_response_alg,
+                &encryption.id_token_encrypted_response_enc,
+                &encryption.userinfo_encrypted_response_alg,
+                &encryption.userinfo_encrypted_response_enc,
+            ],
+        )
+    }
+
+    fn set_subject_type(
+        &self,
+        reference: &str,
+        subject_type: &str,
+        sector_identifier_uri: Option<&str>,
+    ) -> Result<(), StoreError> {
+        self.execute(
+            SET_CLIENT_SUBJECT_TYPE_SQL,
+            &[&reference, &subject_type, &sector_identifier_uri],
+        )
+    }
+
+    fn set_lifetimes(&self, reference: &str, lifetimes: &TokenLifetimes) -> Result<(), StoreError> {
+        self.execute(
+            SET_CLIENT_LIFETIMES_SQL,
+            &[
+                &reference,
+                &lifetimes.id_token,
+                &lifetimes.access_token,
+                &lifetimes.refresh_token,
+                &lifetimes.code,
+            ],
+        )
+    }
+
+    fn get_consent(&self, user_id: &str, client_id: &str) -> Result<Option<Consent>, StoreError> {
+        let con = self.get_connection()?;
+        let mut stmt = con.prepare(GET_CONSENT_SQL)?;
+        let mut rs = stmt.query(&[&user_id, &client_id])?;
+        let consent = match rs.next() {
+            Some(result_row) => Some(row_to_consent(&result_row?)),
+            None => None,
+        };
+        Ok(consent)
+    }
+
+    fn save_consent(&self, consent: &Consent) -> Result<(), StoreError> {
+        self.execute(
+            SAVE_CONSENT_SQL,
+            &[
+                &consent.user_id,
+                &consent.client_id,
+                &consent.scopes.join(" "),
+                &consent.granted_at,
+                &consent.claims.join(" "),
+            ],
+        )
+    }
+
+    fn get_consents(&self, user_reference: &str) -> Result<HashMap<String, Consent>, StoreError> {
+        let con = self.get_connection()?;
+        let mut stmt = con.prepare(LIST_USER_CONSENTS_SQL)?;
+        let mut rs = stmt.query(&[&user_reference])?;
+        let mut consents = HashMap::new();
+        while let Some(result_row) = rs.next() {
+            let row = result_row?;
+            consents.insert(row.get(5), row_to_consent(&row));
+        }
+        Ok(consents)
+    }
+
+    fn revoke_consent(
+        &self,
+        user_reference: &str,
+        client_reference: Option<&str>,
+    ) -> Result<(), StoreError> {
+        match client_reference {
+            Some(client) => self.execute(REVOKE_CONSENT_SQL, &[&user_reference, &client]),
+            None => self.execute(REVOKE_USER_CONSENTS_SQL, &[&user_reference]),
+        }
+    }
+
+    fn revoke_client_consents(&self, client_reference: &str) -> Result<(), StoreError> {
+        self.execute(REVOKE_CLIENT_CONSENTS_SQL, &[&client_reference])
+    }
+
+    fn get_signing_keys(&self) -> Result<Vec<SigningKeyRecord>, StoreError> {
+        let con = self.get_connection()?;
+        let mut stmt = con.prepare(LIST_SIGNING_KEYS_SQL)?;
+        let mut rs = stmt.query(&[])?;
+        let mut keys = Vec::new();
+        while let Some(result_row) = rs.next() {
+            let row = result_row?;
+            keys.push(SigningKeyRecord {
+                kid: row.get(0),
+                key_type: row.get(1),
+                state: row.get(2),
+                created: row.get(3),
+                state_changed: row.get(4),
+            });
+        }
+        Ok(keys)
+    }
+
+    fn update_signing_key_state(
+        &self,
+        kid: &str,
+        state: &str,
+        state_changed: i64,
+    ) -> Result<(), StoreError> {
+        self.execute(UPDATE_SIGNING_KEY_STATE_SQL, &[&kid, &state, &state_changed])
+    }
+
+    fn save_signing_key(&self, key: &SigningKeyRecord) -> Result<(), StoreError> {
+        self.execute(
+            INSERT_SIGNING_KEY_SQL,
+            &[
+                &key.kid,
+                &key.key_type,
+                &key.state,
+                &key.created,
+                &key.state_changed,
+            ],
+        )
+    }
+
+    fn rotate_signing_keys(
+        &self,
+        state_changes: &[(String, String)],
+        new_keys: &[SigningKeyRecord],
+        state_changed: i64,
+    ) -> Result<(), StoreError> {
+        let mut con = self.get_connection()?;
+        let tx = con.transaction()?;
+        {
+            let mut update_stmt = tx.prepare(UPDATE_SIGNING_KEY_STATE_SQL)?;
+            for &(ref kid, ref state) in state_changes {
+                update_stmt.execute(&[kid, state, &state_changed])?;
+            }
+            let mut insert_stmt = tx.prepare(INSERT_SIGNING_KEY_SQL)?;
+            for key in new_keys {
+                insert_stmt.execute(
+                    &[
+                        &key.kid,
+                        &key.key_type,
+                        &key.state,
+                        &key.created,
+                        &key.state_changed,
+                    ],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn get_failed_attempts(&self, user_id: &str) -> Result<FailedAttempts, StoreError> {
+        let con = self.get_connection()?;
+        let mut stmt = con.prepare(GET_FAILED_LOGINS_SQL)?;
+        let mut rs = stmt.query(&[&user_id])?;
+        let attempts = match rs.next() {
+            Some(result_row) => {
+                let row = result_row?;
+                FailedAttempts {
+                    count: row.get(0),
+                    last_failure: row.get(1),
+                    locked_until: row.get(2),
+                }
+            }
+            None => FailedAttempts::default(),
+        };
+        Ok(attempts)
+    }
+
+    fn save_failed_attempts(
+        &self,
+        user_id: &str,
+        attempts: &FailedAttempts,
+    ) -> Result<(), StoreError> {
+        self.execute(
+            SAVE_FAILED_LOGINS_SQL,
+            &[
+                &user_id,
+                &attempts.count,
+                &attempts.last_failure,
+                &attempts.locked_until,
+            ],
+        )
+    }
+
+    fn clear_failed_attempts(&self, reference: &str) -> Result<(), StoreError> {
+        self.execute(CLEAR_FAILED_LOGINS_SQL, &[&reference])
+    }
+
+    fn record_lockout_event(&self, event: &LockoutEvent) -> Result<(), StoreError> {
+        self.execute(
+            INSERT_LOCKOUT_EVENT_SQL,
+            &[&event.subject, &event.event, &event.remote_addr, &event.at],
+        )
+    }
+
+    fn get_lockout_events(&self) -> Result<Vec<LockoutEvent>, StoreError> {
+        let con = self.get_connection()?;
+        let mut stmt = con.prepare(LIST_LOCKOUT_EVENTS_SQL)?;
+        let mut rs = stmt.query(&[])?;
+        let mut events = Vec::new();
+        while let Some(result_row) = rs.next() {
+            let row = result_row?;
+            events.push(LockoutEvent {
+                subject: row.get(0),
+                event: row.get(1),
+                remote_addr: row.get(2),
+                at: row.get(3),
+            });
+        }
+        Ok(events)
+    }
+
+    fn record_sign_in(&self, sign_in: &SignIn) -> Result<(), StoreError> {
+        let mut con = self.get_connection()?;
+        let tx = con.transaction()?;
+        tx.execute(
+            INSERT_SIGN_IN_SQL,
+            &[
+                &sign_in.user_id,
+                &sign_in.at,
+                &sign_in.remote_addr,
+                &sign_in.amr.join(" "),
+            ],
+        )?;
+        tx.execute(PRUNE_SIGN_INS_SQL, &[&sign_in.user_id, &KEPT_SIGN_INS])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn get_sign_ins(&self, user_reference: &str, limit: i64) -> Result<Vec<SignIn>, StoreError> {
+        let con = self.get_connection()?;
+        let mut stmt = con.prepare(LIST_SIGN_INS_SQL)?;
+        let mut rs = stmt.query(&[&user_reference, &limit])?;
+        let mut sign_ins = Vec::new();
+        while let Some(result_row) = rs.next() {
+            let row = result_row?;
+            let amr: String = row.get(3);
+            sign_ins.push(SignIn {
+                user_id: row.get(0),
+                at: row.get(1),
+                remote_addr: row.get(2),
+                amr: amr.split_whitespace().map(String::from).collect(),
+            });
+        }
+        Ok(sign_ins)
+    }
+
     fn delete_client(&self, reference: &str) -> Result<(), StoreError> {
         let con = self.get_connection()?;
         con.execute("PRAGMA foreign_keys = ON", &[])?;
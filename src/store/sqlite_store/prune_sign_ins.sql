@@ -0,0 +1,2 @@
+DELETE FROM sign_ins WHERE user_id = ?1 AND rowid NOT IN
+(SELECT rowid FROM sign_ins WHERE user_id = ?1 ORDER BY at DESC, rowid DESC LIMIT ?2)
@@ -0,0 +1 @@
+INSERT OR REPLACE INTO pending_approvals(user_id, requested) SELECT id, ?2 FROM users WHERE id = ?1 OR email = ?1
@@ -0,0 +1 @@
+INSERT OR IGNORE INTO mfa_groups(user_group) values (?1)
@@ -0,0 +1,2 @@
+SELECT EXISTS(SELECT 1 FROM users WHERE id = ?1 AND mfa_required = 1)
+OR EXISTS(SELECT 1 FROM user_groups ug INNER JOIN mfa_groups mg ON ug.user_group = mg.user_group WHERE ug.user_id = ?1)
@@ -0,0 +1 @@
+DELETE FROM consents WHERE client_id = (SELECT id FROM clients WHERE name = ?1)
@@ -0,0 +1,3 @@
+DELETE FROM consents
+WHERE user_id = (SELECT id FROM users WHERE id = ?1 OR email = ?1)
+AND client_id = (SELECT id FROM clients WHERE name = ?2)
@@ -0,0 +1 @@
+DELETE FROM consents WHERE user_id = (SELECT id FROM users WHERE id = ?1 OR email = ?1)
@@ -0,0 +1 @@
+INSERT OR REPLACE INTO consents(user_id, client_id, scopes, granted_at, claims) values (?1,?2,?3,?4,?5)
@@ -0,0 +1 @@
+INSERT OR REPLACE INTO failed_logins(user_id, count, last_failure, locked_until) values (?1,?2,?3,?4)
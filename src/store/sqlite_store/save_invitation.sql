@@ -0,0 +1 @@
+INSERT OR REPLACE INTO invitations(user_id, token_hash, created, expires) VALUES (?1, ?2, ?3, ?4)
@@ -0,0 +1 @@
+UPDATE clients SET encryption_key = ?2, id_token_encrypted_response_alg = ?3, id_token_encrypted_response_enc = ?4, userinfo_encrypted_response_alg = ?5, userinfo_encrypted_response_enc = ?6 where name = ?1
@@ -0,0 +1 @@
+UPDATE clients SET first_party = ?2 where name = ?1
@@ -0,0 +1 @@
+UPDATE clients SET id_token_lifetime = ?2, access_token_lifetime = ?3, refresh_token_lifetime = ?4, code_lifetime = ?5 where name = ?1
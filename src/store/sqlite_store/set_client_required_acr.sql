@@ -0,0 +1 @@
+UPDATE clients SET required_acr = ?2 where name = ?1
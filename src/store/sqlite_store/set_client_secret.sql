@@ -0,0 +1 @@
+UPDATE clients SET secret = ?2 where name = ?1
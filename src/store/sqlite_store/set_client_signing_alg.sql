@@ -0,0 +1 @@
+UPDATE clients SET id_token_signed_response_alg = ?2 where name = ?1
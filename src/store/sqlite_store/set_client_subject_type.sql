@@ -0,0 +1 @@
+UPDATE clients SET subject_type = ?2, sector_identifier_uri = ?3 where name = ?1
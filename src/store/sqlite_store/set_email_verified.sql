@@ -0,0 +1 @@
+UPDATE users SET email_verified = ?2 WHERE id = ?1 OR email = ?1
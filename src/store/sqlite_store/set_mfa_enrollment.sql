@@ -0,0 +1 @@
+UPDATE users SET mfa_enrollment_until = ?2 WHERE id = ?1 OR email = ?1
@@ -0,0 +1 @@
+UPDATE users SET totp_secret = ?2, totp_last_step = ?3 WHERE id = ?1 OR email = ?1
@@ -0,0 +1 @@
+DELETE FROM mfa_groups WHERE user_group = ?1
@@ -0,0 +1 @@
+UPDATE signing_keys SET state = ?2, state_changed = ?3 WHERE kid = ?1
@@ -0,0 +1,2 @@
+UPDATE users SET name = ?2, given_name = ?3, family_name = ?4, picture = ?5, phone_number = ?6, locale = ?7, address = ?8
+WHERE id = ?1 OR email = ?1
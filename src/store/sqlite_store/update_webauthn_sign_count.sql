@@ -0,0 +1 @@
+UPDATE webauthn_credentials SET sign_count = ?2 WHERE id = ?1
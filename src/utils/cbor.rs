@@ -0,0 +1,178 @@
+//! Just enough of CBOR (RFC 7049) to read WebAuthn attestation objects and
+//! COSE keys: definite length integers, strings, arrays and maps. Tags are
+//! skipped, floats and indefinite lengths are rejected.
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Integer(i64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Value>),
+    /// entries in the encoded order.
+    Map(Vec<(Value, Value)>),
+    Bool(bool),
+    Null,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CborError(&'static str);
+
+impl fmt::Display for CborError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid cbor: {}", self.0)
+    }
+}
+
+// nesting deeper than any WebAuthn structure is refused.
+const MAX_DEPTH: usize = 16;
+
+/// Decodes the value at the start of `data`, returns it with the number of
+/// bytes it took. Anything after it is left alone.
+pub fn decode(data: &[u8]) -> Result<(Value, usize), CborError> {
+    let mut position = 0;
+    let value = decode_value(data, &mut position, 0)?;
+    Ok((value, position))
+}
+
+impl Value {
+    /// The value of `key` in a map.
+    pub fn get(&self, key: &Value) -> Option<&Value> {
+        match *self {
+            Value::Map(ref entries) => {
+                entries.iter().find(|entry| &entry.0 == key).map(
+                    |entry| &entry.1,
+                )
+            }
+            _ => None,
+        }
+    }
+
+    pub fn get_text(&self, key: &str) -> Option<&Value> {
+        self.get(&Value::Text(String::from(key)))
+    }
+
+    pub fn get_int(&self, key: i64) -> Option<&Value> {
+        self.get(&Value::Integer(key))
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match *self {
+            Value::Bytes(ref bytes) => Some(&bytes[..]),
+            _ => None,
+        }
+    }
+
+    pub fn as_text(&self) -> Option<&str> {
+        match *self {
+            Value::Text(ref text) => Some(&text[..]),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match *self {
+            Value::Integer(integer) => Some(integer),
+            _ => None,
+        }
+    }
+}
+
+fn take<'a>(data: &'a [u8], position: &mut usize, len: usize) -> Result<&'a [u8], CborError> {
+    if data.len() - *position < len {
+        return Err(CborError("truncated"));
+    }
+    let taken = &data[*position..*position + len];
+    *position += len;
+    Ok(taken)
+}
+
+// the argument following the initial byte, a length or an integer value.
+fn argument(data: &[u8], position: &mut usize, info: u8) -> Result<u64, CborError> {
+    let size = match info {
+        0...23 => return Ok(info as u64),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        _ => return Err(CborError("unsupported length")),
+    };
+    Ok(take(data, position, size)?.iter().fold(
+        0,
+        |value, &byte| (value << 8) | byte as u64,
+    ))
+}
+
+fn length(data: &[u8], position: &mut usize, info: u8) -> Result<usize, CborError> {
+    let len = argument(data, position, info)?;
+    if len > (data.len() - *position) as u64 {
+        return Err(CborError("truncated"));
+    }
+    Ok(len as usize)
+}
+
+fn decode_value(data: &[u8], position: &mut usize, depth: usize) -> Result<Value, CborError> {
+    if depth > MAX_DEPTH {
+        return Err(CborError("nested too deeply"));
+    }
+    let initial = take(data, position, 1)?[0];
+    let info = initial & 0x1f;
+    match initial >> 5 {
+        0 => {
+            let value = argument(data, position, info)?;
+            if value > i64::max_value() as u64 {
+                return Err(CborError("integer too large"));
+            }
+            Ok(Value::Integer(value as i64))
+        }
+        1 => {
+            let value = argument(data, position, info)?;
+            if value > i64::max_value() as u64 {
+                return Err(CborError("integer too large"));
+            }
+            Ok(Value::Integer(-1 - value as i64))
+        }
+        2 => {
+            let len = length(data, position, info)?;
+            Ok(Value::Bytes(take(data, position, len)?.to_vec()))
+        }
+        3 => {
+            let len = length(data, position, info)?;
+            let text = String::from_utf8(take(data, position, len)?.to_vec())
+                .map_err(|_| CborError("text is not utf-8"))?;
+            Ok(Value::Text(text))
+        }
+        4 => {
+            // every item takes at least one byte, so the length is bounded by the data
+            let len = length(data, position, info)?;
+            let mut items = Vec::with_capacity(len);
+            for _ in 0..len {
+                items.push(decode_value(data, position, depth + 1)?);
+            }
+            Ok(Value::Array(items))
+        }
+        5 => {
+            let len = length(data, position, info)?;
+            let mut entries = Vec::with_capacity(len);
+            for _ in 0..len {
+                let key = decode_value(data, position, depth + 1)?;
+                let value = decode_value(data, position, depth + 1)?;
+                entries.push((key, value));
+            }
+            Ok(Value::Map(entries))
+        }
+        6 => {
+            argument(data, position, info)?;
+            decode_value(data, position, depth + 1)
+        }
+        _ => {
+            match info {
+                20 => Ok(Value::Bool(false)),
+                21 => Ok(Value::Bool(true)),
+                22 => Ok(Value::Null),
+                _ => Err(CborError("unsupported simple value")),
+            }
+        }
+    }
+}
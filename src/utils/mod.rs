@@ -1,4 +1,10 @@
+pub mod cbor;
+pub mod password;
+pub mod recovery_codes;
+pub mod totp;
+
 use std::path::PathBuf;
+use {base64, openssl};
 
 fn push_all(p: &mut PathBuf, sub_paths: &[&str]) {
     for sub_path in sub_paths {
@@ -12,3 +18,31 @@ pub fn get_path(base: &PathBuf, sub_paths: &[&str]) -> PathBuf {
     push_all(&mut cloned, sub_paths);
     cloned
 }
+
+
+pub fn escape_html(raw: &str) -> String {
+    let mut escaped = String::with_capacity(raw.len());
+    for c in raw.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+
+// client secrets are random, so an unsalted digest is sufficient.
+pub fn hash_secret(secret: &str) -> String {
+    base64::encode(&openssl::sha::sha256(secret.as_bytes()))
+}
+
+
+pub fn verify_secret(secret: &str, hash: &str) -> bool {
+    let computed = hash_secret(secret);
+    computed.len() == hash.len() && openssl::memcmp::eq(computed.as_bytes(), hash.as_bytes())
+}
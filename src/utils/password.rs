@@ -0,0 +1,109 @@
+//! Password hashes in the PHC string format,
+//! `$pbkdf2-sha256$i=<iterations>$<salt>$<hash>` with unpadded base64.
+//! Former versions stored `base64(sha256(password + salt))` with one salt
+//! for all users, those hashes are still accepted but reported as outdated.
+
+use {base64, openssl};
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::pkcs5::pbkdf2_hmac;
+
+/// Iterations of new hashes, hashes with fewer are outdated.
+pub const ITERATIONS: usize = 600_000;
+const SALT_SIZE: usize = 16;
+const HASH_SIZE: usize = 32;
+const SCHEME: &str = "pbkdf2-sha256";
+/// A hash of `ITERATIONS` no password is known for. Logins of unknown users
+/// are checked against it, so they take as long as those of known users.
+pub const DUMMY_HASH: &str = "$pbkdf2-sha256$i=600000$bWILKztN4zfHSeejYpetfw$\
+                              b5Y24xsh6qOuEFgWW0KdLgc+f1pPKxE0NgrtzFP2hLs";
+
+/// Outcome of checking a password against its stored hash.
+#[derive(PartialEq, Eq, Debug)]
+pub enum Verification {
+    Invalid,
+    Valid,
+    /// the password is right, but its hash should be replaced by a new one.
+    Outdated,
+}
+
+/// Hashes `password` with a new random salt.
+pub fn hash_password(password: &str) -> Result<String, ErrorStack> {
+    let mut salt = [0; SALT_SIZE];
+    openssl::rand::rand_bytes(&mut salt)?;
+    let hash = pbkdf2(password, &salt, ITERATIONS)?;
+    Ok(format!(
+        "${}$i={}${}${}",
+        SCHEME,
+        ITERATIONS,
+        encode(&salt),
+        encode(&hash)
+    ))
+}
+
+/// Checks `password` against a `stored` hash in constant time. `legacy_salt`
+/// is the global salt hashes of former versions were computed with.
+pub fn verify_password(password: &str, stored: &str, legacy_salt: &str) -> Verification {
+    if !stored.starts_with('$') {
+        let legacy = base64::encode(&openssl::sha::sha256(
+            format!("{}{}", password, legacy_salt).as_bytes(),
+        ));
+        return if constant_time_eq(legacy.as_bytes(), stored.as_bytes()) {
+            Verification::Outdated
+        } else {
+            Verification::Invalid
+        };
+    }
+
+    let parts: Vec<&str> = stored.split('$').collect();
+    if parts.len() != 5 || parts[1] != SCHEME || !parts[2].starts_with("i=") {
+        return Verification::Invalid;
+    }
+    let iterations = match parts[2][2..].parse::<usize>() {
+        Ok(iterations) if iterations > 0 => iterations,
+        _ => return Verification::Invalid,
+    };
+    let (salt, expected) = match (decode(parts[3]), decode(parts[4])) {
+        (Some(salt), Some(expected)) => (salt, expected),
+        _ => return Verification::Invalid,
+    };
+    let computed = match pbkdf2(password, &salt, iterations) {
+        Ok(computed) => computed,
+        Err(_) => return Verification::Invalid,
+    };
+    if !constant_time_eq(&computed, &expected) {
+        Verification::Invalid
+    } else if iterations < ITERATIONS {
+        Verification::Outdated
+    } else {
+        Verification::Valid
+    }
+}
+
+fn pbkdf2(password: &str, salt: &[u8], iterations: usize) -> Result<Vec<u8>, ErrorStack> {
+    let mut hash = vec![0; HASH_SIZE];
+    pbkdf2_hmac(
+        password.as_bytes(),
+        salt,
+        iterations,
+        MessageDigest::sha256(),
+        &mut hash,
+    )?;
+    Ok(hash)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && openssl::memcmp::eq(a, b)
+}
+
+fn encode(raw: &[u8]) -> String {
+    base64::encode(raw).trim_right_matches('=').to_string()
+}
+
+fn decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut padded = String::from(encoded);
+    while padded.len() % 4 != 0 {
+        padded.push('=');
+    }
+    base64::decode(&padded).ok()
+}
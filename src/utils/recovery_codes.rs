@@ -0,0 +1,36 @@
+//! Single-use codes that stand in for the second factor when its device is
+//! lost. Users see them once, only their digests are stored.
+
+use openssl;
+use openssl::error::ErrorStack;
+use utils::{hash_secret, totp};
+
+/// Codes generated at a time.
+pub const COUNT: usize = 10;
+// 80 random bits, 16 base32 characters.
+const CODE_SIZE: usize = 10;
+
+/// New codes, formatted in groups of four like `abcd-efgh-ijkl-mnop`.
+pub fn generate() -> Result<Vec<String>, ErrorStack> {
+    let mut codes = Vec::with_capacity(COUNT);
+    for _ in 0..COUNT {
+        let mut bytes = [0; CODE_SIZE];
+        openssl::rand::rand_bytes(&mut bytes)?;
+        let encoded = totp::base32_encode(&bytes).to_lowercase();
+        let groups: Vec<&str> = (0..encoded.len() / 4)
+            .map(|i| &encoded[i * 4..i * 4 + 4])
+            .collect();
+        codes.push(groups.join("-"));
+    }
+    Ok(codes)
+}
+
+/// The digest stored for a code of the user. Case, dashes and blanks are
+/// ignored, the user id keeps equal codes of different users apart.
+pub fn hash(user_id: &str, code: &str) -> String {
+    let normalized: String = code.chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hash_secret(&format!("{}:{}", user_id, normalized))
+}
@@ -0,0 +1,118 @@
+//! Time-based one-time passwords as defined in RFC 6238, with the defaults
+//! authenticator apps expect: HMAC-SHA1, 6 digits and 30 second steps.
+//! Secrets are exchanged and stored base32 encoded.
+
+use openssl;
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use url::form_urlencoded;
+
+pub const DIGITS: u32 = 6;
+pub const PERIOD: i64 = 30;
+const SECRET_SIZE: usize = 20;
+// steps before and after the current one that are accepted, for clock drift.
+const SKEW: i64 = 1;
+static BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// A new random secret, base32 encoded.
+pub fn generate_secret() -> Result<String, ErrorStack> {
+    let mut secret = [0; SECRET_SIZE];
+    openssl::rand::rand_bytes(&mut secret)?;
+    Ok(base32_encode(&secret))
+}
+
+/// The time step `now` falls into.
+pub fn step(now: i64) -> i64 {
+    now / PERIOD
+}
+
+/// The code of `secret` for the time step `step`, None for an invalid secret.
+pub fn code_at(secret: &str, step: i64) -> Option<String> {
+    let key = base32_decode(secret)?;
+    let code = hotp(&key, step as u64).ok()?;
+    Some(format!("{:0width$}", code, width = DIGITS as usize))
+}
+
+/// The time step `code` is valid for, if it is valid around `now`. The
+/// caller has to reject steps that were already used.
+pub fn verify(secret: &str, code: &str, now: i64) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize {
+        return None;
+    }
+    let current = step(now);
+    (current - SKEW..current + SKEW + 1).find(|&candidate| {
+        code_at(secret, candidate).map_or(false, |expected| {
+            openssl::memcmp::eq(expected.as_bytes(), code.as_bytes())
+        })
+    })
+}
+
+/// The `otpauth` uri authenticator apps read from the provisioning QR code.
+pub fn provisioning_uri(secret: &str, issuer: &str, account: &str) -> String {
+    let label: String = form_urlencoded::byte_serialize(format!("{}:{}", issuer, account).as_bytes())
+        .collect();
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("secret", secret)
+        .append_pair("issuer", issuer)
+        .append_pair("algorithm", "SHA1")
+        .append_pair("digits", &DIGITS.to_string())
+        .append_pair("period", &PERIOD.to_string())
+        .finish();
+    format!("otpauth://totp/{}?{}", label.replace("+", "%20"), query)
+}
+
+// RFC 4226, the dynamically truncated HMAC of the big endian counter.
+fn hotp(key: &[u8], counter: u64) -> Result<u32, ErrorStack> {
+    let mut message = [0; 8];
+    for (i, byte) in message.iter_mut().enumerate() {
+        *byte = (counter >> (8 * (7 - i))) as u8;
+    }
+    let key = PKey::hmac(key)?;
+    let mut signer = Signer::new(MessageDigest::sha1(), &key)?;
+    signer.update(&message)?;
+    let mac = signer.sign_to_vec()?;
+    let offset = (mac[mac.len() - 1] & 0x0f) as usize;
+    let truncated = ((mac[offset] as u32 & 0x7f) << 24) | ((mac[offset + 1] as u32) << 16) |
+        ((mac[offset + 2] as u32) << 8) | (mac[offset + 3] as u32);
+    Ok(truncated % 10u32.pow(DIGITS))
+}
+
+/// RFC 4648 base32 without padding.
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity((data.len() * 8 + 4) / 5);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+/// Decodes base32, ignoring case, padding and the blanks apps show for readability.
+pub fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 5 / 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in encoded.chars().filter(|c| *c != ' ' && *c != '=') {
+        let upper = c.to_ascii_uppercase() as u8;
+        let value = BASE32_ALPHABET.iter().position(|&a| a == upper)? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}